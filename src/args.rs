@@ -5,40 +5,140 @@ pub const USAGE: &str = "
 DS encryption proxy.
 
 Usage:
-  ds_proxy encrypt <input-file> <output-file> [--password-file=<password-file>] [--salt=<salt>] [--chunk-size=<chunk-size>] [--keyring-file=<keyring-file>]
-  ds_proxy decrypt <input-file> <output-file> [--password-file=<password-file>] [--salt=<salt>] [--chunk-size=<chunk-size>] [--keyring-file=<keyring-file>]
-  ds_proxy proxy [--address=<address>] [--verify-ssl-certificate=<verify-ssl-certificate>] [--password-file=<password-file>] [--salt=<salt>] [--chunk-size=<chunk-size>] [--upstream-url=<upstream-url>] [--local-encryption-directory=<local-encryption-directory>] [--write-once] [--keyring-file=<keyring-file>] [--aws-access-key=<aws-access-key>] [--aws-secret-key=<aws-secret-key>] [--aws-region=<aws-region>] [--bypass-aws-signature-check] [--backend-connection-timeout=<backend-connection-timeout>] [--redis-url=<redis-url>] [--redis-timeout-wait=<redis-timeout-wait>] [--redis-timeout-create=<redis-timeout-create>] [--redis-timeout-recycle=<redis-timeout-recycle>] [--redis-pool-max-size=<redis-pool-max-size>]
-  ds_proxy add-key [--password-file=<password-file>] [--salt=<salt>] [--keyring-file=<keyring-file>]
+  ds_proxy encrypt <input-file> <output-file> [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--chunk-size=<chunk-size>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--recovery-public-key=<recovery-public-key>] [--config=<config-file>]
+  ds_proxy decrypt <input-file> <output-file> [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--chunk-size=<chunk-size>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--config=<config-file>]
+  ds_proxy recover <input-file> <output-file> --recovery-secret-key-file=<recovery-secret-key-file>
+  ds_proxy (generate-recovery-key | generate-key-pair) <secret-key-file>
+  ds_proxy proxy [--allow-core-dumps] [--address=<address>] [--tls-cert-file=<tls-cert-file> --tls-key-file=<tls-key-file>] [--tls-client-ca-file=<tls-client-ca-file>] [--verify-ssl-certificate=<verify-ssl-certificate>] [--upstream-ca-file=<upstream-ca-file>] [--upstream-client-cert-file=<upstream-client-cert-file> --upstream-client-key-file=<upstream-client-key-file>] [--upstream-min-tls-version=<upstream-min-tls-version>] [--upstream-pinned-key=<upstream-pinned-key>...] [--https-proxy=<https-proxy>] [--no-proxy=<no-proxy>] [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--chunk-size=<chunk-size>] [--upstream-url=<upstream-url>] [--upstream-endpoint=<upstream-endpoint>...] [--load-balancing=<load-balancing>] [--health-check-interval=<health-check-interval>] [--health-check-path=<health-check-path>] [--max-retries=<max-retries>] [--retry-base-delay=<retry-base-delay>] [--retry-max-delay=<retry-max-delay>] [--circuit-breaker-error-rate=<circuit-breaker-error-rate>] [--circuit-breaker-min-requests=<circuit-breaker-min-requests>] [--circuit-breaker-open-duration=<circuit-breaker-open-duration>] [--local-encryption-directory=<local-encryption-directory>] [--write-once] [--write-once-lock-duration=<write-once-lock-duration>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--keyring-reload-interval=<keyring-reload-interval>] [--max-key-age=<max-key-age>] [--aws-access-key=<aws-access-key>] [--aws-secret-key=<aws-secret-key>] [--aws-region=<aws-region>] [--bypass-aws-signature-check] [--keep-alive=<keep-alive>] [--upstream-force-close] [--upstream-max-connections=<upstream-max-connections>] [--upstream-idle-timeout=<upstream-idle-timeout>] [--backend-connection-timeout=<backend-connection-timeout>] [--response-timeout=<response-timeout>] [--upload-timeout=<upload-timeout>] [--max-in-memory-file-size=<max-in-memory-file-size>] [--stream-uploads] [--copy-streamed-metadata] [--redis-url=<redis-url>] [--redis-timeout-wait=<redis-timeout-wait>] [--redis-timeout-create=<redis-timeout-create>] [--redis-timeout-recycle=<redis-timeout-recycle>] [--redis-pool-max-size=<redis-pool-max-size>] [--tenants-file=<tenants-file>] [--routes-file=<routes-file>] [--recovery-public-key=<recovery-public-key>] [--encryption-public-key=<encryption-public-key>] [--decryption-secret-key-file=<decryption-secret-key-file>] [--config=<config-file>]
+  ds_proxy (print-config | check-config) [--allow-core-dumps] [--address=<address>] [--tls-cert-file=<tls-cert-file> --tls-key-file=<tls-key-file>] [--tls-client-ca-file=<tls-client-ca-file>] [--verify-ssl-certificate=<verify-ssl-certificate>] [--upstream-ca-file=<upstream-ca-file>] [--upstream-client-cert-file=<upstream-client-cert-file> --upstream-client-key-file=<upstream-client-key-file>] [--upstream-min-tls-version=<upstream-min-tls-version>] [--upstream-pinned-key=<upstream-pinned-key>...] [--https-proxy=<https-proxy>] [--no-proxy=<no-proxy>] [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--chunk-size=<chunk-size>] [--upstream-url=<upstream-url>] [--upstream-endpoint=<upstream-endpoint>...] [--load-balancing=<load-balancing>] [--health-check-interval=<health-check-interval>] [--health-check-path=<health-check-path>] [--max-retries=<max-retries>] [--retry-base-delay=<retry-base-delay>] [--retry-max-delay=<retry-max-delay>] [--circuit-breaker-error-rate=<circuit-breaker-error-rate>] [--circuit-breaker-min-requests=<circuit-breaker-min-requests>] [--circuit-breaker-open-duration=<circuit-breaker-open-duration>] [--local-encryption-directory=<local-encryption-directory>] [--write-once] [--write-once-lock-duration=<write-once-lock-duration>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--keyring-reload-interval=<keyring-reload-interval>] [--max-key-age=<max-key-age>] [--aws-access-key=<aws-access-key>] [--aws-secret-key=<aws-secret-key>] [--aws-region=<aws-region>] [--bypass-aws-signature-check] [--keep-alive=<keep-alive>] [--upstream-force-close] [--upstream-max-connections=<upstream-max-connections>] [--upstream-idle-timeout=<upstream-idle-timeout>] [--backend-connection-timeout=<backend-connection-timeout>] [--response-timeout=<response-timeout>] [--upload-timeout=<upload-timeout>] [--max-in-memory-file-size=<max-in-memory-file-size>] [--stream-uploads] [--copy-streamed-metadata] [--redis-url=<redis-url>] [--redis-timeout-wait=<redis-timeout-wait>] [--redis-timeout-create=<redis-timeout-create>] [--redis-timeout-recycle=<redis-timeout-recycle>] [--redis-pool-max-size=<redis-pool-max-size>] [--tenants-file=<tenants-file>] [--routes-file=<routes-file>] [--recovery-public-key=<recovery-public-key>] [--encryption-public-key=<encryption-public-key>] [--decryption-secret-key-file=<decryption-secret-key-file>] [--config=<config-file>]
+  ds_proxy add-key [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--config=<config-file>]
+  ds_proxy list-keys [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--config=<config-file>]
+  ds_proxy sign-keyring [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--config=<config-file>]
+  ds_proxy rotate-keys --max-key-age=<max-key-age> [--password-file=<password-file> | --share-file=<share-file>...] [--salt=<salt>] [--keyring-file=<keyring-file>] [--keyring-state-file=<keyring-state-file>] [--tenants-file=<tenants-file>] [--upstream-path=<upstream-path>] [--config=<config-file>]
+  ds_proxy split-secret <output-prefix> --threshold=<threshold> --shares=<shares> [--password-file=<password-file> | --random-secret] [--config=<config-file>]
+  ds_proxy combine-secret <output-file> --share-file=<share-file>...
   ds_proxy (-h | --help)
   ds_proxy --version
 
 Options:
   -h --help             Show this screen.
   --version             Show version.
+  --max-key-age=<max-key-age>  Age in days after which the active key is rotated.
+  --keyring-reload-interval=<keyring-reload-interval>  Interval in seconds between two keyring reloads.
+  --config=<config-file>  TOML file with the settings, overridden by the env and the cli.
+  --response-timeout=<response-timeout>  Seconds before the upstream must respond.
+  --upload-timeout=<upload-timeout>  Seconds before an upload to the upstream must end.
+  --max-in-memory-file-size=<max-in-memory-file-size>  Bytes buffered in memory before using a file.
+  --stream-uploads  Stream the AWS uploads of known length instead of buffering them.
+  --copy-streamed-metadata  Store the clear etag of a streamed AWS upload by copying it on itself.
+  --write-once-lock-duration=<write-once-lock-duration>  Seconds during which a written url stays locked.
+  --routes-file=<routes-file>  TOML file routing path prefixes to other upstreams.
+  --upstream-endpoint=<upstream-endpoint>  Another endpoint serving the content of the upstream url.
+  --load-balancing=<load-balancing>  round-robin or least-connections between the endpoints.
+  --health-check-interval=<health-check-interval>  Seconds between two probes of the endpoints.
+  --health-check-path=<health-check-path>  Path probed on each endpoint.
+  --max-retries=<max-retries>  Retries of a failed upstream request.
+  --retry-base-delay=<retry-base-delay>  Milliseconds before the first retry, doubled for each next one.
+  --retry-max-delay=<retry-max-delay>  Maximum milliseconds between two retries.
+  --circuit-breaker-error-rate=<circuit-breaker-error-rate>  Percentage of failed upstream requests opening the circuit, 0 (the default) to disable it.
+  --circuit-breaker-min-requests=<circuit-breaker-min-requests>  Requests before the error rate is considered.
+  --circuit-breaker-open-duration=<circuit-breaker-open-duration>  Seconds before an open circuit is probed.
+  --tls-cert-file=<tls-cert-file>  PEM certificate chain served by the listener, reloaded on SIGHUP.
+  --tls-key-file=<tls-key-file>  PEM private key of the certificate.
+  --tls-client-ca-file=<tls-client-ca-file>  PEM ca which must have signed the client certificates.
+  --upstream-ca-file=<upstream-ca-file>  PEM ca bundle verifying the upstream certificates.
+  --upstream-client-cert-file=<upstream-client-cert-file>  PEM certificate presented to the upstream.
+  --upstream-client-key-file=<upstream-client-key-file>  PEM private key of the client certificate.
+  --upstream-min-tls-version=<upstream-min-tls-version>  1.2 or 1.3.
+  --upstream-pinned-key=<upstream-pinned-key>  sha256//<base64> of the public key of a certificate of the upstream chain.
+  --https-proxy=<https-proxy>  http://[user:password@]host:port of a proxy tunneling the upstream connections.
+  --no-proxy=<no-proxy>  Comma separated hosts, and their subdomains, reached without the https proxy.
+  --keep-alive=<keep-alive>  Seconds a client connection is kept idle, 0 to close it after each response.
+  --upstream-force-close  Close the upstream connection after each request instead of pooling it.
+  --upstream-max-connections=<upstream-max-connections>  Simultaneous connections to an upstream.
+  --upstream-idle-timeout=<upstream-idle-timeout>  Seconds a pooled upstream connection is kept idle.
 ";
 
 #[derive(Debug, Deserialize, Clone, Default)]
 pub struct Args {
+    pub flag_config: Option<String>,
     pub flag_address: Option<String>,
+    pub flag_tls_cert_file: Option<String>,
+    pub flag_tls_key_file: Option<String>,
+    pub flag_tls_client_ca_file: Option<String>,
     pub flag_chunk_size: Option<usize>,
     pub arg_input_file: Option<String>,
     pub flag_keyring_file: Option<String>,
+    pub flag_keyring_state_file: Option<String>,
+    pub flag_recovery_public_key: Option<String>,
+    pub flag_recovery_secret_key_file: Option<String>,
+    pub arg_secret_key_file: Option<String>,
+    pub flag_encryption_public_key: Option<String>,
+    pub flag_decryption_secret_key_file: Option<String>,
+    pub flag_tenants_file: Option<String>,
+    pub flag_routes_file: Option<String>,
+    pub flag_upstream_path: Option<String>,
+    pub flag_keyring_reload_interval: Option<u64>,
+    pub flag_max_key_age: Option<u64>,
     pub arg_output_file: Option<String>,
     pub flag_password_file: Option<String>,
+    pub flag_share_file: Vec<String>,
+    pub flag_threshold: Option<u8>,
+    pub flag_shares: Option<u8>,
+    pub flag_random_secret: bool,
+    pub arg_output_prefix: Option<String>,
     pub flag_salt: Option<String>,
     pub flag_upstream_url: Option<String>,
+    pub flag_upstream_ca_file: Option<String>,
+    pub flag_upstream_client_cert_file: Option<String>,
+    pub flag_upstream_client_key_file: Option<String>,
+    pub flag_upstream_min_tls_version: Option<String>,
+    pub flag_upstream_pinned_key: Vec<String>,
+    pub flag_https_proxy: Option<String>,
+    pub flag_no_proxy: Option<String>,
+    pub flag_upstream_endpoint: Vec<String>,
+    pub flag_load_balancing: Option<String>,
+    pub flag_health_check_interval: Option<u64>,
+    pub flag_health_check_path: Option<String>,
+    pub flag_max_retries: Option<u32>,
+    pub flag_retry_base_delay: Option<u64>,
+    pub flag_retry_max_delay: Option<u64>,
+    pub flag_circuit_breaker_error_rate: Option<u8>,
+    pub flag_circuit_breaker_min_requests: Option<u32>,
+    pub flag_circuit_breaker_open_duration: Option<u64>,
     pub flag_local_encryption_directory: Option<String>,
     pub flag_aws_access_key: Option<String>,
     pub flag_aws_secret_key: Option<String>,
     pub flag_aws_region: Option<String>,
     pub flag_bypass_aws_signature_check: bool,
+    pub flag_keep_alive: Option<u64>,
+    pub flag_upstream_force_close: bool,
+    pub flag_upstream_max_connections: Option<usize>,
+    pub flag_upstream_idle_timeout: Option<u64>,
     pub flag_backend_connection_timeout: Option<u64>,
+    pub flag_response_timeout: Option<u64>,
+    pub flag_upload_timeout: Option<u64>,
+    pub flag_max_in_memory_file_size: Option<usize>,
+    pub flag_stream_uploads: bool,
+    pub flag_copy_streamed_metadata: bool,
+    pub flag_write_once_lock_duration: Option<u64>,
     pub cmd_encrypt: bool,
     pub cmd_decrypt: bool,
     pub cmd_proxy: bool,
     pub cmd_add_key: bool,
+    pub cmd_list_keys: bool,
+    pub cmd_rotate_keys: bool,
+    pub cmd_sign_keyring: bool,
+    pub cmd_recover: bool,
+    pub cmd_generate_recovery_key: bool,
+    pub cmd_generate_key_pair: bool,
+    pub cmd_split_secret: bool,
+    pub cmd_combine_secret: bool,
+    pub cmd_print_config: bool,
+    pub cmd_check_config: bool,
     pub flag_redis_url: Option<Url>,
     pub flag_write_once: bool,
+    pub flag_allow_core_dumps: bool,
     pub flag_redis_timeout_wait: Option<u64>,
     pub flag_redis_timeout_create: Option<u64>,
     pub flag_redis_timeout_recycle: Option<u64>,
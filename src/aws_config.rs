@@ -5,7 +5,7 @@ use aws_sigv4::http_request::{
     sign, PercentEncodingMode, SignableRequest, SignatureLocation, SigningInstructions,
     SigningSettings,
 };
-use aws_sigv4::sign::v4::SigningParams;
+use aws_sigv4::sign::v4::{generate_signing_key, SigningParams};
 
 #[derive(Debug, Clone)]
 pub struct AwsConfig {
@@ -50,4 +50,16 @@ impl AwsConfig {
 
         sign(request, &signing_params).unwrap().into_parts()
     }
+
+    // the key of the day, which signs the chunks of a streaming upload
+    pub fn signing_key(&self, time: SystemTime) -> Vec<u8> {
+        generate_signing_key(
+            self.credentials.secret_access_key(),
+            time,
+            &self.region,
+            "s3",
+        )
+        .as_ref()
+        .to_vec()
+    }
 }
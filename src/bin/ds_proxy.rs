@@ -5,9 +5,8 @@ extern crate sodiumoxide;
 
 use docopt::Docopt;
 use ds_proxy::args::{Args, USAGE};
-use ds_proxy::config::{Config, Config::*};
-use ds_proxy::keyring_utils::add_random_key_to_keyring;
-use ds_proxy::{file, http};
+use ds_proxy::config::{core_dumps_allowed, Config, Config::*};
+use ds_proxy::{check_config, file, http, secure_memory, settings, shamir};
 use log::info;
 use std::env;
 
@@ -27,14 +26,51 @@ fn main() {
 
     let args: Args = docopt.deserialize().unwrap_or_else(|e| e.exit());
 
-    let config = Config::create_config(&args);
+    if !core_dumps_allowed(&args) {
+        secure_memory::disable_core_dumps();
+    }
+
+    let config = Config::create_config(&args).unwrap_or_else(|errors| {
+        eprintln!("{}", errors);
+        std::process::exit(1);
+    });
 
     match config {
         Encrypt(config) => file::encrypt(config),
         Decrypt(config) => file::decrypt(config),
-        AddKeyConfig(config) => {
-            add_random_key_to_keyring(&config.keyring_file, config.password, config.salt)
+        GenerateKeyPairConfig(config) => file::generate_key_pair(config),
+        RecoverConfig(config) => file::recover(config),
+        AddKeyConfig(config) => config.keyring_file.add_random_key().unwrap_or_else(|why| {
+            eprintln!("{}", why);
+            std::process::exit(1);
+        }),
+        ListKeysConfig(config) => {
+            for description in config.keyring.describe_keys() {
+                println!("{}", description);
+            }
+        }
+        RotateKeysConfig(config) => {
+            match config.keyring_file.rotate_if_older_than(config.max_key_age) {
+                Ok(true) => println!("a new key has been added to {}", config.keyring_file.path()),
+                Ok(false) => (),
+                Err(why) => {
+                    eprintln!("{}", why);
+                    std::process::exit(1);
+                }
+            }
         }
+        SignKeyringConfig(config) => match config.keyring_file.sign() {
+            Ok(true) => println!("{} is now signed", config.keyring_file.path()),
+            Ok(false) => println!("{} is already signed", config.keyring_file.path()),
+            Err(why) => {
+                eprintln!("{}", why);
+                std::process::exit(1);
+            }
+        },
+        SplitSecretConfig(config) => shamir::split_secret(config),
+        CombineSecretConfig(config) => shamir::combine_secret(config),
+        PrintConfig(config) => settings::print_config(config),
+        CheckConfig(config) => check_config::print_check(config),
         Http(config) => http::main(config).unwrap(),
     }
 }
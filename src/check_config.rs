@@ -0,0 +1,154 @@
+use super::config::{HttpConfig, Upstream};
+use super::config_error::{ConfigError, ConfigErrors};
+use super::egress_proxy::EgressProxy;
+use deadpool_redis::redis;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+use url::Url;
+
+const CHECK_FILE: &str = ".ds_proxy_check_config";
+
+// what the proxy needs from its environment, checked without starting it.
+// The keyring password has already been checked by unlocking the keyrings.
+pub fn check_config(config: &HttpConfig) -> Result<(), ConfigErrors> {
+    let mut errors = ConfigErrors::default();
+
+    errors.check(check_address(config));
+    for upstream in config.upstreams() {
+        errors.check(check_upstream(upstream));
+    }
+    errors.check(check_writable(
+        "local_encryption_directory",
+        &config.local_encryption_directory,
+    ));
+
+    // a rotation writes the new keyring next to the old one
+    if let Some(keyring_reload) = &config.keyring_reload {
+        if keyring_reload.max_key_age.is_some() {
+            let keyring_files = std::iter::once(keyring_reload.keyring_file.path())
+                .chain(config.tenants.iter().map(|t| t.keyring_file.path()));
+
+            for keyring_file in keyring_files {
+                let directory = match Path::new(keyring_file).parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+
+                errors.check(check_writable("keyring_file", directory));
+            }
+        }
+    }
+
+    // redis also holds the parts of the multipart uploads to s3
+    if config.write_once || config.upstreams().any(|u| u.aws_config.is_some()) {
+        errors.check(check_redis(config));
+    }
+
+    errors.finish(Some(()))
+}
+
+pub fn print_check(config: HttpConfig) {
+    match check_config(&config) {
+        Ok(()) => println!("the configuration is valid"),
+        Err(errors) => {
+            eprintln!("{}", errors);
+            std::process::exit(1);
+        }
+    }
+}
+
+// the address is resolved with the configuration: only its ip is checked,
+// its port may be held by the proxy being checked
+fn check_address(config: &HttpConfig) -> Result<(), ConfigError> {
+    TcpListener::bind(SocketAddr::new(config.address.ip(), 0))
+        .map(|_| ())
+        .map_err(|why| {
+            ConfigError::invalid(
+                "address",
+                format!("cannot listen on {}: {}", config.address, why),
+            )
+        })
+}
+
+fn check_upstream(upstream: &Upstream) -> Result<(), ConfigError> {
+    let setting = if upstream.prefix.is_empty() {
+        "upstream_url".to_string()
+    } else {
+        format!("upstream_url of route {}", upstream.prefix)
+    };
+
+    // each endpoint of the pool must be reachable, or the egress proxy tunneling to it
+    for url in upstream.pool.base_urls() {
+        match &upstream.egress_proxy {
+            Some(proxy) if !proxy.bypasses(url.host_str().unwrap_or_default()) => {
+                check_egress_proxy(proxy, upstream.backend_connection_timeout)
+                    .map_err(|why| ConfigError::invalid("https_proxy", why))?
+            }
+            _ => check_endpoint(url, upstream.backend_connection_timeout)
+                .map_err(|why| ConfigError::invalid(&setting, why))?,
+        }
+    }
+
+    Ok(())
+}
+
+fn check_endpoint(url: &Url, timeout: Duration) -> Result<(), String> {
+    let addresses = url
+        .socket_addrs(|| None)
+        .map_err(|why| format!("cannot resolve {}: {}", url, why))?;
+
+    connect(url.as_str(), addresses, timeout)
+}
+
+fn check_egress_proxy(proxy: &EgressProxy, timeout: Duration) -> Result<(), String> {
+    let address = format!("{}:{}", proxy.host, proxy.port);
+    let addresses = address
+        .to_socket_addrs()
+        .map_err(|why| format!("cannot resolve {}: {}", address, why))?;
+
+    connect(&address, addresses, timeout)
+}
+
+fn connect(
+    name: &str,
+    addresses: impl IntoIterator<Item = SocketAddr>,
+    timeout: Duration,
+) -> Result<(), String> {
+    let mut last_error = format!("{} has no address", name);
+
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(_) => return Ok(()),
+            Err(why) => last_error = format!("cannot connect to {}: {}", address, why),
+        }
+    }
+
+    Err(last_error)
+}
+
+fn check_writable(setting: &str, directory: &Path) -> Result<(), ConfigError> {
+    let check_file = directory.join(CHECK_FILE);
+
+    std::fs::write(&check_file, b"")
+        .and_then(|_| std::fs::remove_file(&check_file))
+        .map_err(|why| {
+            ConfigError::invalid(setting, format!("cannot write in {:?}: {}", directory, why))
+        })
+}
+
+fn check_redis(config: &HttpConfig) -> Result<(), ConfigError> {
+    let redis_config = &config.redis_config;
+    let timeout = redis_config
+        .pool_config
+        .timeouts
+        .create
+        .unwrap_or(Duration::from_secs(1));
+
+    let pong: redis::RedisResult<String> = redis::Client::open(redis_config.url.as_str())
+        .and_then(|client| client.get_connection_with_timeout(timeout))
+        .and_then(|mut connection| redis::cmd("PING").query(&mut connection));
+
+    pong.map(|_| ())
+        .map_err(|why| ConfigError::invalid("redis_url", format!("cannot reach redis: {}", why)))
+}
@@ -0,0 +1,232 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// the errors are counted over windows of this duration
+const WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerPolicy {
+    // percentage of failed requests opening the circuit, 0 disables it
+    pub error_rate: u8,
+    // the rate is not considered below this number of requests
+    pub min_requests: u32,
+    pub open_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        window_start: Instant,
+        requests: u32,
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    // a single probe is let through, another one if it never reports
+    HalfOpen {
+        probe_sent_at: Instant,
+    },
+}
+
+// stops sending requests to a failing upstream for a while,
+// rather than having every request wait for its timeouts
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: CircuitBreakerPolicy) -> CircuitBreaker {
+        CircuitBreaker {
+            policy,
+            state: Mutex::new(closed(Instant::now())),
+        }
+    }
+
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        self.policy
+    }
+
+    // Err with the time left before a request can go through
+    pub fn allow(&self) -> Result<(), Duration> {
+        self.allow_at(Instant::now())
+    }
+
+    pub fn record(&self, success: bool) {
+        self.record_at(success, Instant::now())
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn allow_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if now < until => Err(until - now),
+            State::HalfOpen { probe_sent_at }
+                if now < probe_sent_at + self.policy.open_duration =>
+            {
+                Err(probe_sent_at + self.policy.open_duration - now)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                log::warn!("circuit half-open, probing the upstream");
+                *state = State::HalfOpen { probe_sent_at: now };
+                Ok(())
+            }
+        }
+    }
+
+    fn record_at(&self, success: bool, now: Instant) {
+        if self.policy.error_rate == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        match &mut *state {
+            State::Closed {
+                window_start,
+                requests,
+                failures,
+            } => {
+                if now >= *window_start + WINDOW {
+                    *window_start = now;
+                    *requests = 0;
+                    *failures = 0;
+                }
+
+                *requests += 1;
+                if !success {
+                    *failures += 1;
+                }
+
+                if *requests >= self.policy.min_requests
+                    && *failures * 100 >= *requests * self.policy.error_rate as u32
+                {
+                    log::error!(
+                        "circuit open for {:?}: {} failures out of {} requests",
+                        self.policy.open_duration,
+                        failures,
+                        requests
+                    );
+                    *state = State::Open {
+                        until: now + self.policy.open_duration,
+                    };
+                }
+            }
+            State::HalfOpen { .. } if success => {
+                log::warn!("circuit closed, the upstream is back");
+                *state = closed(now);
+            }
+            State::HalfOpen { .. } => {
+                log::error!("circuit open again for {:?}", self.policy.open_duration);
+                *state = State::Open {
+                    until: now + self.policy.open_duration,
+                };
+            }
+            // the answers of requests sent before the opening
+            State::Open { .. } => (),
+        }
+    }
+}
+
+fn closed(now: Instant) -> State {
+    State::Closed {
+        window_start: now,
+        requests: 0,
+        failures: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerPolicy {
+            error_rate: 50,
+            min_requests: 4,
+            open_duration: Duration::from_secs(30),
+        })
+    }
+
+    #[test]
+    fn the_circuit_opens_then_probes_the_upstream() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_at(false, now);
+        breaker.record_at(false, now);
+        breaker.record_at(true, now);
+        // not enough requests yet
+        assert_eq!(CircuitState::Closed, breaker.state());
+
+        breaker.record_at(false, now);
+        assert_eq!(CircuitState::Open, breaker.state());
+        assert_eq!(
+            Err(Duration::from_secs(20)),
+            breaker.allow_at(now + Duration::from_secs(10))
+        );
+
+        // a single probe goes through
+        let later = now + Duration::from_secs(31);
+        assert_eq!(Ok(()), breaker.allow_at(later));
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        assert!(breaker.allow_at(later).is_err());
+
+        breaker.record_at(false, later);
+        assert_eq!(CircuitState::Open, breaker.state());
+
+        let even_later = later + Duration::from_secs(31);
+        assert_eq!(Ok(()), breaker.allow_at(even_later));
+        breaker.record_at(true, even_later);
+        assert_eq!(CircuitState::Closed, breaker.state());
+        assert_eq!(Ok(()), breaker.allow_at(even_later));
+    }
+
+    #[test]
+    fn the_failures_are_counted_over_a_window() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record_at(false, now);
+        breaker.record_at(false, now);
+        breaker.record_at(true, now);
+
+        let next_window = now + WINDOW;
+        breaker.record_at(false, next_window);
+        assert_eq!(CircuitState::Closed, breaker.state());
+    }
+
+    #[test]
+    fn a_lost_probe_is_replaced() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        for _ in 0..4 {
+            breaker.record_at(false, now);
+        }
+
+        let probe = now + Duration::from_secs(30);
+        assert_eq!(Ok(()), breaker.allow_at(probe));
+        assert_eq!(Ok(()), breaker.allow_at(probe + Duration::from_secs(30)));
+    }
+}
@@ -1,17 +1,30 @@
 use super::aws_config::AwsConfig;
-use super::{args, keyring::Keyring, keyring_utils::load_keyring};
+use super::config_error::{ConfigError, ConfigErrors};
+use super::settings::Settings;
+use super::shamir::{combine, Share};
+use super::{args, keyring::Keyring, keyring_utils::KeyringFile};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerPolicy};
+use crate::egress_proxy::EgressProxy;
 use crate::redis_config::RedisConfig;
+use crate::retry::RetryPolicy;
+use crate::tls::{parse_pinned_key, parse_tls_version, TlsConfig, UpstreamTls};
+use crate::upstream_pool::{EndpointPool, LoadBalancing};
 use actix_web::HttpRequest;
 use aws_sdk_s3::config::Credentials;
-use std::env;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use sodiumoxide::crypto::box_::{PublicKey, SecretKey};
+use sodiumoxide::crypto::pwhash;
+use std::collections::HashMap;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
-// match nginx default (proxy_buffer_size in ngx_stream_proxy_module)
-pub const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
-pub const DEFAULT_LOCAL_ENCRYPTION_DIRECTORY: &str = "ds_proxy/local_encryption/";
+pub use super::settings::{DEFAULT_CHUNK_SIZE, DEFAULT_LOCAL_ENCRYPTION_DIRECTORY};
+pub const DEFAULT_KEYRING_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
 
 #[allow(clippy::large_enum_variant)]
 pub enum Config {
@@ -19,11 +32,21 @@ pub enum Config {
     Encrypt(EncryptConfig),
     Http(HttpConfig),
     AddKeyConfig(AddKeyConfig),
+    ListKeysConfig(ListKeysConfig),
+    SplitSecretConfig(SplitSecretConfig),
+    CombineSecretConfig(CombineSecretConfig),
+    RotateKeysConfig(RotateKeysConfig),
+    SignKeyringConfig(SignKeyringConfig),
+    GenerateKeyPairConfig(GenerateKeyPairConfig),
+    RecoverConfig(RecoverConfig),
+    PrintConfig(Settings),
+    CheckConfig(HttpConfig),
 }
 
 #[derive(Debug, Clone)]
 pub struct DecryptConfig {
     pub keyring: Keyring,
+    pub decryption_secret_key: Option<SecretKey>,
     pub input_file: String,
     pub output_file: String,
 }
@@ -34,202 +57,880 @@ pub struct EncryptConfig {
     pub chunk_size: usize,
     pub input_file: String,
     pub output_file: String,
+    pub recovery_public_key: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GenerateKeyPairConfig {
+    pub secret_key_file: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoverConfig {
+    pub recovery_secret_key: SecretKey,
+    pub input_file: String,
+    pub output_file: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct HttpConfig {
-    pub upstream_base_url: Url,
+    // where the requests matching no route go
+    pub upstream: Upstream,
+    pub routes: Vec<Upstream>,
     pub keyring: Keyring,
     pub chunk_size: usize,
     pub address: SocketAddr,
     pub local_encryption_directory: PathBuf,
-    pub aws_config: Option<AwsConfig>,
-    pub backend_connection_timeout: Duration,
     pub write_once: bool,
     pub redis_config: RedisConfig,
+    pub keyring_reload: Option<KeyringReloadConfig>,
+    pub tenants: Vec<Tenant>,
+    pub recovery_public_key: Option<PublicKey>,
+    // uploads are sealed to this public key instead of the keyring
+    pub encryption_public_key: Option<PublicKey>,
+    // opens the files sealed to the encryption public key
+    pub decryption_secret_key: Option<SecretKey>,
+    pub max_in_memory_file_size: usize,
+    pub stream_uploads: bool,
+    pub copy_streamed_metadata: bool,
+    pub write_once_lock_duration: u64,
+    // of the client connections, none when they are closed after each response
+    pub keep_alive: Option<Duration>,
+    pub tls: Option<TlsConfig>,
+}
+
+// a backend behind the proxy, each one with its own connection settings
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    // the incoming path prefix replaced by base_url, empty for the default upstream
+    pub prefix: String,
+    pub base_url: Url,
+    // base_url and the other endpoints serving the same content
+    pub pool: Arc<EndpointPool>,
+    pub health_check_interval: Duration,
+    pub health_check_path: Option<String>,
+    pub retry: RetryPolicy,
+    pub circuit_breaker: Arc<CircuitBreaker>,
+    pub aws_config: Option<AwsConfig>,
     pub verify_ssl_certificate: bool,
+    pub tls: UpstreamTls,
+    // the connections are tunneled through it, shared by the routes
+    pub egress_proxy: Option<EgressProxy>,
+    // pooled connections, unless closed after each request
+    pub force_close: bool,
+    pub max_connections: usize,
+    pub idle_timeout: Duration,
+    pub backend_connection_timeout: Duration,
+    pub response_timeout: Duration,
+    pub upload_timeout: Duration,
+}
+
+// the key material held by a proxy
+struct ProxyKeys {
+    keyring: Keyring,
+    keyring_reload: Option<KeyringReloadConfig>,
+    tenants: Vec<Tenant>,
 }
 
+// the files stored under an upstream path prefix (a bucket or a container)
+// are encrypted with a keyring of their own
 #[derive(Debug, Clone)]
-pub struct AddKeyConfig {
-    pub password: String,
-    pub salt: String,
-    pub keyring_file: String,
+pub struct Tenant {
+    pub prefix: String,
+    pub keyring_file: KeyringFile,
+    pub keyring: Keyring,
 }
 
-impl Config {
-    pub fn create_config(args: &args::Args) -> Config {
-        let password = match &args.flag_password_file {
-            Some(password_file) => read_file_content(password_file),
-            None => env::var("DS_PASSWORD")
-                .expect("Missing password, use DS_PASSWORD env or --password-file cli argument"),
-        };
+#[derive(Debug, Clone, Deserialize)]
+struct TenantsFile {
+    tenants: Vec<TenantEntry>,
+}
 
-        let salt = match &args.flag_salt {
-            Some(salt) => salt.to_string(),
-            None => {
-                env::var("DS_SALT").expect("Missing salt, use DS_SALT env or --salt cli argument")
-            }
+#[derive(Debug, Clone, Deserialize)]
+struct TenantEntry {
+    prefix: String,
+    keyring_file: String,
+    keyring_state_file: Option<String>,
+    password_file: Option<String>,
+    #[serde(default)]
+    share_files: Vec<String>,
+    // defaults to the global salt
+    salt: Option<String>,
+}
+
+impl TenantEntry {
+    fn password(&self) -> Result<String, ConfigErrors> {
+        if !self.share_files.is_empty() {
+            return password_from_shares(&self.share_files);
+        }
+
+        match &self.password_file {
+            Some(password_file) => Ok(read_file_content("password_file", password_file)?),
+            None => Err(ConfigError::missing(
+                &format!("password for tenant {}", self.prefix),
+                "password_file or share_files",
+            )
+            .into()),
+        }
+    }
+
+    fn load(&self, default_salt: &str) -> Result<Tenant, ConfigErrors> {
+        let salt = match &self.salt {
+            Some(salt) => check_salt(&format!("salt of tenant {}", self.prefix), salt),
+            None => Ok(default_salt.to_string()),
         };
 
-        let keyring_file: String = match &args.flag_keyring_file {
-            Some(keyring_file) => keyring_file.to_string(),
-            None => env::var("DS_KEYRING")
-                .expect("Missing keyring, use DS_KEYRING env or --keyring-file cli argument"),
+        let mut errors = ConfigErrors::default();
+        let salt = errors.check(salt);
+        let password = errors.check(self.password());
+
+        let (Some(salt), Some(password)) = (salt, password) else {
+            return Err(errors);
         };
 
-        if args.cmd_add_key {
-            return Config::AddKeyConfig(AddKeyConfig {
-                password,
-                salt,
-                keyring_file,
-            });
+        let keyring_file = KeyringFile::open(
+            &self.keyring_file,
+            password,
+            salt,
+            self.keyring_state_file.as_deref(),
+        );
+
+        let keyring = keyring_file.try_load().map_err(|why| {
+            ConfigError::invalid(&format!("keyring of tenant {}", self.prefix), why)
+        })?;
+
+        Ok(Tenant {
+            prefix: self.prefix.clone(),
+            keyring_file,
+            keyring,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RoutesFile {
+    routes: Vec<RouteEntry>,
+}
+
+// the settings missing from a route are the global ones,
+// except the aws credentials: a route without them is not signed
+#[derive(Debug, Clone, Deserialize)]
+struct RouteEntry {
+    prefix: String,
+    upstream_url: String,
+    #[serde(default)]
+    endpoints: Vec<String>,
+    load_balancing: Option<LoadBalancing>,
+    health_check_interval: Option<u64>,
+    health_check_path: Option<String>,
+    max_retries: Option<u32>,
+    retry_base_delay: Option<u64>,
+    retry_max_delay: Option<u64>,
+    circuit_breaker_error_rate: Option<u8>,
+    circuit_breaker_min_requests: Option<u32>,
+    circuit_breaker_open_duration: Option<u64>,
+    aws_access_key: Option<String>,
+    aws_secret_key: Option<String>,
+    aws_region: Option<String>,
+    bypass_aws_signature_check: Option<bool>,
+    verify_ssl_certificate: Option<bool>,
+    ca_file: Option<String>,
+    client_cert_file: Option<String>,
+    client_key_file: Option<String>,
+    min_tls_version: Option<String>,
+    pinned_keys: Option<Vec<String>>,
+    force_close: Option<bool>,
+    max_connections: Option<usize>,
+    idle_timeout: Option<u64>,
+    backend_connection_timeout: Option<u64>,
+    response_timeout: Option<u64>,
+    upload_timeout: Option<u64>,
+}
+
+impl RouteEntry {
+    fn upstream(&self, default: &Upstream) -> Result<Upstream, ConfigError> {
+        let setting = format!("upstream_url of route {}", self.prefix);
+
+        if self.prefix.trim_matches('/').is_empty() {
+            return Err(ConfigError::invalid(&setting, "a route needs a prefix"));
         }
 
-        let chunk_size = match &args.flag_chunk_size {
-            Some(chunk_size) => *chunk_size,
-            None => match env::var("DS_CHUNK_SIZE") {
-                Ok(chunk_str) => chunk_str.parse::<usize>().unwrap_or(DEFAULT_CHUNK_SIZE),
-                _ => DEFAULT_CHUNK_SIZE,
-            },
+        let base_url = normalize_and_parse_upstream_url(&setting, self.upstream_url.clone())?;
+        let endpoints = parse_endpoints(
+            &format!("endpoints of route {}", self.prefix),
+            &base_url,
+            &self.endpoints,
+        )?;
+
+        let verify_ssl_certificate = self
+            .verify_ssl_certificate
+            .unwrap_or(default.verify_ssl_certificate);
+        let tls_setting = format!("tls of route {}", self.prefix);
+        let (client_cert_file, client_key_file) =
+            if self.client_cert_file.is_some() || self.client_key_file.is_some() {
+                (
+                    self.client_cert_file.as_ref().map(PathBuf::from),
+                    self.client_key_file.as_ref().map(PathBuf::from),
+                )
+            } else {
+                (
+                    default.tls.client_cert_file.clone(),
+                    default.tls.client_key_file.clone(),
+                )
+            };
+        let pinned_keys = match &self.pinned_keys {
+            Some(pinned_keys) => parse_pinned_keys(&tls_setting, pinned_keys)?,
+            None => default.tls.pinned_keys.clone(),
         };
+        let tls = upstream_tls(
+            &tls_setting,
+            UpstreamTls {
+                ca_file: self
+                    .ca_file
+                    .as_ref()
+                    .map(PathBuf::from)
+                    .or_else(|| default.tls.ca_file.clone()),
+                client_cert_file,
+                client_key_file,
+                min_version: self
+                    .min_tls_version
+                    .clone()
+                    .or_else(|| default.tls.min_version.clone()),
+                pinned_keys,
+            },
+            verify_ssl_certificate,
+        )?;
+
+        let default_policy = default.circuit_breaker.policy();
+        let circuit_breaker = circuit_breaker_policy(
+            &format!("circuit_breaker_error_rate of route {}", self.prefix),
+            self.circuit_breaker_error_rate
+                .unwrap_or(default_policy.error_rate),
+            self.circuit_breaker_min_requests
+                .unwrap_or(default_policy.min_requests),
+            self.circuit_breaker_open_duration
+                .map(Duration::from_secs)
+                .unwrap_or(default_policy.open_duration),
+        )?;
+
+        Ok(Upstream {
+            prefix: self.prefix.clone(),
+            base_url,
+            pool: Arc::new(EndpointPool::new(
+                endpoints,
+                self.load_balancing.unwrap_or(default.pool.load_balancing()),
+            )),
+            health_check_interval: self
+                .health_check_interval
+                .map(Duration::from_secs)
+                .unwrap_or(default.health_check_interval),
+            health_check_path: self
+                .health_check_path
+                .clone()
+                .or_else(|| default.health_check_path.clone()),
+            retry: RetryPolicy {
+                max_retries: self.max_retries.unwrap_or(default.retry.max_retries),
+                base_delay: self
+                    .retry_base_delay
+                    .map(Duration::from_millis)
+                    .unwrap_or(default.retry.base_delay),
+                max_delay: self
+                    .retry_max_delay
+                    .map(Duration::from_millis)
+                    .unwrap_or(default.retry.max_delay),
+            },
+            circuit_breaker: Arc::new(CircuitBreaker::new(circuit_breaker)),
+            aws_config: aws_config(
+                &self.aws_access_key,
+                &self.aws_secret_key,
+                &self.aws_region,
+                self.bypass_aws_signature_check.unwrap_or(false),
+            ),
+            verify_ssl_certificate,
+            tls,
+            egress_proxy: default.egress_proxy.clone(),
+            force_close: self.force_close.unwrap_or(default.force_close),
+            max_connections: self.max_connections.unwrap_or(default.max_connections),
+            idle_timeout: self
+                .idle_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default.idle_timeout),
+            backend_connection_timeout: self
+                .backend_connection_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default.backend_connection_timeout),
+            response_timeout: self
+                .response_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default.response_timeout),
+            upload_timeout: self
+                .upload_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(default.upload_timeout),
+        })
+    }
+}
 
-        let keyring = load_keyring(&keyring_file, password, salt);
+// the proxy periodically reloads the keyring, to pick up keys added
+// by another process, and can rotate the active key itself
+#[derive(Debug, Clone)]
+pub struct KeyringReloadConfig {
+    pub keyring_file: KeyringFile,
+    pub interval: Duration,
+    pub max_key_age: Option<Duration>,
+}
 
-        if args.cmd_encrypt {
-            Config::Encrypt(EncryptConfig {
-                keyring,
-                chunk_size,
-                input_file: args.arg_input_file.clone().unwrap(),
+#[derive(Debug, Clone)]
+pub struct AddKeyConfig {
+    pub keyring_file: KeyringFile,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListKeysConfig {
+    pub keyring: Keyring,
+}
+
+#[derive(Debug, Clone)]
+pub struct RotateKeysConfig {
+    pub keyring_file: KeyringFile,
+    pub max_key_age: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct SignKeyringConfig {
+    pub keyring_file: KeyringFile,
+}
+
+#[derive(Debug, Clone)]
+pub struct SplitSecretConfig {
+    // None when a random secret must be generated
+    pub secret: Option<Vec<u8>>,
+    pub threshold: u8,
+    pub nb_shares: u8,
+    pub output_prefix: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CombineSecretConfig {
+    pub secret: Vec<u8>,
+    pub output_file: String,
+}
+
+impl Config {
+    // every problem found is reported, not only the first one
+    pub fn create_config(args: &args::Args) -> Result<Config, ConfigErrors> {
+        let settings = Settings::load(args)?;
+
+        if args.cmd_print_config {
+            return Ok(Config::PrintConfig(settings));
+        }
+
+        if args.cmd_split_secret {
+            let mut errors = ConfigErrors::default();
+
+            let secret = if args.flag_random_secret {
+                Some(None)
+            } else {
+                errors
+                    .check(read_password(&settings))
+                    .map(|password| Some(password.into_bytes()))
+            };
+
+            let threshold = errors.check(check_threshold(
+                args.flag_threshold.unwrap(),
+                args.flag_shares.unwrap(),
+            ));
+
+            return errors.finish(secret.zip(threshold).map(|(secret, threshold)| {
+                Config::SplitSecretConfig(SplitSecretConfig {
+                    secret,
+                    threshold,
+                    nb_shares: args.flag_shares.unwrap(),
+                    output_prefix: args.arg_output_prefix.clone().unwrap(),
+                })
+            }));
+        }
+
+        if args.cmd_combine_secret {
+            return Ok(Config::CombineSecretConfig(CombineSecretConfig {
+                secret: combine_share_files(&args.flag_share_file)?,
                 output_file: args.arg_output_file.clone().unwrap(),
-            })
-        } else if args.cmd_decrypt {
-            Config::Decrypt(DecryptConfig {
-                keyring,
+            }));
+        }
+
+        if args.cmd_generate_recovery_key || args.cmd_generate_key_pair {
+            return Ok(Config::GenerateKeyPairConfig(GenerateKeyPairConfig {
+                secret_key_file: args.arg_secret_key_file.clone().unwrap(),
+            }));
+        }
+
+        if args.cmd_recover {
+            let recovery_secret_key_file = args.flag_recovery_secret_key_file.clone().unwrap();
+
+            return Ok(Config::RecoverConfig(RecoverConfig {
+                recovery_secret_key: read_secret_key(
+                    "recovery_secret_key_file",
+                    &recovery_secret_key_file,
+                )?,
                 input_file: args.arg_input_file.clone().unwrap(),
                 output_file: args.arg_output_file.clone().unwrap(),
-            })
-        } else {
-            let local_encryption_directory = match &args.flag_local_encryption_directory {
-                Some(directory) => PathBuf::from(directory),
-                None => match env::var("DS_LOCAL_ENCRYPTION_DIRECTORY") {
-                    Ok(directory) => PathBuf::from(directory),
-                    _ => {
-                        let mut path_buf = PathBuf::new();
-                        path_buf.push(env::temp_dir());
-                        path_buf.push(DEFAULT_LOCAL_ENCRYPTION_DIRECTORY);
-                        path_buf
-                    }
-                },
-            };
+            }));
+        }
 
-            std::fs::create_dir_all(local_encryption_directory.clone()).unwrap_or_else(|why| {
-                panic!(
-                    "Cannot create tmp directory {:?}: {}",
-                    local_encryption_directory, why
-                )
-            });
+        let mut errors = ConfigErrors::default();
+        let is_proxy = args.cmd_proxy || args.cmd_check_config;
 
-            let raw_upstream_base_url = match &args.flag_upstream_url {
-                Some(upstream_url) => Some(upstream_url.to_string()),
-                None => Some(env::var("DS_UPSTREAM_URL").expect(
-                    "Missing upstream_url, use DS_UPSTREAM_URL env or --upstream-url cli argument",
-                )),
+        let tenant_entries = match &settings.tenants_file {
+            Some(tenants_file) => errors
+                .check(read_tenant_entries(tenants_file))
+                .unwrap_or_default(),
+            None => vec![],
+        };
+
+        let chunk_size = settings.chunk_size.unwrap();
+
+        // the encrypted lengths are computed per chunk
+        if chunk_size == 0 {
+            errors.push(ConfigError::invalid("chunk_size", "must be greater than 0"));
+        }
+
+        // a proxy holding only an asymmetric key pair has no keyring to unlock
+        if is_proxy && is_keyless_proxy(&settings) {
+            if !tenant_entries.is_empty() {
+                errors.push(ConfigError::invalid(
+                    "tenants_file",
+                    "a proxy without keyring cannot have tenants",
+                ));
             }
-            .unwrap();
 
-            let upstream_base_url = normalize_and_parse_upstream_url(raw_upstream_base_url);
+            let http_config =
+                errors.check(create_http_config(&settings, chunk_size, ProxyKeys::none()));
 
-            let address = match &args.flag_address {
-                Some(address) => match address.to_socket_addrs() {
-                    Ok(mut sockets) => Some(sockets.next().unwrap()),
-                    _ => panic!("Unable to parse the address"),
-                },
-                None => match (env::var("DS_ADDRESS")
-                    .expect("Missing address, use DS_ADDRESS env or --address cli argument"))
-                .to_socket_addrs()
-                {
-                    Ok(mut sockets) => Some(sockets.next().unwrap()),
-                    _ => panic!("Unable to parse the address"),
+            return errors.finish(http_config.map(|c| Config::for_proxy(args, c)));
+        }
+
+        // on the command line, only the keyring of the tenant
+        // owning the given upstream path is unlocked
+        let tenant_entry = args
+            .flag_upstream_path
+            .as_deref()
+            .and_then(|path| longest_prefix_match(&tenant_entries, path, |e| &e.prefix));
+
+        let (password, salt, keyring_file, keyring_state_file) = match tenant_entry {
+            Some(entry) => (
+                errors.check(entry.password()),
+                match &entry.salt {
+                    Some(salt) => errors.check(check_salt("salt", salt)),
+                    None => errors.check(read_salt(&settings)),
                 },
+                Some(entry.keyring_file.clone()),
+                entry.keyring_state_file.clone(),
+            ),
+            None => (
+                errors.check(read_password(&settings)),
+                errors.check(read_salt(&settings)),
+                errors.check(read_keyring_file(&settings)),
+                settings.keyring_state_file.clone(),
+            ),
+        };
+
+        let (Some(password), Some(salt), Some(keyring_file)) = (password, salt, keyring_file)
+        else {
+            // the settings of the proxy are reported along
+            if is_proxy {
+                errors.check(create_http_config(&settings, chunk_size, ProxyKeys::none()));
             }
-            .unwrap();
-
-            let backend_connection_timeout = match &args.flag_backend_connection_timeout {
-                Some(timeout_u64) => Duration::from_secs(*timeout_u64),
-                None => match env::var("BACKEND_CONNECTION_TIMEOUT") {
-                    Ok(timeout_string) => Duration::from_secs(
-                        timeout_string
-                            .parse()
-                            .expect("BACKEND_CONNECTION_TIMEOUT is not a u64"),
-                    ),
-                    _ => Duration::from_secs(1),
-                },
-            };
 
-            let write_once = if args.flag_write_once {
-                true
-            } else {
-                match env::var("WRITE_ONCE") {
-                    Ok(write_once_string) => write_once_string
-                        .parse()
-                        .expect("WRITE_ONCE is not a boolean"),
-                    _ => false,
-                }
-            };
+            return Err(errors);
+        };
 
-            let verify_ssl_certificate = match &args.flag_verify_ssl_certificate {
-                Some(verify_ssl_certificate) => verify_ssl_certificate.parse().unwrap(),
-                None => match env::var("VERIFY_SSL_CERTIFICATE") {
-                    Ok(verify_ssl_certificate_string) => verify_ssl_certificate_string
-                        .parse()
-                        .expect("VERIFY_SSL_CERTIFICATE is not a boolean"),
-                    _ => true,
-                },
-            };
+        let keyring_file = KeyringFile::open(
+            &keyring_file,
+            password,
+            salt.clone(),
+            keyring_state_file.as_deref(),
+        );
+
+        if args.cmd_add_key {
+            return errors.finish(Some(Config::AddKeyConfig(AddKeyConfig { keyring_file })));
+        }
 
-            log::info!("verify_ssl_certificate: {:?}", verify_ssl_certificate);
+        let max_key_age = settings.max_key_age.map(days_to_duration);
 
-            log::info!(
-                "backend_connection_timeout: {:?}",
-                backend_connection_timeout
-            );
+        if args.cmd_rotate_keys {
+            return errors.finish(Some(Config::RotateKeysConfig(RotateKeysConfig {
+                keyring_file,
+                max_key_age: max_key_age.unwrap(),
+            })));
+        }
+
+        if args.cmd_sign_keyring {
+            return errors.finish(Some(Config::SignKeyringConfig(SignKeyringConfig {
+                keyring_file,
+            })));
+        }
+
+        let keyring = errors.check(
+            keyring_file
+                .try_load()
+                .map_err(|why| ConfigError::invalid("keyring_file", why)),
+        );
 
-            let aws_config = if let (Some(aws_access_key), Some(aws_secret_key), Some(region)) = (
-                &args.flag_aws_access_key,
-                &args.flag_aws_secret_key,
-                &args.flag_aws_region,
-            ) {
-                let config = AwsConfig::new(
-                    Credentials::new(
-                        aws_access_key,
-                        aws_secret_key,
-                        None,
-                        None,
-                        "cli-credentials",
-                    ),
-                    region.to_string(),
-                    args.flag_bypass_aws_signature_check,
-                );
-                Some(config)
+        if args.cmd_list_keys {
+            errors.finish(keyring.map(|keyring| Config::ListKeysConfig(ListKeysConfig { keyring })))
+        } else if args.cmd_encrypt {
+            let recovery_public_key = errors.check(read_recovery_public_key(&settings));
+
+            errors.finish(
+                keyring
+                    .zip(recovery_public_key)
+                    .map(|(keyring, recovery_public_key)| {
+                        Config::Encrypt(EncryptConfig {
+                            keyring,
+                            chunk_size,
+                            input_file: args.arg_input_file.clone().unwrap(),
+                            output_file: args.arg_output_file.clone().unwrap(),
+                            recovery_public_key,
+                        })
+                    }),
+            )
+        } else if args.cmd_decrypt {
+            errors.finish(keyring.map(|keyring| {
+                Config::Decrypt(DecryptConfig {
+                    keyring,
+                    decryption_secret_key: None,
+                    input_file: args.arg_input_file.clone().unwrap(),
+                    output_file: args.arg_output_file.clone().unwrap(),
+                })
+            }))
+        } else {
+            let keyring_reload_interval = settings.keyring_reload_interval.map(Duration::from_secs);
+
+            let keyring_reload = if keyring_reload_interval.is_some() || max_key_age.is_some() {
+                Some(KeyringReloadConfig {
+                    keyring_file,
+                    interval: keyring_reload_interval.unwrap_or(DEFAULT_KEYRING_RELOAD_INTERVAL),
+                    max_key_age,
+                })
             } else {
                 None
             };
 
-            Config::Http(HttpConfig {
-                keyring,
+            let tenants = tenant_entries
+                .iter()
+                .filter_map(|entry| errors.check(entry.load(&salt)))
+                .collect();
+
+            let http_config = errors.check(create_http_config(
+                &settings,
                 chunk_size,
-                upstream_base_url,
-                address,
-                local_encryption_directory,
-                aws_config,
-                backend_connection_timeout,
-                write_once,
-                redis_config: RedisConfig::create_redis_config(args),
-                verify_ssl_certificate,
-            })
+                ProxyKeys {
+                    keyring: keyring.unwrap_or_else(|| Keyring::new(HashMap::new())),
+                    keyring_reload,
+                    tenants,
+                },
+            ));
+
+            errors.finish(http_config.map(|c| Config::for_proxy(args, c)))
+        }
+    }
+
+    // check-config builds the same configuration as the proxy
+    fn for_proxy(args: &args::Args, http_config: HttpConfig) -> Config {
+        if args.cmd_check_config {
+            Config::CheckConfig(http_config)
+        } else {
+            Config::Http(http_config)
+        }
+    }
+}
+
+impl ProxyKeys {
+    fn none() -> ProxyKeys {
+        ProxyKeys {
+            keyring: Keyring::new(HashMap::new()),
+            keyring_reload: None,
+            tenants: vec![],
+        }
+    }
+}
+
+fn create_http_config(
+    settings: &Settings,
+    chunk_size: usize,
+    keys: ProxyKeys,
+) -> Result<HttpConfig, ConfigErrors> {
+    let mut errors = ConfigErrors::default();
+
+    let local_encryption_directory =
+        PathBuf::from(settings.local_encryption_directory.clone().unwrap());
+
+    if let Err(why) = std::fs::create_dir_all(&local_encryption_directory) {
+        errors.push(ConfigError::invalid(
+            "local_encryption_directory",
+            format!("cannot create {:?}: {}", local_encryption_directory, why),
+        ));
+    }
+
+    let upstream_base_url = match &settings.upstream_url {
+        Some(upstream_url) => errors.check(normalize_and_parse_upstream_url(
+            "upstream_url",
+            upstream_url.clone(),
+        )),
+        None => {
+            errors.push(ConfigError::missing(
+                "upstream_url",
+                "DS_UPSTREAM_URL env, --upstream-url cli argument or upstream_url in the config file",
+            ));
+            None
         }
+    };
+
+    let address = match &settings.address {
+        Some(address) => errors.check(parse_address(address)),
+        None => {
+            errors.push(ConfigError::missing(
+                "address",
+                "DS_ADDRESS env, --address cli argument or address in the config file",
+            ));
+            None
+        }
+    };
+
+    let tls = errors.check(read_tls_config(settings));
+    let pinned_keys = errors.check(parse_pinned_keys(
+        "upstream_pinned_keys",
+        settings.upstream_pinned_keys.as_deref().unwrap_or_default(),
+    ));
+    let upstream_tls = errors
+        .check(upstream_tls(
+            "upstream_tls",
+            UpstreamTls {
+                ca_file: settings.upstream_ca_file.as_ref().map(PathBuf::from),
+                client_cert_file: settings
+                    .upstream_client_cert_file
+                    .as_ref()
+                    .map(PathBuf::from),
+                client_key_file: settings
+                    .upstream_client_key_file
+                    .as_ref()
+                    .map(PathBuf::from),
+                min_version: settings.upstream_min_tls_version.clone(),
+                pinned_keys: pinned_keys.clone().unwrap_or_default(),
+            },
+            settings.verify_ssl_certificate.unwrap(),
+        ))
+        .filter(|_| pinned_keys.is_some());
+    let egress_proxy = errors.check(read_egress_proxy(settings));
+    let redis_config = errors.check(RedisConfig::create_redis_config(settings));
+    let recovery_public_key = errors.check(read_recovery_public_key(settings));
+    let encryption_public_key = errors.check(read_encryption_public_key(settings));
+    let decryption_secret_key = errors.check(read_decryption_secret_key(settings));
+
+    let circuit_breaker = errors.check(circuit_breaker_policy(
+        "circuit_breaker_error_rate",
+        settings.circuit_breaker_error_rate.unwrap(),
+        settings.circuit_breaker_min_requests.unwrap(),
+        Duration::from_secs(settings.circuit_breaker_open_duration.unwrap()),
+    ));
+
+    let route_entries = match &settings.routes_file {
+        Some(routes_file) => errors.check(read_route_entries(routes_file)),
+        None => Some(vec![]),
+    };
+
+    let endpoints = upstream_base_url.as_ref().and_then(|base_url| {
+        errors.check(parse_endpoints(
+            "upstream_endpoints",
+            base_url,
+            settings.upstream_endpoints.as_deref().unwrap_or_default(),
+        ))
+    });
+
+    let (
+        Some(upstream_base_url),
+        Some(endpoints),
+        Some(circuit_breaker),
+        Some(route_entries),
+        Some(address),
+        Some(tls),
+        Some(upstream_tls),
+        Some(egress_proxy),
+        Some(redis_config),
+        Some(recovery_public_key),
+        Some(encryption_public_key),
+        Some(decryption_secret_key),
+    ) = (
+        upstream_base_url,
+        endpoints,
+        circuit_breaker,
+        route_entries,
+        address,
+        tls,
+        upstream_tls,
+        egress_proxy,
+        redis_config,
+        recovery_public_key,
+        encryption_public_key,
+        decryption_secret_key,
+    )
+    else {
+        return Err(errors);
+    };
+
+    let upstream = Upstream {
+        prefix: String::new(),
+        base_url: upstream_base_url,
+        pool: Arc::new(EndpointPool::new(
+            endpoints,
+            settings.load_balancing.unwrap(),
+        )),
+        health_check_interval: Duration::from_secs(settings.health_check_interval.unwrap()),
+        health_check_path: settings.health_check_path.clone(),
+        retry: RetryPolicy {
+            max_retries: settings.max_retries.unwrap(),
+            base_delay: Duration::from_millis(settings.retry_base_delay.unwrap()),
+            max_delay: Duration::from_millis(settings.retry_max_delay.unwrap()),
+        },
+        circuit_breaker: Arc::new(CircuitBreaker::new(circuit_breaker)),
+        aws_config: aws_config(
+            &settings.aws_access_key,
+            &settings.aws_secret_key,
+            &settings.aws_region,
+            settings.bypass_aws_signature_check.unwrap(),
+        ),
+        verify_ssl_certificate: settings.verify_ssl_certificate.unwrap(),
+        tls: upstream_tls,
+        egress_proxy,
+        force_close: settings.upstream_force_close.unwrap(),
+        max_connections: settings.upstream_max_connections.unwrap(),
+        idle_timeout: Duration::from_secs(settings.upstream_idle_timeout.unwrap()),
+        backend_connection_timeout: Duration::from_secs(
+            settings.backend_connection_timeout.unwrap(),
+        ),
+        response_timeout: Duration::from_secs(settings.response_timeout.unwrap()),
+        upload_timeout: Duration::from_secs(settings.upload_timeout.unwrap()),
+    };
+
+    log::info!(
+        "verify_ssl_certificate: {:?}",
+        upstream.verify_ssl_certificate
+    );
+
+    log::info!(
+        "backend_connection_timeout: {:?}",
+        upstream.backend_connection_timeout
+    );
+
+    let routes: Vec<Upstream> = route_entries
+        .iter()
+        .filter_map(|entry| errors.check(entry.upstream(&upstream)))
+        .collect();
+
+    for (i, route) in routes.iter().enumerate() {
+        if routes[..i]
+            .iter()
+            .any(|other| other.prefix.trim_matches('/') == route.prefix.trim_matches('/'))
+        {
+            errors.push(ConfigError::invalid(
+                "routes_file",
+                format!("the prefix {} has several routes", route.prefix),
+            ));
+        }
+    }
+
+    errors.finish(Some(HttpConfig {
+        upstream,
+        routes,
+        keyring: keys.keyring,
+        chunk_size,
+        address,
+        local_encryption_directory,
+        write_once: settings.write_once.unwrap(),
+        redis_config,
+        max_in_memory_file_size: settings.max_in_memory_file_size.unwrap(),
+        stream_uploads: settings.stream_uploads.unwrap(),
+        copy_streamed_metadata: settings.copy_streamed_metadata.unwrap(),
+        write_once_lock_duration: settings.write_once_lock_duration.unwrap(),
+        keep_alive: match settings.keep_alive.unwrap() {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        },
+        tls,
+        keyring_reload: keys.keyring_reload,
+        tenants: keys.tenants,
+        recovery_public_key,
+        encryption_public_key,
+        decryption_secret_key,
+    }))
+}
+
+fn aws_config(
+    aws_access_key: &Option<String>,
+    aws_secret_key: &Option<String>,
+    aws_region: &Option<String>,
+    bypass_aws_signature_check: bool,
+) -> Option<AwsConfig> {
+    if let (Some(aws_access_key), Some(aws_secret_key), Some(region)) =
+        (aws_access_key, aws_secret_key, aws_region)
+    {
+        Some(AwsConfig::new(
+            Credentials::new(
+                aws_access_key,
+                aws_secret_key,
+                None,
+                None,
+                "cli-credentials",
+            ),
+            region.to_string(),
+            bypass_aws_signature_check,
+        ))
+    } else {
+        None
+    }
+}
+
+// the base url comes first
+fn parse_endpoints(
+    setting: &str,
+    base_url: &Url,
+    endpoints: &[String],
+) -> Result<Vec<Url>, ConfigError> {
+    std::iter::once(Ok(base_url.clone()))
+        .chain(
+            endpoints
+                .iter()
+                .map(|endpoint| normalize_and_parse_upstream_url(setting, endpoint.clone())),
+        )
+        .collect()
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr, ConfigError> {
+    address
+        .to_socket_addrs()
+        .map_err(|why| ConfigError::invalid("address", why))?
+        .next()
+        .ok_or_else(|| ConfigError::invalid("address", format!("{} has no address", address)))
+}
+
+fn circuit_breaker_policy(
+    setting: &str,
+    error_rate: u8,
+    min_requests: u32,
+    open_duration: Duration,
+) -> Result<CircuitBreakerPolicy, ConfigError> {
+    if error_rate > 100 {
+        return Err(ConfigError::invalid(
+            setting,
+            format!("{} is not a percentage", error_rate),
+        ));
     }
+
+    Ok(CircuitBreakerPolicy {
+        error_rate,
+        min_requests,
+        open_duration,
+    })
 }
 
 // ensure upstream_url ends with a "/ to avoid
@@ -237,17 +938,82 @@ impl Config {
 // request: "https://proxy/file"
 // "https://upstream/dir".join('file') => https://upstream/file
 // instead ".../upstream/dir/".join('file') => https://upstream/dir/file
-fn normalize_and_parse_upstream_url(mut url: String) -> Url {
+fn normalize_and_parse_upstream_url(setting: &str, mut url: String) -> Result<Url, ConfigError> {
     if !url.ends_with('/') {
         url.push('/');
     }
-    Url::parse(&url).unwrap()
+
+    let url = Url::parse(&url).map_err(|why| ConfigError::invalid(setting, why))?;
+
+    if !["http", "https"].contains(&url.scheme()) {
+        return Err(ConfigError::invalid(
+            setting,
+            format!("{} is not an http(s) url", url),
+        ));
+    }
+
+    Ok(url)
 }
 
 impl HttpConfig {
+    // the keyring of the tenant owning the upstream url, the default one otherwise.
+    // The url must come from `create_upstream_url` so that any `..` is already resolved,
+    // the tenant is then picked on the incoming path.
+    pub fn keyring_for(&self, upstream: &Upstream, upstream_url: &str) -> &Keyring {
+        let path = Url::parse(upstream_url)
+            .ok()
+            .and_then(|url| {
+                url.path()
+                    .strip_prefix(upstream.base_url.path())
+                    .map(|path| path.to_string())
+            })
+            .unwrap_or_default();
+
+        let prefix = upstream.prefix.trim_matches('/');
+
+        if prefix.is_empty() {
+            self.keyring_for_path(&path)
+        } else {
+            self.keyring_for_path(&format!("{}/{}", prefix, path))
+        }
+    }
+
+    // the routes exposed by the proxy depend on the key material it holds
+    pub fn can_encrypt(&self) -> bool {
+        self.encryption_public_key.is_some() || !self.keyring.is_empty()
+    }
+
+    pub fn can_decrypt(&self) -> bool {
+        self.decryption_secret_key.is_some() || !self.keyring.is_empty()
+    }
+
+    pub fn keyring_for_path(&self, path: &str) -> &Keyring {
+        longest_prefix_match(&self.tenants, path, |t| &t.prefix)
+            .map(|tenant| &tenant.keyring)
+            .unwrap_or(&self.keyring)
+    }
+
+    pub fn upstreams(&self) -> impl Iterator<Item = &Upstream> {
+        std::iter::once(&self.upstream).chain(self.routes.iter())
+    }
+
+    // the route with the longest prefix matching the request, the default upstream otherwise
+    pub fn upstream_for(&self, req: &HttpRequest) -> &Upstream {
+        let path = req.uri().path().strip_prefix("/upstream/").unwrap_or("");
+
+        longest_prefix_match(&self.routes, path, |r| &r.prefix).unwrap_or(&self.upstream)
+    }
+
     pub fn create_upstream_url(&self, req: &HttpRequest) -> Option<String> {
+        // the bucket itself, listed with the query
         if req.match_info().get("name").is_none() {
-            return Some(self.upstream_base_url.to_string());
+            let mut url = self.upstream.base_url.clone();
+
+            if !req.query_string().is_empty() {
+                url.set_query(Some(req.query_string()));
+            }
+
+            return Some(url.to_string());
         }
 
         // we does not use `get("name")` as it decodes percent-encoded characters
@@ -255,13 +1021,26 @@ impl HttpConfig {
         let upstream_path = req.uri().path().strip_prefix("/upstream/").unwrap();
         log::debug!("Creating upstream url for : {}", upstream_path);
 
+        let upstream = self.upstream_for(req);
+
+        // the prefix of a route is replaced by its base url
+        let upstream_path = if upstream.prefix.is_empty() {
+            upstream_path
+        } else {
+            upstream_path
+                .trim_start_matches('/')
+                .strip_prefix(upstream.prefix.trim_matches('/'))
+                .unwrap()
+                .trim_start_matches('/')
+        };
+
         // Warning: join process '../'
         // "https://a.com/jail/".join('../escape') => "https://a.com/escape"
-        let mut url = self.upstream_base_url.join(upstream_path).unwrap();
+        let mut url = upstream.base_url.join(upstream_path).unwrap();
 
         log::debug!("Created upstream url: {}", url);
 
-        if self.is_traversal_attack(&url) {
+        if upstream.is_traversal_attack(&url) {
             return None;
         }
 
@@ -281,19 +1060,25 @@ impl HttpConfig {
 
         Some(filepath)
     }
+}
+
+impl Upstream {
+    // the url built by `create_upstream_url`, sent to an endpoint of the pool
+    pub fn endpoint_url(&self, url: &str, endpoint: usize) -> String {
+        self.pool.endpoint_url(&self.base_url, url, endpoint)
+    }
 
     fn is_traversal_attack(&self, url: &Url) -> bool {
         // https://upstream.com => [Some("")]
         // https://upstream.com/jail/cell/ => [Some("jail"), Some("cell"), Some("")]
-        let mut base_segments: Vec<&str> =
-            self.upstream_base_url.path_segments().unwrap().collect();
+        let mut base_segments: Vec<&str> = self.base_url.path_segments().unwrap().collect();
 
         // remove the last segment corresponding to "/"
         base_segments.pop();
 
         let mut url_segments = url.path_segments().unwrap();
 
-        // ensure that all segment of the upstream_base_url
+        // ensure that all segment of the upstream base url
         // are present in the final url
         let safe = base_segments.iter().all(|base_segment| {
             let url_segment = url_segments.next().unwrap();
@@ -304,11 +1089,306 @@ impl HttpConfig {
     }
 }
 
-fn read_file_content(path_string: &str) -> String {
-    match std::fs::read(path_string) {
-        Err(why) => panic!("couldn't open {}: {}", path_string, why),
-        Ok(file) => String::from_utf8(file).unwrap(),
+fn decode_key(setting: &str, base64_key: &str) -> Result<Vec<u8>, ConfigError> {
+    STANDARD
+        .decode(base64_key.trim())
+        .map_err(|_| ConfigError::invalid(setting, "a key must be encoded in base64"))
+}
+
+fn read_public_key(
+    public_key: &Option<String>,
+    setting: &str,
+) -> Result<Option<PublicKey>, ConfigError> {
+    match public_key {
+        Some(public_key) => PublicKey::from_slice(&decode_key(setting, public_key)?)
+            .map(Some)
+            .ok_or_else(|| ConfigError::invalid(setting, "not a public key")),
+        None => Ok(None),
+    }
+}
+
+fn read_secret_key(setting: &str, secret_key_file: &str) -> Result<SecretKey, ConfigError> {
+    let content = read_file_content(setting, secret_key_file)?;
+
+    SecretKey::from_slice(&decode_key(setting, &content)?).ok_or_else(|| {
+        ConfigError::invalid(setting, format!("no secret key in {}", secret_key_file))
+    })
+}
+
+// the files are loaded to report their problems before starting
+fn upstream_tls(
+    setting: &str,
+    tls: UpstreamTls,
+    verify_ssl_certificate: bool,
+) -> Result<UpstreamTls, ConfigError> {
+    if tls.client_cert_file.is_some() != tls.client_key_file.is_some() {
+        return Err(ConfigError::invalid(
+            setting,
+            "a client certificate needs both a certificate and a key file",
+        ));
+    }
+
+    if let Some(version) = &tls.min_version {
+        parse_tls_version(version).map_err(|why| ConfigError::invalid(setting, why))?;
+    }
+
+    // without pinned keys, nothing would authenticate the upstream
+    if !verify_ssl_certificate && tls.pinned_keys.is_empty() {
+        return Err(ConfigError::invalid(
+            setting,
+            "the certificate of the upstream cannot go unverified: trust its authority with --upstream-ca-file, or pin its key with --upstream-pinned-key",
+        ));
+    }
+
+    tls.ssl_connector(verify_ssl_certificate)
+        .map_err(|why| ConfigError::invalid(setting, why))?;
+
+    Ok(tls)
+}
+
+fn read_egress_proxy(settings: &Settings) -> Result<Option<EgressProxy>, ConfigError> {
+    match &settings.https_proxy {
+        Some(https_proxy) => EgressProxy::parse(
+            https_proxy,
+            settings.no_proxy.as_deref().unwrap_or_default(),
+        )
+        .map(Some)
+        .map_err(|why| ConfigError::invalid("https_proxy", why)),
+        None => Ok(None),
+    }
+}
+
+fn parse_pinned_keys(setting: &str, pinned_keys: &[String]) -> Result<Vec<[u8; 32]>, ConfigError> {
+    pinned_keys
+        .iter()
+        .map(|pin| parse_pinned_key(pin).map_err(|why| ConfigError::invalid(setting, why)))
+        .collect()
+}
+
+// the files are loaded to report their problems before starting
+fn read_tls_config(settings: &Settings) -> Result<Option<TlsConfig>, ConfigError> {
+    let tls = match (&settings.tls_cert_file, &settings.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => TlsConfig {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+            client_ca_file: settings.tls_client_ca_file.as_ref().map(|file| file.into()),
+        },
+        (None, None) if settings.tls_client_ca_file.is_some() => {
+            return Err(ConfigError::invalid(
+                "tls_client_ca_file",
+                "client certificates require tls_cert_file and tls_key_file",
+            ))
+        }
+        (None, None) => return Ok(None),
+        (Some(_), None) => {
+            return Err(ConfigError::missing(
+                "tls_key_file",
+                "DS_TLS_KEY_FILE env, --tls-key-file cli argument or tls_key_file in the config file",
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(ConfigError::missing(
+                "tls_cert_file",
+                "DS_TLS_CERT_FILE env, --tls-cert-file cli argument or tls_cert_file in the config file",
+            ))
+        }
+    };
+
+    tls.ssl_context()
+        .map_err(|why| ConfigError::invalid("tls_cert_file", why))?;
+
+    Ok(Some(tls))
+}
+
+fn read_recovery_public_key(settings: &Settings) -> Result<Option<PublicKey>, ConfigError> {
+    read_public_key(&settings.recovery_public_key, "recovery_public_key")
+}
+
+// the files are then sealed to the encryption key only, not to the recovery one
+fn read_encryption_public_key(settings: &Settings) -> Result<Option<PublicKey>, ConfigError> {
+    if settings.encryption_public_key.is_some() && settings.recovery_public_key.is_some() {
+        return Err(ConfigError::invalid(
+            "encryption_public_key",
+            "cannot be combined with recovery_public_key: keep the decryption secret key in escrow instead",
+        ));
+    }
+
+    read_public_key(&settings.encryption_public_key, "encryption_public_key")
+}
+
+fn read_decryption_secret_key(settings: &Settings) -> Result<Option<SecretKey>, ConfigError> {
+    settings
+        .decryption_secret_key_file
+        .as_ref()
+        .map(|secret_key_file| read_secret_key("decryption_secret_key_file", secret_key_file))
+        .transpose()
+}
+
+// without keyring, a proxy with the encryption public key only can encrypt
+// but never decrypt, and one with the decryption secret key only the reverse
+fn is_keyless_proxy(settings: &Settings) -> bool {
+    settings.keyring_file.is_none()
+        && (settings.encryption_public_key.is_some()
+            || settings.decryption_secret_key_file.is_some())
+}
+
+// core dumps are disabled by default as they would contain the keys,
+// and stay disabled if the settings cannot be loaded
+pub fn core_dumps_allowed(args: &args::Args) -> bool {
+    Settings::load(args)
+        .map(|settings| settings.allow_core_dumps.unwrap())
+        .unwrap_or(false)
+}
+
+// a prefix only matches whole path segments:
+// "bucket" owns "bucket/file" but not "bucket-2/file"
+fn longest_prefix_match<'a, T>(
+    items: &'a [T],
+    path: &str,
+    prefix_of: impl Fn(&T) -> &str,
+) -> Option<&'a T> {
+    let path = path.trim_start_matches('/');
+
+    items
+        .iter()
+        .filter(|item| {
+            let prefix = prefix_of(item).trim_matches('/');
+            path == prefix || path.starts_with(&format!("{}/", prefix))
+        })
+        .max_by_key(|item| prefix_of(item).trim_matches('/').len())
+}
+
+fn read_tenant_entries(tenants_file: &str) -> Result<Vec<TenantEntry>, ConfigError> {
+    let content = read_file_content("tenants_file", tenants_file)?;
+
+    toml::from_str::<TenantsFile>(&content)
+        .map(|file| file.tenants)
+        .map_err(|why| ConfigError::invalid("tenants_file", format!("{}: {}", tenants_file, why)))
+}
+
+fn read_route_entries(routes_file: &str) -> Result<Vec<RouteEntry>, ConfigError> {
+    let content = read_file_content("routes_file", routes_file)?;
+
+    toml::from_str::<RoutesFile>(&content)
+        .map(|file| file.routes)
+        .map_err(|why| ConfigError::invalid("routes_file", format!("{}: {}", routes_file, why)))
+}
+
+fn read_salt(settings: &Settings) -> Result<String, ConfigError> {
+    match &settings.salt {
+        Some(salt) => check_salt("salt", salt),
+        None => Err(ConfigError::missing(
+            "salt",
+            "DS_SALT env, --salt cli argument or salt in the config file",
+        )),
+    }
+}
+
+fn check_salt(setting: &str, salt: &str) -> Result<String, ConfigError> {
+    if salt.len() != pwhash::SALTBYTES {
+        return Err(ConfigError::invalid(
+            setting,
+            format!("must be {} bytes long", pwhash::SALTBYTES),
+        ));
     }
+
+    Ok(salt.to_string())
+}
+
+fn read_keyring_file(settings: &Settings) -> Result<String, ConfigError> {
+    settings.keyring_file.clone().ok_or_else(|| {
+        ConfigError::missing(
+            "keyring_file",
+            "DS_KEYRING env, --keyring-file cli argument or keyring_file in the config file",
+        )
+    })
+}
+
+fn days_to_duration(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 60 * 60)
+}
+
+// any threshold of shares must rebuild the secret
+fn check_threshold(threshold: u8, nb_shares: u8) -> Result<u8, ConfigError> {
+    if threshold == 0 {
+        Err(ConfigError::invalid(
+            "threshold",
+            "at least 1 share is needed",
+        ))
+    } else if nb_shares < threshold {
+        Err(ConfigError::invalid(
+            "threshold",
+            format!("{} is more than the {} shares", threshold, nb_shares),
+        ))
+    } else {
+        Ok(threshold)
+    }
+}
+
+fn read_password(settings: &Settings) -> Result<String, ConfigErrors> {
+    if let Some(share_files) = &settings.share_files {
+        return password_from_shares(share_files);
+    }
+
+    match &settings.password_file {
+        Some(password_file) => Ok(read_file_content("password_file", password_file)?),
+        None => settings.password.clone().ok_or_else(|| {
+            ConfigError::missing(
+                "password",
+                "DS_PASSWORD env, --password-file or --share-file cli argument",
+            )
+            .into()
+        }),
+    }
+}
+
+fn password_from_shares(share_files: &[String]) -> Result<String, ConfigErrors> {
+    String::from_utf8(combine_share_files(share_files)?).map_err(|_| {
+        ConfigError::invalid(
+            "share_file",
+            "the shares do not combine into a valid password",
+        )
+        .into()
+    })
+}
+
+// each share file can be a fifo filled by its custodian
+fn combine_share_files(share_files: &[String]) -> Result<Vec<u8>, ConfigErrors> {
+    let mut errors = ConfigErrors::default();
+
+    let shares: Vec<Share> = share_files
+        .iter()
+        .filter_map(|share_file| {
+            let share = read_file_content("share_file", share_file).and_then(|content| {
+                content.parse::<Share>().map_err(|why| {
+                    ConfigError::invalid("share_file", format!("{}: {}", share_file, why))
+                })
+            });
+
+            errors.check(share)
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    combine(&shares).map_err(|why| {
+        ConfigError::invalid(
+            "share_file",
+            format!("couldn't combine the shares: {}", why),
+        )
+        .into()
+    })
+}
+
+fn read_file_content(setting: &str, path_string: &str) -> Result<String, ConfigError> {
+    let content = std::fs::read(path_string).map_err(|why| {
+        ConfigError::invalid(setting, format!("couldn't open {}: {}", path_string, why))
+    })?;
+
+    String::from_utf8(content)
+        .map_err(|_| ConfigError::invalid(setting, format!("{} is not valid utf-8", path_string)))
 }
 
 #[cfg(test)]
@@ -354,7 +1434,11 @@ mod tests {
     #[test]
     fn test_normalize_and_parse_upstream_url() {
         assert_eq!(
-            normalize_and_parse_upstream_url("https://upstream.com/dir".to_string()),
+            normalize_and_parse_upstream_url(
+                "upstream_url",
+                "https://upstream.com/dir".to_string()
+            )
+            .unwrap(),
             Url::parse("https://upstream.com/dir/").unwrap()
         );
     }
@@ -431,6 +1515,15 @@ mod tests {
             Some("https://upstream.com/".to_string())
         );
 
+        let listing = TestRequest::default()
+            .uri("https://proxy.com/upstream?list-type=2&prefix=dir%2F")
+            .to_http_request();
+
+        assert_eq!(
+            config.create_upstream_url(&listing),
+            Some("https://upstream.com/?list-type=2&prefix=dir%2F".to_string())
+        );
+
         let testing_encoding = TestRequest::default()
             .uri("https://proxy.com/upstream/plop%20plop%27plop.png")
             .param("name", "plop plop'plop.png")
@@ -442,20 +1535,283 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_routes() {
+        let mut config = default_config("https://swift.com/container/");
+        config.routes = vec![
+            upstream("attachments", "https://s3.com/attachments"),
+            upstream("/exports/", "https://s3.com/exports/"),
+        ];
+
+        let request = |path: &str| {
+            TestRequest::default()
+                .uri(&format!("https://proxy.com/upstream/{}", path))
+                .param("name", path.to_string())
+                .to_http_request()
+        };
+
+        let test_route = |path: &str, expected: Option<&str>| {
+            assert_eq!(
+                config.create_upstream_url(&request(path)),
+                expected.map(|url| url.to_string())
+            );
+        };
+
+        test_route("attachments/file", Some("https://s3.com/attachments/file"));
+        test_route("exports/sub/file", Some("https://s3.com/exports/sub/file"));
+        test_route("attachments", Some("https://s3.com/attachments/"));
+        test_route(
+            "attachments-2/file",
+            Some("https://swift.com/container/attachments-2/file"),
+        );
+        test_route("file", Some("https://swift.com/container/file"));
+        // the traversal check applies to each route
+        test_route("attachments/../exports/file", None);
+
+        assert_eq!(
+            "attachments",
+            config.upstream_for(&request("attachments/file")).prefix
+        );
+        assert_eq!("", config.upstream_for(&request("file")).prefix);
+    }
+
+    #[test]
+    fn a_route_inherits_the_retry_policy() {
+        let entry: RouteEntry = toml::from_str(
+            r#"
+            prefix = "attachments"
+            upstream_url = "https://s3.com/attachments"
+            max_retries = 5
+            "#,
+        )
+        .unwrap();
+
+        let default = upstream("", "https://swift.com/container/");
+        let route = entry.upstream(&default).unwrap();
+
+        assert_eq!(5, route.retry.max_retries);
+        assert_eq!(default.retry.base_delay, route.retry.base_delay);
+        assert_eq!(default.retry.max_delay, route.retry.max_delay);
+    }
+
+    #[test]
+    fn test_keyring_for_a_route() {
+        use sodiumoxide::crypto::secretstream::xchacha20poly1305::Key;
+
+        let mut config = default_config("https://swift.com/container/");
+        config.keyring = Keyring::new(HashMap::from([(0, Key([0; 32]))]));
+        config.routes = vec![upstream("attachments", "https://s3.com/bucket/")];
+        config.tenants = vec![Tenant {
+            prefix: "attachments/".to_string(),
+            keyring_file: KeyringFile::open("keyring.toml", "".to_string(), "s".repeat(32), None),
+            keyring: Keyring::new(HashMap::from([(1, Key([0; 32]))])),
+        }];
+
+        let key_id = |upstream: &Upstream, url: &str| {
+            config.keyring_for(upstream, url).get_last_key().unwrap().0
+        };
+
+        // the tenant is picked on the incoming path
+        assert_eq!(1, key_id(&config.routes[0], "https://s3.com/bucket/file"));
+        assert_eq!(
+            0,
+            key_id(&config.upstream, "https://swift.com/container/file")
+        );
+    }
+
+    #[test]
+    fn test_keyring_for() {
+        use sodiumoxide::crypto::secretstream::xchacha20poly1305::Key;
+
+        let tenant = |prefix: &str, key_id: u64| Tenant {
+            prefix: prefix.to_string(),
+            keyring_file: KeyringFile::open("keyring.toml", "".to_string(), "s".repeat(32), None),
+            keyring: Keyring::new(HashMap::from([(key_id, Key([0; 32]))])),
+        };
+
+        let mut config = default_config("https://upstream.com/jail/");
+        config.keyring = Keyring::new(HashMap::from([(0, Key([0; 32]))]));
+        config.tenants = vec![
+            tenant("bucket", 1),
+            tenant("bucket/sub/", 2),
+            tenant("/other/", 3),
+        ];
+
+        let key_id = |url: &str| {
+            config
+                .keyring_for(&config.upstream, url)
+                .get_last_key()
+                .unwrap()
+                .0
+        };
+
+        assert_eq!(1, key_id("https://upstream.com/jail/bucket/file"));
+        assert_eq!(1, key_id("https://upstream.com/jail/bucket?list-type=2"));
+        assert_eq!(2, key_id("https://upstream.com/jail/bucket/sub/file"));
+        assert_eq!(3, key_id("https://upstream.com/jail/other/file"));
+        assert_eq!(0, key_id("https://upstream.com/jail/bucket-2/file"));
+        assert_eq!(0, key_id("https://upstream.com/jail/file"));
+        assert_eq!(0, key_id("https://upstream.com/bucket/file"));
+
+        assert_eq!(
+            2,
+            config
+                .keyring_for_path("bucket/sub/file")
+                .get_last_key()
+                .unwrap()
+                .0
+        );
+    }
+
+    #[test]
+    fn test_routes_depend_on_key_material() {
+        use sodiumoxide::crypto::box_;
+        use sodiumoxide::crypto::secretstream::xchacha20poly1305::Key;
+
+        let (public_key, secret_key) = box_::gen_keypair();
+
+        let mut write_only = default_config("https://upstream.com/");
+        write_only.encryption_public_key = Some(public_key);
+        assert!(write_only.can_encrypt());
+        assert!(!write_only.can_decrypt());
+
+        let mut read_only = default_config("https://upstream.com/");
+        read_only.decryption_secret_key = Some(secret_key);
+        assert!(!read_only.can_encrypt());
+        assert!(read_only.can_decrypt());
+
+        let mut with_keyring = default_config("https://upstream.com/");
+        with_keyring.keyring = Keyring::new(HashMap::from([(0, Key([0; 32]))]));
+        assert!(with_keyring.can_encrypt());
+        assert!(with_keyring.can_decrypt());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let args = args::Args {
+            cmd_proxy: true,
+            flag_address: Some("not an address".to_string()),
+            flag_upstream_url: Some("ftp://upstream.com".to_string()),
+            flag_salt: Some("too short".to_string()),
+            flag_recovery_public_key: Some("not base64 !".to_string()),
+            flag_tls_cert_file: Some("cert.pem".to_string()),
+            flag_https_proxy: Some("https://egress:3128".to_string()),
+            ..args::Args::default()
+        };
+
+        let Err(ConfigErrors(errors)) = Config::create_config(&args) else {
+            panic!("the configuration should be refused");
+        };
+
+        let settings: Vec<&str> = errors
+            .iter()
+            .map(|error| match error {
+                ConfigError::Missing { setting, .. } => setting.as_str(),
+                ConfigError::Invalid { setting, .. } => setting.as_str(),
+            })
+            .collect();
+
+        assert_eq!(
+            vec![
+                "password",
+                "salt",
+                "keyring_file",
+                "upstream_url",
+                "address",
+                "tls_key_file",
+                "https_proxy",
+                "recovery_public_key"
+            ],
+            settings
+        );
+    }
+
+    #[test]
+    fn the_upstream_certificate_is_always_checked() {
+        assert!(upstream_tls("upstream_tls", UpstreamTls::default(), true).is_ok());
+        assert!(upstream_tls("upstream_tls", UpstreamTls::default(), false).is_err());
+
+        // only the pinned key is checked
+        let pinned = UpstreamTls {
+            pinned_keys: vec![[7; 32]],
+            ..UpstreamTls::default()
+        };
+        assert!(upstream_tls("upstream_tls", pinned, false).is_ok());
+    }
+
+    #[test]
+    fn a_write_only_proxy_has_no_recovery_key() {
+        use sodiumoxide::crypto::box_;
+
+        let (public_key, _) = box_::gen_keypair();
+        let public_key = STANDARD.encode(public_key);
+
+        let mut settings = Settings {
+            encryption_public_key: Some(public_key.clone()),
+            ..Settings::default()
+        };
+        assert!(read_encryption_public_key(&settings).unwrap().is_some());
+
+        settings.recovery_public_key = Some(public_key);
+        assert!(read_encryption_public_key(&settings).is_err());
+    }
+
     fn default_config(upstream_base_url: &str) -> HttpConfig {
         let keyring = Keyring::new(HashMap::new());
 
         HttpConfig {
+            upstream: upstream("", upstream_base_url),
+            routes: vec![],
             keyring,
             chunk_size: DEFAULT_CHUNK_SIZE,
-            upstream_base_url: normalize_and_parse_upstream_url(upstream_base_url.to_string()),
             address: "127.0.0.1:1234".to_socket_addrs().unwrap().next().unwrap(),
             local_encryption_directory: PathBuf::from(DEFAULT_LOCAL_ENCRYPTION_DIRECTORY),
-            aws_config: None,
-            backend_connection_timeout: Duration::from_secs(1),
             write_once: false,
             redis_config: RedisConfig::default(),
+            keyring_reload: None,
+            tenants: vec![],
+            recovery_public_key: None,
+            encryption_public_key: None,
+            decryption_secret_key: None,
+            max_in_memory_file_size: 1024,
+            stream_uploads: false,
+            copy_streamed_metadata: false,
+            write_once_lock_duration: 3600,
+            keep_alive: None,
+            tls: None,
+        }
+    }
+
+    fn upstream(prefix: &str, base_url: &str) -> Upstream {
+        let base_url =
+            normalize_and_parse_upstream_url("upstream_url", base_url.to_string()).unwrap();
+
+        Upstream {
+            prefix: prefix.to_string(),
+            base_url: base_url.clone(),
+            pool: Arc::new(EndpointPool::new(vec![base_url], LoadBalancing::RoundRobin)),
+            health_check_interval: Duration::from_secs(10),
+            health_check_path: None,
+            retry: RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(100),
+                max_delay: Duration::from_millis(2000),
+            },
+            circuit_breaker: Arc::new(CircuitBreaker::new(CircuitBreakerPolicy {
+                error_rate: 0,
+                min_requests: 20,
+                open_duration: Duration::from_secs(30),
+            })),
+            aws_config: None,
             verify_ssl_certificate: true,
+            tls: UpstreamTls::default(),
+            egress_proxy: None,
+            force_close: false,
+            max_connections: 100,
+            idle_timeout: Duration::from_secs(15),
+            backend_connection_timeout: Duration::from_secs(1),
+            response_timeout: Duration::from_secs(30),
+            upload_timeout: Duration::from_secs(3600),
         }
     }
 }
@@ -0,0 +1,115 @@
+use std::fmt;
+
+// a problem found while loading the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    Missing { setting: String, hint: String },
+    Invalid { setting: String, reason: String },
+}
+
+impl ConfigError {
+    // hint: where the setting can be given
+    pub fn missing(setting: &str, hint: &str) -> ConfigError {
+        ConfigError::Missing {
+            setting: setting.to_string(),
+            hint: hint.to_string(),
+        }
+    }
+
+    pub fn invalid(setting: &str, reason: impl fmt::Display) -> ConfigError {
+        ConfigError::Invalid {
+            setting: setting.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Missing { setting, hint } => {
+                write!(f, "missing {}, use {}", setting, hint)
+            }
+            ConfigError::Invalid { setting, reason } => {
+                write!(f, "invalid {}: {}", setting, reason)
+            }
+        }
+    }
+}
+
+// all the problems found, so that they can be fixed at once
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl ConfigErrors {
+    // keeps the errors of the result to report them later
+    pub fn check<T, E: Into<ConfigErrors>>(&mut self, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(errors) => {
+                self.0.extend(errors.into().0);
+                None
+            }
+        }
+    }
+
+    pub fn push(&mut self, error: ConfigError) {
+        self.0.push(error);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // the value is only usable if no problem was found
+    pub fn finish<T>(self, value: Option<T>) -> Result<T, ConfigErrors> {
+        match value {
+            Some(value) if self.is_empty() => Ok(value),
+            _ => Err(self),
+        }
+    }
+}
+
+impl From<ConfigError> for ConfigErrors {
+    fn from(error: ConfigError) -> ConfigErrors {
+        ConfigErrors(vec![error])
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_and_display_the_errors() {
+        let mut errors = ConfigErrors::default();
+
+        assert_eq!(Some(1), errors.check(Ok::<_, ConfigError>(1)));
+        assert_eq!(
+            None,
+            errors.check(Err::<u8, _>(ConfigError::missing("address", "--address")))
+        );
+        errors.push(ConfigError::invalid("salt", "must be 32 bytes long"));
+
+        assert_eq!(
+            "invalid configuration:\n  - missing address, use --address\n  - invalid salt: must be 32 bytes long",
+            errors.to_string()
+        );
+        assert!(errors.finish(Some(1)).is_err());
+        assert_eq!(Ok(1), ConfigErrors::default().finish(Some(1)));
+    }
+}
@@ -1,3 +1,5 @@
+use super::escrow::SEALED_KEY_SIZE;
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum DecipherType {
     Encrypted {
@@ -5,5 +7,13 @@ pub enum DecipherType {
         key_id: u64,
         header_size: usize,
     },
+    // the key is sealed in the header, to be opened with a secret key
+    Sealed {
+        chunk_size: usize,
+        sealed_key: [u8; SEALED_KEY_SIZE],
+        header_size: usize,
+    },
+    // segments, each one holding an encrypted file
+    Multipart,
     Plaintext,
 }
@@ -1,12 +1,39 @@
 use super::super::keyring::Keyring;
 use super::decipher_type::DecipherType;
+use super::escrow::open_sealed_key;
+use super::header_decoder::{parse_header, parse_segment_header, ParseHeaderResponse};
+use actix_web::error::PayloadError;
 use actix_web::web::{Bytes, BytesMut};
+use actix_web::ResponseError;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use futures_core::stream::Stream;
 use log::{error, trace};
+use sodiumoxide::crypto::box_::SecretKey;
 use sodiumoxide::crypto::secretstream::xchacha20poly1305;
 use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Header, Key};
+use std::fmt;
+
+// an encrypted content which cannot be read: the stream ends with it
+#[derive(Debug)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl ResponseError for DecodeError {}
+
+// the body of an upstream response is read as a payload
+impl From<DecodeError> for PayloadError {
+    fn from(e: DecodeError) -> Self {
+        PayloadError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
 
 pub struct Decoder<E> {
     inner: Box<dyn Stream<Item = Result<Bytes, E>> + Unpin>,
@@ -15,43 +42,202 @@ pub struct Decoder<E> {
     stream_decoder: Option<xchacha20poly1305::Stream<xchacha20poly1305::Pull>>,
     buffer: BytesMut,
     keyring: Keyring,
+    secret_key: Option<SecretKey>,
+    // the sealed key, once opened
+    opened_key: Option<Key>,
+    // for a multipart upload, the encrypted file of each segment is decrypted in turn
+    segments: Option<Segments>,
 }
 
-impl<E> Decoder<E> {
+struct Segments {
+    // the bytes received after the current segment
+    pending: BytesMut,
+    // the bytes of the current segment not moved to the buffer yet
+    left: usize,
+    // the segment header has been read, then the header of its encrypted file
+    in_segment: bool,
+    header_parsed: bool,
+}
+
+impl<E: From<DecodeError>> Decoder<E> {
     pub fn new_from_cypher_and_buffer(
         keyring: Keyring,
         s: Box<dyn Stream<Item = Result<Bytes, E>> + Unpin>,
         decipher_type: DecipherType,
         b: Option<BytesMut>,
     ) -> Decoder<E> {
+        let buffer = b.unwrap_or_default();
+
+        // the segment headers are left in the buffer by the header decoder
+        let (buffer, segments) = match decipher_type {
+            DecipherType::Multipart => (
+                BytesMut::new(),
+                Some(Segments {
+                    pending: buffer,
+                    left: 0,
+                    in_segment: false,
+                    header_parsed: false,
+                }),
+            ),
+            _ => (buffer, None),
+        };
+
         Decoder {
             inner: s,
             inner_ended: false,
             decipher_type,
             stream_decoder: None,
-            buffer: b.unwrap_or_default(),
+            buffer,
             keyring,
+            secret_key: None,
+            opened_key: None,
+            segments,
         }
     }
 
+    // opens the keys sealed in the header of the files without key id
+    pub fn with_secret_key(mut self, secret_key: Option<SecretKey>) -> Self {
+        self.secret_key = secret_key;
+        self
+    }
+
     fn decrypt_buffer(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, E>>> {
+        if self.segments.is_some() {
+            return self.decrypt_segments(cx);
+        }
+
         if self.inner_ended && self.buffer.is_empty() {
             trace!("buffer empty and stream ended, stop");
             Poll::Ready(None)
         } else {
-            match self.decipher_type {
-                DecipherType::Encrypted {
-                    chunk_size, key_id, ..
-                } => {
-                    if let Some(key) = self.keyring.get_key_by_id(&key_id) {
-                        self.decrypt(cx, &chunk_size, key)
-                    } else {
-                        panic!("Key {} not found !", key_id)
-                    }
+            self.decrypt_file(cx)
+        }
+    }
+
+    fn decrypt_file(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, E>>> {
+        match self.decipher_type {
+            DecipherType::Encrypted {
+                chunk_size, key_id, ..
+            } => {
+                if let Some(key) = self.keyring.get_key_by_id(&key_id) {
+                    self.decrypt(cx, &chunk_size, key)
+                } else {
+                    panic!("Key {} not found !", key_id)
+                }
+            }
+
+            DecipherType::Sealed {
+                chunk_size,
+                sealed_key,
+                ..
+            } => {
+                if self.opened_key.is_none() {
+                    self.opened_key = self
+                        .secret_key
+                        .as_ref()
+                        .and_then(|secret_key| open_sealed_key(&sealed_key, secret_key));
                 }
 
-                DecipherType::Plaintext => Poll::Ready(Some(Ok(self.buffer.split().freeze()))),
+                if let Some(key) = self.opened_key.clone() {
+                    self.decrypt(cx, &chunk_size, key)
+                } else {
+                    panic!("Unable to open the sealed key !")
+                }
             }
+
+            DecipherType::Multipart => self.fail("a segment holds another segment"),
+
+            DecipherType::Plaintext => Poll::Ready(Some(Ok(self.buffer.split().freeze()))),
+        }
+    }
+
+    fn decrypt_segments(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, E>>> {
+        let segments = self.segments.as_mut().unwrap();
+
+        if !segments.in_segment {
+            if segments.pending.is_empty() && self.inner_ended {
+                trace!("no more segment, stop");
+                return Poll::Ready(None);
+            }
+
+            if segments.pending.len() < super::header::SEGMENT_HEADER_SIZE {
+                trace!("not enough data to read the segment header");
+                return self.wait_for_more(cx);
+            }
+
+            let Some(left) = parse_segment_header(&mut segments.pending) else {
+                return self.fail("invalid segment header");
+            };
+
+            segments.left = left;
+            segments.in_segment = true;
+            segments.header_parsed = false;
+            self.stream_decoder = None;
+            self.opened_key = None;
+        }
+
+        let moved = segments.left.min(segments.pending.len());
+        self.buffer
+            .extend_from_slice(&segments.pending.split_to(moved));
+        segments.left -= moved;
+
+        if !segments.header_parsed {
+            match parse_header(&mut self.buffer) {
+                ParseHeaderResponse::DecipherType(DecipherType::Multipart) => {
+                    return self.fail("a segment holds another segment");
+                }
+                ParseHeaderResponse::DecipherType(decipher_type) => {
+                    self.decipher_type = decipher_type;
+                    segments.header_parsed = true;
+                }
+                // an empty part has no header
+                ParseHeaderResponse::MissingBytes if segments.left == 0 => {
+                    segments.in_segment = false;
+                    return self.decrypt_segments(cx);
+                }
+                ParseHeaderResponse::MissingBytes => return self.wait_for_more(cx),
+            }
+        }
+
+        if segments.left == 0 && self.buffer.is_empty() {
+            trace!("segment decrypted");
+            segments.in_segment = false;
+            return self.decrypt_segments(cx);
+        }
+
+        self.decrypt_file(cx)
+    }
+
+    // the error is the last item of the stream
+    fn fail(&mut self, why: &str) -> Poll<Option<Result<Bytes, E>>> {
+        error!("unable to decrypt: {}", why);
+
+        self.inner_ended = true;
+        self.buffer.clear();
+        self.segments = Some(Segments {
+            pending: BytesMut::new(),
+            left: 0,
+            in_segment: false,
+            header_parsed: false,
+        });
+
+        Poll::Ready(Some(Err(DecodeError(why.to_string()).into())))
+    }
+
+    // the end of the encrypted file: the end of the stream, or of the segment
+    fn input_ended(&self) -> bool {
+        match &self.segments {
+            Some(segments) => segments.left == 0,
+            None => self.inner_ended,
+        }
+    }
+
+    // a truncated file ends with the stream
+    fn wait_for_more(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, E>>> {
+        if self.inner_ended {
+            Poll::Ready(None)
+        } else {
+            Pin::new(self).poll_next(cx)
         }
     }
 
@@ -61,6 +247,8 @@ impl<E> Decoder<E> {
         chunk_size: &usize,
         key: Key,
     ) -> Poll<Option<Result<Bytes, E>>> {
+        let input_ended = self.input_ended();
+
         match self.stream_decoder {
             None => {
                 trace!("no stream_decoder");
@@ -79,12 +267,11 @@ impl<E> Decoder<E> {
                     self.decrypt_buffer(cx)
                 } else {
                     trace!("not enough data to decrypt the header");
-                    if self.inner_ended {
+                    if self.input_ended() {
                         // TODO: throw error
                         Poll::Ready(None)
                     } else {
-                        // waiting for more data
-                        Pin::new(self).poll_next(cx)
+                        self.wait_for_more(cx)
                     }
                 }
             }
@@ -111,7 +298,7 @@ impl<E> Decoder<E> {
 
                 if !decrypted.is_empty() {
                     Poll::Ready(Some(Ok(decrypted)))
-                } else if self.inner_ended {
+                } else if input_ended {
                     trace!("inner stream over, decrypting whats left");
 
                     let decrypted = stream
@@ -123,19 +310,24 @@ impl<E> Decoder<E> {
                 } else {
                     trace!("waiting for more data");
 
-                    Pin::new(self).poll_next(cx)
+                    self.wait_for_more(cx)
                 }
             }
         }
     }
 }
 
-impl<E> Stream for Decoder<E> {
+impl<E: From<DecodeError>> Stream for Decoder<E> {
     type Item = Result<Bytes, E>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let decoder = self.get_mut();
 
+        // the segments left are decrypted without polling the ended stream
+        if decoder.inner_ended {
+            return decoder.decrypt_buffer(cx);
+        }
+
         match Pin::new(decoder.inner.as_mut()).poll_next(cx) {
             Poll::Pending => {
                 trace!("poll: not ready");
@@ -143,7 +335,10 @@ impl<E> Stream for Decoder<E> {
             }
             Poll::Ready(Some(Ok(bytes))) => {
                 trace!("poll: bytes, + {:?}", bytes.len());
-                decoder.buffer.extend(bytes);
+                match &mut decoder.segments {
+                    Some(segments) => segments.pending.extend(bytes),
+                    None => decoder.buffer.extend(bytes),
+                }
                 decoder.decrypt_buffer(cx)
             }
             Poll::Ready(None) => {
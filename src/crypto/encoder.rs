@@ -1,4 +1,5 @@
-use super::header::{Header, HEADER_SIZE};
+use super::escrow::seal_key;
+use super::header::{Header, HEADER_SIZE, HEADER_V2_SIZE, HEADER_V3_SIZE};
 use actix_web::web::{Bytes, BytesMut};
 use core::pin::Pin;
 use core::task::{Context, Poll};
@@ -6,6 +7,7 @@ use data_encoding::HEXLOWER;
 use futures_core::stream::Stream;
 use log::trace;
 use md5::{digest::DynDigest, Digest, Md5};
+use sodiumoxide::crypto::box_::PublicKey;
 use sodiumoxide::crypto::secretstream::xchacha20poly1305;
 use sodiumoxide::crypto::secretstream::xchacha20poly1305::Key;
 use sodiumoxide::crypto::secretstream::Tag;
@@ -19,6 +21,10 @@ pub struct Encoder<E> {
     key: Key,
     key_id: u64,
     md5_hasher: Box<dyn DynDigest>,
+    input_length: usize,
+    sealed_to: Option<PublicKey>,
+    // the key is ephemeral and can only be opened with the secret key
+    sealed_only: bool,
 }
 
 impl<E> Encoder<E> {
@@ -37,11 +43,48 @@ impl<E> Encoder<E> {
             key,
             key_id,
             md5_hasher: Box::new(Md5::new()),
+            input_length: 0,
+            sealed_to: None,
+            sealed_only: false,
         }
     }
 
-    pub fn input_md5(self) -> String {
-        HEXLOWER.encode(&self.md5_hasher.finalize()[..])
+    // encrypts with a new random key, only sealed in the header to the public key:
+    // the content can be decrypted with the matching secret key alone
+    pub fn new_sealed(
+        public_key: PublicKey,
+        chunk_size: usize,
+        s: Box<dyn Stream<Item = Result<Bytes, E>> + Unpin>,
+    ) -> Encoder<E> {
+        let mut encoder = Encoder::new(xchacha20poly1305::gen_key(), 0, chunk_size, s);
+        encoder.sealed_to = Some(public_key);
+        encoder.sealed_only = true;
+        encoder
+    }
+
+    // the key is also sealed in the header to the recovery public key
+    pub fn with_recovery_public_key(mut self, recovery_public_key: Option<PublicKey>) -> Self {
+        if !self.sealed_only {
+            self.sealed_to = recovery_public_key;
+        }
+        self
+    }
+
+    pub fn header_size(&self) -> usize {
+        match self.sealed_to {
+            Some(_) => HEADER_V3_SIZE,
+            None => HEADER_V2_SIZE,
+        }
+    }
+
+    // the length of the clear content read so far
+    pub fn input_length(&self) -> usize {
+        self.input_length
+    }
+
+    // the md5 of the clear content read so far
+    pub fn input_md5(&self) -> String {
+        HEXLOWER.encode(&self.md5_hasher.box_clone().finalize()[..])
     }
 
     fn encrypt_buffer(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, E>>> {
@@ -64,7 +107,15 @@ impl<E> Encoder<E> {
                     let mut buf =
                         BytesMut::with_capacity(HEADER_SIZE + encryption_header_bytes.len());
 
-                    let ds_header = Header::new(self.chunk_size, self.key_id);
+                    let ds_header = match &self.sealed_to {
+                        Some(public_key) if self.sealed_only => {
+                            Header::sealed_only(self.chunk_size, seal_key(&self.key, public_key))
+                        }
+                        Some(public_key) => Header::new(self.chunk_size, self.key_id)
+                            .with_sealed_key(seal_key(&self.key, public_key)),
+                        None => Header::new(self.chunk_size, self.key_id),
+                    };
+
                     let ds_header_bytes: Vec<u8> = ds_header.into();
                     buf.extend(&ds_header_bytes[..]);
                     buf.extend(encryption_header_bytes);
@@ -74,10 +125,12 @@ impl<E> Encoder<E> {
 
                 Some(ref mut stream) => {
                     trace!("stream encoder present !");
-                    if self.chunk_size <= self.buffer.len() {
+                    // the last chunk is held until the content ends: an error
+                    // of the content then stops the upload before it is whole
+                    if self.chunk_size < self.buffer.len() {
                         let mut encoded_buff = BytesMut::with_capacity(self.buffer.len());
 
-                        while self.chunk_size <= self.buffer.len() {
+                        while self.chunk_size < self.buffer.len() {
                             trace!("encoding a whole chunk");
 
                             let encoded_message = stream
@@ -114,6 +167,10 @@ impl<E> Stream for Encoder<E> {
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         let encoder = self.get_mut();
 
+        if encoder.inner_ended {
+            return encoder.encrypt_buffer(cx);
+        }
+
         match Pin::new(encoder.inner.as_mut()).poll_next(cx) {
             Poll::Pending => {
                 trace!("poll: not ready");
@@ -122,6 +179,7 @@ impl<E> Stream for Encoder<E> {
             Poll::Ready(Some(Ok(bytes))) => {
                 trace!("poll: bytes");
                 encoder.md5_hasher.update(&bytes);
+                encoder.input_length += bytes.len();
                 encoder.buffer.extend_from_slice(&bytes);
                 encoder.encrypt_buffer(cx)
             }
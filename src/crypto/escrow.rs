@@ -0,0 +1,82 @@
+use super::header::{self, HEADER_V2_SIZE, HEADER_V3_SIZE};
+use crate::secure_memory::zeroize;
+use sodiumoxide::crypto::box_::{PublicKey, SecretKey};
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Key, KEYBYTES};
+use std::convert::TryInto;
+
+// Key escrow: the key of each file is also sealed to an offline recovery
+// public key (X25519), so that the files can be recovered
+// with the recovery secret key if the keyring is lost.
+pub const SEALED_KEY_SIZE: usize = KEYBYTES + sealedbox::SEALBYTES;
+
+pub fn seal_key(key: &Key, recovery_public_key: &PublicKey) -> [u8; SEALED_KEY_SIZE] {
+    sealedbox::seal(&key.0, recovery_public_key)
+        .try_into()
+        .unwrap()
+}
+
+// returns the key id and the key of an encrypted file from its header
+pub fn recover_key(encrypted: &[u8], recovery_secret_key: &SecretKey) -> Option<(u64, Key)> {
+    if encrypted.len() < HEADER_V3_SIZE || &encrypted[..header::PREFIX_SIZE] != header::PREFIX {
+        return None;
+    }
+
+    let version = usize::from_le_bytes(
+        encrypted[header::PREFIX_SIZE..header::PREFIX_SIZE + header::VERSION_NB_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+
+    if version != header::VERSION_WITH_SEALED_KEY_NB && version != header::VERSION_SEALED_ONLY_NB {
+        return None;
+    }
+
+    let key_id = u64::from_le_bytes(
+        encrypted[header::HEADER_SIZE..HEADER_V2_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+
+    open_sealed_key(
+        &encrypted[HEADER_V2_SIZE..HEADER_V3_SIZE],
+        recovery_secret_key,
+    )
+    .map(|key| (key_id, key))
+}
+
+pub fn open_sealed_key(sealed_key: &[u8], secret_key: &SecretKey) -> Option<Key> {
+    let mut key = sealedbox::open(sealed_key, &secret_key.public_key(), secret_key).ok()?;
+    let opened = Key::from_slice(&key);
+    zeroize(&mut key);
+
+    opened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::header::Header;
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn seal_and_recover_a_key() {
+        let (public_key, secret_key) = box_::gen_keypair();
+        let (_, other_secret_key) = box_::gen_keypair();
+        let key = Key([7; KEYBYTES]);
+
+        let header: Vec<u8> = Header::new(16, 42)
+            .with_sealed_key(seal_key(&key, &public_key))
+            .into();
+
+        assert_eq!(HEADER_V3_SIZE, header.len());
+        assert_eq!(Some((42, key.clone())), recover_key(&header, &secret_key));
+        assert_eq!(None, recover_key(&header, &other_secret_key));
+
+        let header_v2: Vec<u8> = Header::new(16, 42).into();
+        assert_eq!(None, recover_key(&header_v2, &secret_key));
+
+        let header_v4: Vec<u8> = Header::sealed_only(16, seal_key(&key, &public_key)).into();
+        assert_eq!(Some((0, key)), recover_key(&header_v4, &secret_key));
+    }
+}
@@ -1,17 +1,31 @@
+use super::escrow::SEALED_KEY_SIZE;
+
 pub const PREFIX: &[u8] = b"J'apercus l'audacieux capitaine.";
 pub const PREFIX_SIZE: usize = 32;
 const VERSION_NB: usize = 2;
+// the version 3 adds the file key sealed to a recovery public key
+pub const VERSION_WITH_SEALED_KEY_NB: usize = 3;
+// the version 4 has the same layout, but the file key is ephemeral:
+// it is only sealed to a public key and belongs to no keyring
+pub const VERSION_SEALED_ONLY_NB: usize = 4;
+// the version 5 starts a segment: the length of the encrypted file following it
+// takes the place of the chunk size. The parts of a multipart upload are
+// segments, concatenated by the upstream.
+pub const VERSION_SEGMENT_NB: usize = 5;
 pub const VERSION_NB_SIZE: usize = 8;
 const CHUNK_SIZE_SIZE: usize = 8; //usize size
 const KEY_ID_SIZE: usize = 8; //u64 size
 pub const HEADER_SIZE: usize = PREFIX_SIZE + VERSION_NB_SIZE + CHUNK_SIZE_SIZE;
 pub const HEADER_V2_SIZE: usize = HEADER_SIZE + KEY_ID_SIZE;
+pub const HEADER_V3_SIZE: usize = HEADER_V2_SIZE + SEALED_KEY_SIZE;
+pub const SEGMENT_HEADER_SIZE: usize = HEADER_SIZE;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct Header {
     version: usize,
     pub chunk_size: usize,
     pub key_id: u64,
+    pub sealed_key: Option<[u8; SEALED_KEY_SIZE]>,
 }
 
 impl Header {
@@ -20,6 +34,29 @@ impl Header {
             version: VERSION_NB,
             chunk_size,
             key_id,
+            sealed_key: None,
+        }
+    }
+
+    pub fn with_sealed_key(mut self, sealed_key: [u8; SEALED_KEY_SIZE]) -> Header {
+        self.version = VERSION_WITH_SEALED_KEY_NB;
+        self.sealed_key = Some(sealed_key);
+        self
+    }
+
+    pub fn sealed_only(chunk_size: usize, sealed_key: [u8; SEALED_KEY_SIZE]) -> Header {
+        Header {
+            version: VERSION_SEALED_ONLY_NB,
+            chunk_size,
+            key_id: 0,
+            sealed_key: Some(sealed_key),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self.sealed_key {
+            Some(_) => HEADER_V3_SIZE,
+            None => HEADER_V2_SIZE,
         }
     }
 }
@@ -28,10 +65,20 @@ impl From<Header> for Vec<u8> {
     fn from(header: Header) -> Vec<u8> {
         [
             PREFIX,
-            &2_usize.to_le_bytes(),
+            &header.version.to_le_bytes(),
             &header.chunk_size.to_le_bytes(),
             &header.key_id.to_le_bytes(),
+            header.sealed_key.as_ref().map_or(&[], |k| &k[..]),
         ]
         .concat()
     }
 }
+
+pub fn segment_header(encrypted_length: usize) -> Vec<u8> {
+    [
+        PREFIX,
+        &VERSION_SEGMENT_NB.to_le_bytes(),
+        &encrypted_length.to_le_bytes(),
+    ]
+    .concat()
+}
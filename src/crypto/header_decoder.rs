@@ -23,63 +23,121 @@ impl<E> HeaderDecoder<'_, E> {
     }
 
     pub fn parse_header(&mut self) -> ParseHeaderResponse {
-        if self.buffer.len() < header::HEADER_SIZE {
-            return ParseHeaderResponse::MissingBytes;
-        }
+        parse_header(&mut self.buffer)
+    }
+}
 
-        if &self.buffer[..header::PREFIX_SIZE] != header::PREFIX {
-            return ParseHeaderResponse::DecipherType(DecipherType::Plaintext);
-        }
+// the header is removed from the buffer once parsed, but for a multipart
+// upload, whose segments are parsed by the decoder
+pub fn parse_header(buffer: &mut BytesMut) -> ParseHeaderResponse {
+    if buffer.len() < header::HEADER_SIZE {
+        return ParseHeaderResponse::MissingBytes;
+    }
 
-        let version = usize::from_le_bytes(
-            self.buffer[header::PREFIX_SIZE..header::PREFIX_SIZE + header::VERSION_NB_SIZE]
-                .try_into()
-                .unwrap(),
-        );
+    if &buffer[..header::PREFIX_SIZE] != header::PREFIX {
+        return ParseHeaderResponse::DecipherType(DecipherType::Plaintext);
+    }
 
-        let chunk_size = usize::from_le_bytes(
-            self.buffer[header::PREFIX_SIZE + header::VERSION_NB_SIZE..header::HEADER_SIZE]
-                .try_into()
-                .unwrap(),
-        );
+    let version = usize::from_le_bytes(
+        buffer[header::PREFIX_SIZE..header::PREFIX_SIZE + header::VERSION_NB_SIZE]
+            .try_into()
+            .unwrap(),
+    );
 
-        if version == 1 {
-            let _ = self.buffer.split_to(header::HEADER_SIZE);
-            trace!(
-                "header version: {:?}, chunk_size: {:?}, key_id: {:?}",
-                version,
-                chunk_size,
-                0
-            );
-            return ParseHeaderResponse::DecipherType(DecipherType::Encrypted {
-                chunk_size,
-                key_id: 0,
-                header_size: header::HEADER_SIZE,
-            });
-        } else if self.buffer.len() < header::HEADER_V2_SIZE {
-            return ParseHeaderResponse::MissingBytes;
-        }
+    if version == header::VERSION_SEGMENT_NB {
+        return ParseHeaderResponse::DecipherType(DecipherType::Multipart);
+    }
 
-        let key_id = u64::from_le_bytes(
-            self.buffer[header::HEADER_SIZE..header::HEADER_V2_SIZE]
-                .try_into()
-                .unwrap(),
-        );
+    let chunk_size = usize::from_le_bytes(
+        buffer[header::PREFIX_SIZE + header::VERSION_NB_SIZE..header::HEADER_SIZE]
+            .try_into()
+            .unwrap(),
+    );
 
+    if version == 1 {
+        let _ = buffer.split_to(header::HEADER_SIZE);
         trace!(
             "header version: {:?}, chunk_size: {:?}, key_id: {:?}",
             version,
             chunk_size,
-            key_id
+            0
         );
+        return ParseHeaderResponse::DecipherType(DecipherType::Encrypted {
+            chunk_size,
+            key_id: 0,
+            header_size: header::HEADER_SIZE,
+        });
+    } else if buffer.len() < header::HEADER_V2_SIZE {
+        return ParseHeaderResponse::MissingBytes;
+    }
+
+    let key_id = u64::from_le_bytes(
+        buffer[header::HEADER_SIZE..header::HEADER_V2_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+
+    trace!(
+        "header version: {:?}, chunk_size: {:?}, key_id: {:?}",
+        version,
+        chunk_size,
+        key_id
+    );
+
+    // the sealed key is only used to recover the file offline
+    let header_size = if version == header::VERSION_WITH_SEALED_KEY_NB
+        || version == header::VERSION_SEALED_ONLY_NB
+    {
+        header::HEADER_V3_SIZE
+    } else {
+        header::HEADER_V2_SIZE
+    };
+
+    if buffer.len() < header_size {
+        return ParseHeaderResponse::MissingBytes;
+    }
+
+    let header_bytes = buffer.split_to(header_size);
 
-        let _ = self.buffer.split_to(header::HEADER_V2_SIZE);
-        ParseHeaderResponse::DecipherType(DecipherType::Encrypted {
+    if version == header::VERSION_SEALED_ONLY_NB {
+        return ParseHeaderResponse::DecipherType(DecipherType::Sealed {
             chunk_size,
-            key_id,
-            header_size: header::HEADER_V2_SIZE,
-        })
+            sealed_key: header_bytes[header::HEADER_V2_SIZE..].try_into().unwrap(),
+            header_size,
+        });
     }
+
+    ParseHeaderResponse::DecipherType(DecipherType::Encrypted {
+        chunk_size,
+        key_id,
+        header_size,
+    })
+}
+
+// the length of the encrypted file following a segment header, which is removed
+pub fn parse_segment_header(buffer: &mut BytesMut) -> Option<usize> {
+    if buffer.len() < header::SEGMENT_HEADER_SIZE
+        || &buffer[..header::PREFIX_SIZE] != header::PREFIX
+    {
+        return None;
+    }
+
+    let header_bytes = buffer.split_to(header::SEGMENT_HEADER_SIZE);
+    let version = usize::from_le_bytes(
+        header_bytes[header::PREFIX_SIZE..header::PREFIX_SIZE + header::VERSION_NB_SIZE]
+            .try_into()
+            .unwrap(),
+    );
+
+    if version != header::VERSION_SEGMENT_NB {
+        return None;
+    }
+
+    Some(usize::from_le_bytes(
+        header_bytes[header::PREFIX_SIZE + header::VERSION_NB_SIZE..]
+            .try_into()
+            .unwrap(),
+    ))
 }
 
 impl<E> Future for HeaderDecoder<'_, E>
@@ -180,6 +238,49 @@ mod tests {
             decoder.parse_header()
         );
         assert_eq!(empty, decoder.buffer[..]);
+
+        let header_bytes_3: Vec<u8> = Header::new(13, 15)
+            .with_sealed_key([1; crate::crypto::escrow::SEALED_KEY_SIZE])
+            .into();
+        let mut decoder = build_decoder(&header_bytes_3[..header::HEADER_V2_SIZE]);
+        assert_eq!(ParseHeaderResponse::MissingBytes, decoder.parse_header());
+
+        let mut decoder = build_decoder(&header_bytes_3);
+        assert_eq!(
+            ParseHeaderResponse::DecipherType(DecipherType::Encrypted {
+                chunk_size: 13,
+                key_id: 15,
+                header_size: header::HEADER_V3_SIZE
+            }),
+            decoder.parse_header()
+        );
+        assert_eq!(empty, decoder.buffer[..]);
+
+        let header_bytes_4: Vec<u8> =
+            Header::sealed_only(13, [1; crate::crypto::escrow::SEALED_KEY_SIZE]).into();
+        let mut decoder = build_decoder(&header_bytes_4);
+        assert_eq!(
+            ParseHeaderResponse::DecipherType(DecipherType::Sealed {
+                chunk_size: 13,
+                sealed_key: [1; crate::crypto::escrow::SEALED_KEY_SIZE],
+                header_size: header::HEADER_V3_SIZE
+            }),
+            decoder.parse_header()
+        );
+        assert_eq!(empty, decoder.buffer[..]);
+
+        // the segment header is read by the decoder, with the segment
+        let segment = [header::segment_header(42), header_bytes_2.clone()].concat();
+        let mut decoder = build_decoder(&segment);
+        assert_eq!(
+            ParseHeaderResponse::DecipherType(DecipherType::Multipart),
+            decoder.parse_header()
+        );
+        assert_eq!(segment, decoder.buffer[..]);
+
+        assert_eq!(Some(42), parse_segment_header(&mut decoder.buffer));
+        assert_eq!(header_bytes_2, decoder.buffer[..]);
+        assert_eq!(None, parse_segment_header(&mut decoder.buffer));
     }
 
     fn build_decoder(slice: &[u8]) -> HeaderDecoder<'_, String> {
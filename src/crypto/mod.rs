@@ -1,19 +1,30 @@
 mod decipher_type;
 mod decoder;
 mod encoder;
+pub mod escrow;
 pub mod header;
 mod header_decoder;
 
-pub use self::decoder::Decoder;
+pub use self::decoder::{DecodeError, Decoder};
 pub use self::encoder::Encoder;
 pub use self::header::Header;
 pub use self::header_decoder::HeaderDecoder;
 
+use actix_web::web::BytesMut;
 use decipher_type::DecipherType;
 use header::*;
+use header_decoder::{parse_header, parse_segment_header, ParseHeaderResponse};
 use sodiumoxide::crypto::secretstream::xchacha20poly1305::{ABYTES, HEADERBYTES};
 
 pub fn encrypted_content_length(clear_length: usize, chunk_size: usize) -> usize {
+    encrypted_content_length_with_header(clear_length, chunk_size, HEADER_V2_SIZE)
+}
+
+pub fn encrypted_content_length_with_header(
+    clear_length: usize,
+    chunk_size: usize,
+    header_size: usize,
+) -> usize {
     if clear_length == 0 {
         return 0;
     }
@@ -22,15 +33,16 @@ pub fn encrypted_content_length(clear_length: usize, chunk_size: usize) -> usize
     let remainder = clear_length % chunk_size;
 
     if remainder == 0 {
-        HEADER_V2_SIZE + HEADERBYTES + nb_chunk * (ABYTES + chunk_size)
+        header_size + HEADERBYTES + nb_chunk * (ABYTES + chunk_size)
     } else {
-        HEADER_V2_SIZE + HEADERBYTES + nb_chunk * (ABYTES + chunk_size) + ABYTES + remainder
+        header_size + HEADERBYTES + nb_chunk * (ABYTES + chunk_size) + ABYTES + remainder
     }
 }
 
-pub fn decrypted_content_length(encrypted_length: usize, decipher: DecipherType) -> usize {
+// unknown for a multipart upload, whose parts are not delimited by its length
+pub fn decrypted_content_length(encrypted_length: usize, decipher: DecipherType) -> Option<usize> {
     if encrypted_length == 0 {
-        return 0;
+        return Some(0);
     }
 
     match decipher {
@@ -38,28 +50,100 @@ pub fn decrypted_content_length(encrypted_length: usize, decipher: DecipherType)
             chunk_size,
             header_size,
             ..
-        } => {
-            // encrypted = header_ds + header_crypto + n ( abytes + chunk ) + a (abytes + remainder)
-            // with remainder < chunk and a = 0 if remainder = 0, a = 1 otherwise
-            //
-            //  encrypted - header_ds - header_crypto = n ( abytes + chunk ) + a (abytes + remainder)
-            //
-            //  integer_part ((encrypted - header_ds - header_crypto) / ( abytes + chunk ))
-            //    = integer_part ( n + a (abytes + remainder) / (abytes + chunk) )
-            //    = n
-
-            let nb_chunk = (encrypted_length - header_size - HEADERBYTES) / (ABYTES + chunk_size);
-            let remainder_exists =
-                !(encrypted_length - header_size - HEADERBYTES).is_multiple_of(ABYTES + chunk_size);
-
-            if remainder_exists {
-                encrypted_length - header_size - HEADERBYTES - (nb_chunk + 1) * ABYTES
-            } else {
-                encrypted_length - header_size - HEADERBYTES - nb_chunk * ABYTES
-            }
         }
+        | DecipherType::Sealed {
+            chunk_size,
+            header_size,
+            ..
+        } => decrypted_content_length_with_header(encrypted_length, chunk_size, header_size),
+
+        DecipherType::Multipart => None,
+
+        DecipherType::Plaintext => Some(encrypted_length),
+    }
+}
 
-        DecipherType::Plaintext => encrypted_length,
+// none when the length is too short for an encrypted file
+pub fn decrypted_content_length_with_header(
+    encrypted_length: usize,
+    chunk_size: usize,
+    header_size: usize,
+) -> Option<usize> {
+    if encrypted_length == 0 {
+        return Some(0);
+    }
+
+    if encrypted_length <= header_size + HEADERBYTES {
+        return None;
+    }
+
+    // encrypted = header_ds + header_crypto + n ( abytes + chunk ) + a (abytes + remainder)
+    // with remainder < chunk and a = 0 if remainder = 0, a = 1 otherwise
+    //
+    //  encrypted - header_ds - header_crypto = n ( abytes + chunk ) + a (abytes + remainder)
+    //
+    //  integer_part ((encrypted - header_ds - header_crypto) / ( abytes + chunk ))
+    //    = integer_part ( n + a (abytes + remainder) / (abytes + chunk) )
+    //    = n
+
+    let nb_chunk = (encrypted_length - header_size - HEADERBYTES) / (ABYTES + chunk_size);
+    let remainder_exists =
+        !(encrypted_length - header_size - HEADERBYTES).is_multiple_of(ABYTES + chunk_size);
+
+    if remainder_exists {
+        (encrypted_length - header_size - HEADERBYTES).checked_sub((nb_chunk + 1) * ABYTES)
+    } else {
+        Some(encrypted_length - header_size - HEADERBYTES - nb_chunk * ABYTES)
+    }
+}
+
+// enough to read a segment header and the header of the file following it
+pub const CLEAR_LENGTH_PROBE_SIZE: usize = SEGMENT_HEADER_SIZE + HEADER_V3_SIZE;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClearLength {
+    File(usize),
+    // the next segment of a multipart upload
+    Segment {
+        encrypted_length: usize,
+        clear_length: usize,
+    },
+}
+
+// The clear length of what spans `length` bytes from `first_bytes`, which hold
+// at least `CLEAR_LENGTH_PROBE_SIZE` bytes unless `length` is shorter.
+pub fn clear_length(first_bytes: &[u8], length: usize) -> Option<ClearLength> {
+    let mut buffer = BytesMut::from(first_bytes);
+
+    match parse_header(&mut buffer) {
+        // shorter than a header, so in clear
+        ParseHeaderResponse::MissingBytes if first_bytes.len() >= length => {
+            Some(ClearLength::File(length))
+        }
+        ParseHeaderResponse::MissingBytes => None,
+
+        ParseHeaderResponse::DecipherType(DecipherType::Multipart) => {
+            let encrypted_length = parse_segment_header(&mut buffer)?;
+
+            let clear_length = match parse_header(&mut buffer) {
+                ParseHeaderResponse::DecipherType(DecipherType::Multipart) => return None,
+                ParseHeaderResponse::DecipherType(decipher) => {
+                    decrypted_content_length(encrypted_length, decipher)?
+                }
+                // an empty part
+                ParseHeaderResponse::MissingBytes if encrypted_length == 0 => 0,
+                ParseHeaderResponse::MissingBytes => return None,
+            };
+
+            Some(ClearLength::Segment {
+                encrypted_length: SEGMENT_HEADER_SIZE + encrypted_length,
+                clear_length,
+            })
+        }
+
+        ParseHeaderResponse::DecipherType(decipher) => {
+            decrypted_content_length(length, decipher).map(ClearLength::File)
+        }
     }
 }
 
@@ -82,7 +166,7 @@ mod tests {
             },
         );
 
-        assert_eq!(original_length, decrypted_length);
+        assert_eq!(Some(original_length), decrypted_length);
     }
 
     #[test]
@@ -101,7 +185,7 @@ mod tests {
             },
         );
 
-        assert_eq!(original_length, decrypted_length);
+        assert_eq!(Some(original_length), decrypted_length);
     }
 
     #[test]
@@ -121,7 +205,7 @@ mod tests {
             },
         );
 
-        assert_eq!(original_length, decrypted_length);
+        assert_eq!(Some(original_length), decrypted_length);
     }
 
     #[test]
@@ -138,7 +222,7 @@ mod tests {
             },
         );
 
-        assert_eq!(original_length, decrypted_length);
+        assert_eq!(Some(original_length), decrypted_length);
     }
 
     #[test]
@@ -180,6 +264,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_encrypted_content_length_with_a_sealed_key() {
+        let original_length = 32;
+        let chunk_size = 16;
+        let nb_chunk = 32 / 16;
+        let encrypted_length = HEADER_V3_SIZE + HEADERBYTES + nb_chunk * (ABYTES + chunk_size);
+
+        assert_eq!(
+            encrypted_length,
+            encrypted_content_length_with_header(original_length, chunk_size, HEADER_V3_SIZE)
+        );
+    }
+
     #[test]
     fn test_encrypted_content_length_with_another_exemple() {
         let original_length = 5882;
@@ -191,4 +288,61 @@ mod tests {
             encrypted_content_length(original_length, chunk_size)
         );
     }
+
+    #[test]
+    fn test_decrypt_content_length_of_a_multipart_upload() {
+        assert_eq!(
+            None,
+            decrypted_content_length(1024, DecipherType::Multipart)
+        );
+    }
+
+    #[test]
+    fn test_decrypt_content_length_of_a_too_short_file() {
+        assert_eq!(
+            None,
+            decrypted_content_length_with_header(HEADER_V2_SIZE + HEADERBYTES, 16, HEADER_V2_SIZE)
+        );
+
+        assert_eq!(
+            Some(33),
+            decrypted_content_length_with_header(
+                encrypted_content_length(33, 16),
+                16,
+                HEADER_V2_SIZE
+            )
+        );
+    }
+
+    #[test]
+    fn test_clear_length() {
+        let encrypted_length = encrypted_content_length(33, 16);
+        let header: Vec<u8> = Header::new(16, 0).into();
+
+        assert_eq!(
+            Some(ClearLength::File(33)),
+            clear_length(&header, encrypted_length)
+        );
+
+        let segment = [segment_header(encrypted_length), header].concat();
+
+        assert_eq!(
+            Some(ClearLength::Segment {
+                encrypted_length: SEGMENT_HEADER_SIZE + encrypted_length,
+                clear_length: 33
+            }),
+            clear_length(&segment, 10 * encrypted_length)
+        );
+
+        assert_eq!(
+            Some(ClearLength::Segment {
+                encrypted_length: SEGMENT_HEADER_SIZE,
+                clear_length: 0
+            }),
+            clear_length(&segment_header(0), 10 * encrypted_length)
+        );
+
+        assert_eq!(Some(ClearLength::File(5)), clear_length(b"clear", 5));
+        assert_eq!(None, clear_length(&segment[..20], encrypted_length));
+    }
 }
@@ -1,9 +1,17 @@
 use super::config::*;
+use super::crypto::escrow::recover_key;
 use super::crypto::*;
+use super::keyring::Keyring;
 use actix_web::web::{BufMut, Bytes, BytesMut};
 use actix_web::Error;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use futures::executor::block_on;
 use futures::executor::block_on_stream;
+use sodiumoxide::crypto::box_;
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 
 pub fn encrypt(config: EncryptConfig) {
     let input: Vec<u8> = std::fs::read(config.input_file).unwrap();
@@ -16,7 +24,8 @@ pub fn encrypt(config: EncryptConfig) {
         .get_last_key()
         .expect("no key avalaible for encryption");
 
-    let encoder = Encoder::new(key, key_id, config.chunk_size, Box::new(source_stream));
+    let encoder = Encoder::new(key, key_id, config.chunk_size, Box::new(source_stream))
+        .with_recovery_public_key(config.recovery_public_key);
 
     let buf = block_on_stream(encoder).map(|r| r.unwrap()).fold(
         BytesMut::with_capacity(64),
@@ -41,7 +50,8 @@ pub fn decrypt(config: DecryptConfig) {
     let (cypher_type, buff) = block_on(header_decoder);
 
     let decoder =
-        Decoder::new_from_cypher_and_buffer(config.keyring.clone(), boxy, cypher_type, buff);
+        Decoder::new_from_cypher_and_buffer(config.keyring.clone(), boxy, cypher_type, buff)
+            .with_secret_key(config.decryption_secret_key);
 
     let buf = block_on_stream(decoder).map(|r| r.unwrap()).fold(
         BytesMut::with_capacity(64),
@@ -53,3 +63,36 @@ pub fn decrypt(config: DecryptConfig) {
 
     std::fs::write(config.output_file, &buf[..]).unwrap();
 }
+
+pub fn generate_key_pair(config: GenerateKeyPairConfig) {
+    let (public_key, secret_key) = box_::gen_keypair();
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&config.secret_key_file)
+        .and_then(|mut f| f.write_all(STANDARD.encode(secret_key.as_ref()).as_bytes()))
+        .unwrap_or_else(|why| panic!("couldn't write {}: {}", config.secret_key_file, why));
+
+    println!("{}", STANDARD.encode(public_key.as_ref()));
+}
+
+// decrypts a file without the keyring, from the key sealed in its header
+pub fn recover(config: RecoverConfig) {
+    let input: Vec<u8> = std::fs::read(&config.input_file).unwrap();
+
+    let (key_id, key) = recover_key(&input, &config.recovery_secret_key).unwrap_or_else(|| {
+        panic!(
+            "no key sealed to this recovery key in {}",
+            config.input_file
+        )
+    });
+
+    decrypt(DecryptConfig {
+        keyring: Keyring::new(HashMap::from([(key_id, key)])),
+        decryption_secret_key: Some(config.recovery_secret_key),
+        input_file: config.input_file,
+        output_file: config.output_file,
+    })
+}
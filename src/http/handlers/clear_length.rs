@@ -0,0 +1,132 @@
+use super::*;
+use actix_web::http::Method;
+use futures::StreamExt;
+
+// the clear length of a multipart object is recorded on its completion:
+// an unknown one is only probed up to this number of segments
+const MAX_PROBED_SEGMENTS: usize = 32;
+
+// Without metadata, the clear length is computed from the headers of the file,
+// read with a ranged request: one per segment for a multipart upload.
+// An object with too many segments is refused rather than probed.
+pub async fn probe_clear_length(
+    clients: &UpstreamClients,
+    upstream: &Upstream,
+    url: &str,
+    req: &HttpRequest,
+) -> Result<Option<usize>, Error> {
+    let mut offset = 0;
+    let mut total_clear_length = 0;
+
+    for _ in 0..MAX_PROBED_SEGMENTS {
+        let Some((first_bytes, length)) = read_range(clients, upstream, url, req, offset).await
+        else {
+            return Ok(None);
+        };
+
+        if length == 0 {
+            return Ok(Some(0));
+        }
+
+        let Some(probed) = length
+            .checked_sub(offset)
+            .and_then(|rest| clear_length(&first_bytes, rest))
+        else {
+            return Ok(None);
+        };
+
+        match probed {
+            ClearLength::File(clear_length) if offset == 0 => return Ok(Some(clear_length)),
+            ClearLength::File(_) => return Ok(None),
+            ClearLength::Segment {
+                encrypted_length,
+                clear_length,
+            } => {
+                total_clear_length += clear_length;
+                offset += encrypted_length;
+
+                if offset >= length {
+                    return Ok(Some(total_clear_length));
+                }
+            }
+        }
+    }
+
+    error!(
+        "the clear length of {} is unknown and it holds more than {} segments",
+        url, MAX_PROBED_SEGMENTS
+    );
+
+    Err(actix_web::error::ErrorBadGateway(
+        "the clear length of the object is unknown",
+    ))
+}
+
+// the first bytes from the offset, and the length of the object
+async fn read_range(
+    clients: &UpstreamClients,
+    upstream: &Upstream,
+    url: &str,
+    req: &HttpRequest,
+    offset: usize,
+) -> Option<(web::BytesMut, usize)> {
+    let range = format!("bytes={}-{}", offset, offset + CLEAR_LENGTH_PROBE_SIZE - 1);
+
+    let res = send_with_retries(upstream, url, |url| {
+        let mut probe_req = clients
+            .request_from(upstream, url, req.head())
+            .method(Method::GET);
+
+        for header in FETCH_REQUEST_HEADERS_TO_REMOVE
+            .iter()
+            .chain(&CONDITIONAL_REQUEST_HEADERS)
+        {
+            probe_req.headers_mut().remove(header);
+        }
+
+        sign_if_needed(
+            probe_req.insert_header((header::RANGE, range.clone())),
+            upstream,
+        )
+        .send()
+    })
+    .await;
+
+    let mut res = match res {
+        Ok(res) => res,
+        Err(e) => {
+            warn!("unable to read the header of {}: {}", url, e);
+            return None;
+        }
+    };
+
+    // bytes 0-99/1000, or bytes */1000 when the range starts after the end
+    let range_length = res
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.rsplit('/').next())
+        .and_then(|length| length.parse().ok());
+
+    let length = match res.status().as_u16() {
+        206 | 416 => range_length?,
+        // the range is ignored by the upstream
+        200 => content_length(res.headers())?,
+        _ => {
+            warn!("unable to read the header of {}: {}", url, res.status());
+            return None;
+        }
+    };
+
+    let mut first_bytes = web::BytesMut::new();
+
+    while first_bytes.len() < CLEAR_LENGTH_PROBE_SIZE {
+        match res.next().await {
+            Some(Ok(bytes)) => first_bytes.extend_from_slice(&bytes),
+            Some(Err(_)) => return None,
+            None => break,
+        }
+    }
+
+    Some((first_bytes, length))
+}
@@ -9,12 +9,10 @@ pub async fn encrypt_to_file(
 ) -> HttpResponse {
     let filepath = config.local_encryption_path_for(&req).unwrap();
 
-    let (id, key) = config
-        .keyring
-        .get_last_key()
-        .expect("no key avalaible for encryption");
+    let name = req.match_info().get("name").unwrap_or_default();
 
-    let mut encrypted_stream = Encoder::new(key, id, config.chunk_size, Box::new(payload));
+    let mut encrypted_stream =
+        encoder_for(&config, config.keyring_for_path(name), Box::new(payload));
 
     log::info!("Encrypting to file: {}", filepath.display());
 
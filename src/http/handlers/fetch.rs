@@ -1,13 +1,23 @@
+use super::clear_length::probe_clear_length;
+use super::metadata::{clear_etag, clear_length};
 use super::*;
+use crate::crypto::header::{HEADER_V2_SIZE, HEADER_V3_SIZE};
+use crate::http::utils::conditional::range_applies;
+use crate::http::utils::known_objects::KnownObjects;
+use crate::http::utils::listing::Listing;
 use crate::http::utils::{aws_helper::sign_request, partial_extractor::*};
 use actix_files::HttpRange;
 use actix_web::web::Bytes;
 
+// a listing holds at most 10 000 objects
+const MAX_LISTING_SIZE: usize = 16 * 1024 * 1024;
+
 pub async fn fetch(
     req: HttpRequest,
     body: web::Bytes,
-    client: web::Data<Client>,
+    clients: web::Data<UpstreamClients>,
     config: web::Data<HttpConfig>,
+    known_objects: web::Data<KnownObjects>,
 ) -> Result<HttpResponse, Error> {
     let get_url = config.create_upstream_url(&req);
 
@@ -17,26 +27,43 @@ pub async fn fetch(
 
     let get_url = get_url.unwrap();
 
-    let mut fetch_req = client
-        .request_from(get_url.clone(), req.head())
-        .force_close();
+    let upstream = config.upstream_for(&req);
+
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
+    }
 
-    let raw_range = req
+    let mut raw_range = req
         .headers()
         .get(header::RANGE)
         .and_then(|l| l.to_str().ok());
 
-    for header in &FETCH_REQUEST_HEADERS_TO_REMOVE {
-        fetch_req.headers_mut().remove(header);
-    }
+    let listing = Listing::requested(&req);
 
-    let req_to_send = if let Some(aws_config) = config.aws_config.clone() {
-        sign_request(fetch_req, aws_config)
-    } else {
-        fetch_req
-    };
+    let res = send_with_retries(upstream, &get_url, |url| {
+        let mut fetch_req = clients.request_from(upstream, url, req.head());
+
+        for header in &FETCH_REQUEST_HEADERS_TO_REMOVE {
+            fetch_req.headers_mut().remove(header);
+        }
 
-    let res = req_to_send.send_body(body).await.map_err(|e| {
+        // the preconditions on an object are evaluated on its clear etag
+        if listing.is_none() {
+            for header in &CONDITIONAL_REQUEST_HEADERS {
+                fetch_req.headers_mut().remove(header);
+            }
+        }
+
+        let req_to_send = if let Some(aws_config) = upstream.aws_config.clone() {
+            sign_request(fetch_req, aws_config)
+        } else {
+            fetch_req
+        };
+
+        req_to_send.send_body(body.clone())
+    })
+    .await
+    .map_err(|e| {
         error!("fetch error {:?}, {:?}", e, req);
         match e {
             awc::error::SendRequestError::Timeout => actix_web::error::ErrorGatewayTimeout(e),
@@ -60,16 +87,65 @@ pub async fn fetch(
         client_resp.append_header(header);
     }
 
-    let original_length = content_length(res.headers());
+    // an object read with the query of a listing is decrypted
+    let listing = listing.filter(|listing| listing.answered_by(res.headers()));
+
+    match listing {
+        Some(listing) if res.status().is_success() => {
+            let mut res = res;
+            let body = res.body().limit(MAX_LISTING_SIZE).await?;
+
+            let rewritten = listing.rewrite(&body, |size, upstream_etag| {
+                clear_object(&config, &known_objects, size, upstream_etag)
+            });
+
+            return Ok(client_resp.body(rewritten.map(Bytes::from).unwrap_or(body)));
+        }
+        None if res.status().is_success() => {
+            let etag = clear_etag(res.headers(), &known_objects);
+
+            if let Some(response) = unmet_precondition(&req, res.headers(), etag.as_ref()) {
+                return Ok(response);
+            }
+
+            if !range_applies(&req, etag.as_ref(), last_modified(res.headers())) {
+                raw_range = None;
+            }
+
+            if let Some(etag) = etag {
+                client_resp.insert_header(header::ETag(etag));
+            }
+        }
+        _ => (),
+    }
+
+    let upstream_length = content_length(res.headers());
+    let stored_length = clear_length(res.headers(), &known_objects);
+    let status = res.status();
 
     let mut boxy: Box<dyn Stream<Item = Result<Bytes, _>> + Unpin> = Box::new(res);
     let header_decoder = HeaderDecoder::new(&mut boxy);
     let (cypher_type, buff) = header_decoder.await;
-    let fetch_length =
-        original_length.map(|content_length| decrypted_content_length(content_length, cypher_type));
+    let fetch_length = upstream_length
+        .and_then(|content_length| decrypted_content_length(content_length, cypher_type));
+
+    // a chunked response or a multipart upload: the segments are
+    // probed only when the proxy does not know the clear length
+    let fetch_length = match fetch_length {
+        None if status.is_success() => match stored_length {
+            Some(length) => Some(length),
+            None => probe_clear_length(&clients, upstream, &get_url, &req).await?,
+        },
+        fetch_length => fetch_length,
+    };
 
-    let decoder =
-        Decoder::new_from_cypher_and_buffer(config.keyring.clone(), boxy, cypher_type, buff);
+    let decoder = Decoder::new_from_cypher_and_buffer(
+        config.keyring_for(upstream, &get_url).clone(),
+        boxy,
+        cypher_type,
+        buff,
+    )
+    .with_secret_key(config.decryption_secret_key.clone());
 
     if let Some(length) = fetch_length {
         use std::convert::TryInto;
@@ -85,10 +161,12 @@ pub async fn fetch(
 
                 let pe = PartialExtractor::new(Box::new(decoder), range_start, range_end);
 
-                client_resp.append_header((
-                    header::CONTENT_RANGE,
-                    format!("bytes {}-{}/{}", range_start, range_end, length),
-                ));
+                client_resp
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .append_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", range_start, range_end, length),
+                    ));
 
                 Ok(client_resp.no_chunking(r.length).streaming(pe))
             }
@@ -98,3 +176,34 @@ pub async fn fetch(
         Ok(client_resp.streaming(decoder))
     }
 }
+
+// The clear size of an object is only an estimate, unless the object is known:
+// a listing does not give the header of the objects, the size is computed with
+// the chunk size and header of the current configuration, not the ones the object
+// was written with. The segments of a multipart object have unknown sizes, and
+// the etag stays the one of the encrypted content.
+fn clear_object(
+    config: &HttpConfig,
+    known_objects: &KnownObjects,
+    size: usize,
+    upstream_etag: &str,
+) -> (usize, String) {
+    if let Some(known) = known_objects.get(upstream_etag) {
+        return (known.size, known.etag);
+    }
+
+    let header_size =
+        if config.encryption_public_key.is_some() || config.recovery_public_key.is_some() {
+            HEADER_V3_SIZE
+        } else {
+            HEADER_V2_SIZE
+        };
+
+    let size = if upstream_etag.contains('-') {
+        size
+    } else {
+        decrypted_content_length_with_header(size, config.chunk_size, header_size).unwrap_or(size)
+    };
+
+    (size, upstream_etag.to_string())
+}
@@ -1,13 +1,18 @@
-use crate::http::utils::{aws_helper::sign_request, memory_or_file_buffer::MemoryOrFileBuffer};
+use crate::http::utils::aws_chunked::{decoded_content_length, is_aws_chunked};
+use crate::http::utils::aws_helper::{copy_source, sign_request};
+use crate::http::utils::known_objects::{KnownObject, KnownObjects};
+use crate::http::utils::memory_or_file_buffer::MemoryOrFileBuffer;
+use crate::http::utils::xml::unquote;
 
+use super::metadata::{metadata_headers, MetadataHeaders};
 use super::*;
 use actix_web::body::SizedStream;
-use futures::StreamExt;
-use std::time::Duration;
+use actix_web::http::Method;
+use futures::{stream, StreamExt};
+use std::cell::RefCell;
+use std::rc::Rc;
 
-const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60 * 60);
-
-static FORWARD_REQUEST_HEADERS_TO_REMOVE: [header::HeaderName; 4] = [
+pub static FORWARD_REQUEST_HEADERS_TO_REMOVE: [header::HeaderName; 4] = [
     // Connection settings (keepalived) must not be resend
     header::CONNECTION,
     // Encryption changes the length of the content
@@ -20,6 +25,9 @@ static FORWARD_REQUEST_HEADERS_TO_REMOVE: [header::HeaderName; 4] = [
     header::EXPECT,
 ];
 
+// the largest object s3 copies in one request
+const MAX_COPY_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
 static FORWARD_RESPONSE_HEADERS_TO_REMOVE: [header::HeaderName; 1] = [
     // Connection settings (keepalived) must not be resend
     header::CONNECTION,
@@ -28,77 +36,164 @@ static FORWARD_RESPONSE_HEADERS_TO_REMOVE: [header::HeaderName; 1] = [
 pub async fn forward(
     req: HttpRequest,
     payload: web::Payload,
-    client: web::Data<Client>,
+    clients: web::Data<UpstreamClients>,
     config: web::Data<HttpConfig>,
+    known_objects: web::Data<KnownObjects>,
 ) -> Result<HttpResponse, Error> {
     let Some(put_url) = config.create_upstream_url(&req) else {
         return not_found();
     };
 
-    let mut forwarded_req = client
-        .request_from(put_url.clone(), req.head())
-        .force_close()
-        .timeout(UPLOAD_TIMEOUT);
+    let upstream = config.upstream_for(&req);
+
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
+    }
 
-    if let Some(length) = content_length(req.headers()) {
-        if config.aws_config.is_some() {
+    let metadata = metadata_headers(upstream, &req);
+
+    // the length of an aws-chunked upload includes its frames
+    let clear_length = if is_aws_chunked(req.headers()) {
+        decoded_content_length(req.headers())
+    } else {
+        content_length(req.headers())
+    };
+
+    // the clear etag is known before sending only when the upload is spooled
+    let build = |url: String, original_etag: Option<&str>| {
+        let mut forwarded_req = clients
+            .request_from(upstream, url, req.head())
+            .timeout(upstream.upload_timeout);
+
+        if let (Some(length), Some(metadata)) = (clear_length, metadata) {
             log::info!(
-                "Adding x-amz-meta-original-content-length header with length {}",
+                "Adding {} header with length {}",
+                metadata.original_length,
                 length
             );
-            forwarded_req = forwarded_req
-                .insert_header(("x-amz-meta-original-content-length", length.to_string()));
+            forwarded_req =
+                forwarded_req.insert_header((metadata.original_length, length.to_string()));
         }
-    }
 
-    let forward_length: Option<usize> = content_length(req.headers())
-        .map(|content_length| encrypted_content_length(content_length, config.chunk_size));
+        if let (Some(etag), Some(metadata)) = (original_etag, metadata) {
+            forwarded_req = forwarded_req.insert_header((metadata.original_etag, etag));
+        }
 
-    for header in &FORWARD_REQUEST_HEADERS_TO_REMOVE {
-        forwarded_req.headers_mut().remove(header);
-    }
+        for header in &FORWARD_REQUEST_HEADERS_TO_REMOVE {
+            forwarded_req.headers_mut().remove(header);
+        }
+        remove_checksums(&mut forwarded_req);
+        remove_aws_chunked(&mut forwarded_req);
 
-    let (key_id, key) = config
-        .keyring
-        .get_last_key()
-        .expect("no key avalaible for encryption");
+        forwarded_req
+    };
+
+    let (payload, verification) = clear_payload(&req, payload, upstream);
+    let mut encrypted_stream =
+        encoder_for(&config, config.keyring_for(upstream, &put_url), payload);
 
-    let mut encrypted_stream = Encoder::new(key, key_id, config.chunk_size, Box::new(payload));
+    let forward_length: Option<usize> = clear_length.map(|content_length| {
+        encrypted_content_length_with_header(
+            content_length,
+            config.chunk_size,
+            encrypted_stream.header_size(),
+        )
+    });
 
     let cloned_req = req.clone();
 
-    let mut input_etag: Option<String> = None;
+    let mut clear_object: Option<KnownObject> = None;
+
+    // s3 needs the length of the upload before it is sent, and only takes
+    // its clear etag with it: unless enabled, the upload is spooled, which
+    // also lets it be retried. The copy adding the etag is limited in size.
+    let streamed = config.stream_uploads
+        && forward_length
+            .is_some_and(|length| !config.copy_streamed_metadata || length <= MAX_COPY_SIZE);
+
+    let res_e = match upstream.aws_config.clone() {
+        Some(aws_config) if !streamed => {
+            let filepath = config.local_encryption_path_for(&req).unwrap();
+            let mut buffer = MemoryOrFileBuffer::new(filepath, config.max_in_memory_file_size);
+
+            while let Ok(Some(v)) = encrypted_stream.try_next().await {
+                buffer.append(v).await;
+            }
+
+            if let Some(rejection) = verification.rejection() {
+                return Ok(rejected(rejection));
+            }
+
+            let (_output_sha256, length) = buffer.sha256_and_len();
+            let size = encrypted_stream.input_length();
+            let etag = encrypted_stream.input_md5();
 
-    let res_e = if let Some(aws_config) = config.aws_config.clone() {
-        let filepath = config.local_encryption_path_for(&req).unwrap();
-        let mut buffer = MemoryOrFileBuffer::new(filepath);
+            // the spooled upload is replayed on each attempt
+            let buffer = &buffer;
+            let res = send_with_retries(upstream, &put_url, |url| {
+                let signed_req = sign_request(build(url, Some(&etag)), aws_config.clone());
 
-        while let Ok(Some(v)) = encrypted_stream.try_next().await {
-            buffer.append(v).await;
+                async move {
+                    signed_req
+                        .send_body(SizedStream::new(length, buffer.as_stream().await))
+                        .await
+                }
+            })
+            .await;
+
+            clear_object = Some(KnownObject { etag, size });
+            res
         }
+        _ => {
+            // the upload is streamed, it is sent once
+            let encrypted_stream = Rc::new(RefCell::new(encrypted_stream));
+            let sent_stream = encrypted_stream.clone();
 
-        let (_output_sha256, length) = buffer.sha256_and_len();
-        input_etag = Some(encrypted_stream.input_md5());
+            let stream_to_send =
+                stream::poll_fn(move |cx| sent_stream.borrow_mut().poll_next_unpin(cx))
+                    .map_err(move |e| {
+                        error!("forward error with stream {:?}, {:?}", e, cloned_req);
+                        Error::from(e)
+                    })
+                    .boxed_local();
 
-        let stream_to_send = buffer.as_stream().await;
+            let res = send_once(upstream, &put_url, |url| {
+                let forwarded_req = sign_if_needed(build(url, None), upstream);
 
-        sign_request(forwarded_req, aws_config)
-            .send_body(SizedStream::new(length, stream_to_send))
-            .await
-    } else {
-        let stream_to_send = encrypted_stream
-            .map_err(move |e| {
-                error!("forward error with stream {:?}, {:?}", e, cloned_req);
-                Error::from(e)
+                if let Some(length) = forward_length {
+                    forwarded_req.send_body(SizedStream::new(length as u64, stream_to_send))
+                } else {
+                    forwarded_req.send_stream(stream_to_send)
+                }
             })
-            .boxed_local();
-
-        if let Some(length) = forward_length {
-            forwarded_req
-                .send_body(SizedStream::new(length as u64, stream_to_send))
-                .await
-        } else {
-            forwarded_req.send_stream(stream_to_send).await
+            .await;
+
+            // the upload was interrupted before its end
+            if let Some(rejection) = verification.rejection() {
+                return Ok(rejected(rejection));
+            }
+
+            if matches!(&res, Ok(res) if res.status().is_success()) {
+                let object = KnownObject {
+                    etag: encrypted_stream.borrow().input_md5(),
+                    size: encrypted_stream.borrow().input_length(),
+                };
+
+                // without the copy, the etag is only known by this proxy
+                match metadata {
+                    Some(metadata) if upstream.aws_config.is_none() => {
+                        post_metadata(&clients, upstream, &put_url, &req, metadata, &object).await;
+                    }
+                    Some(metadata) if config.copy_streamed_metadata => {
+                        copy_metadata(&clients, upstream, &put_url, &req, metadata, &object).await;
+                    }
+                    _ => (),
+                }
+
+                clear_object = Some(object);
+            }
+
+            res
         }
     };
 
@@ -123,9 +218,110 @@ pub async fn forward(
         client_resp.append_header(header);
     }
 
-    if let Some(etag) = input_etag {
-        client_resp.insert_header(("etag", format!("\"{}\"", etag)));
+    if res.status().is_success() {
+        for checksum in verification.response_headers() {
+            client_resp.insert_header(checksum);
+        }
+    }
+
+    if let Some(clear_object) = clear_object {
+        client_resp.insert_header(("etag", format!("\"{}\"", clear_object.etag)));
+
+        if let Some(upstream_etag) = res.headers().get(header::ETAG) {
+            known_objects.insert(
+                unquote(upstream_etag.to_str().unwrap_or_default()),
+                clear_object,
+            );
+        }
     }
 
     Ok(client_resp.body(res.body().await?))
 }
+
+// Swift takes the metadata with the headers, while the clear etag of a streamed
+// upload is only known once it is sent: it is added by a POST, which replaces
+// all the metadata of the object, so they are sent again.
+async fn post_metadata(
+    clients: &UpstreamClients,
+    upstream: &Upstream,
+    url: &str,
+    req: &HttpRequest,
+    metadata: &MetadataHeaders,
+    object: &KnownObject,
+) {
+    // a temp url is signed for the method of the upload, it would refuse the POST
+    if req.query_string().to_lowercase().contains("temp_url_sig") {
+        return;
+    }
+
+    let res = send_with_retries(upstream, url, |url| {
+        let mut post_req = clients
+            .request_from(upstream, url, req.head())
+            .method(Method::POST)
+            .insert_header((metadata.original_length, object.size.to_string()))
+            .insert_header((metadata.original_etag, object.etag.clone()));
+
+        for header in FORWARD_REQUEST_HEADERS_TO_REMOVE
+            .iter()
+            .chain(&[header::TRANSFER_ENCODING])
+            .chain(&CONDITIONAL_REQUEST_HEADERS)
+        {
+            post_req.headers_mut().remove(header);
+        }
+        remove_checksums(&mut post_req);
+        remove_aws_chunked(&mut post_req);
+
+        post_req.send()
+    })
+    .await;
+
+    match res {
+        Ok(res) if res.status().is_success() => (),
+        Ok(res) => warn!("unable to record the etag of {}: {}", url, res.status()),
+        Err(e) => warn!("unable to record the etag of {}: {}", url, e),
+    }
+}
+
+// S3 only changes the metadata of an object by copying it: a streamed upload
+// is copied on itself, with all its metadata sent again. This writes the object
+// twice, and adds a version to a versioned bucket.
+async fn copy_metadata(
+    clients: &UpstreamClients,
+    upstream: &Upstream,
+    url: &str,
+    req: &HttpRequest,
+    metadata: &MetadataHeaders,
+    object: &KnownObject,
+) {
+    let Ok(copy_source) = url::Url::parse(url).map(|url| copy_source(&url)) else {
+        return;
+    };
+
+    let res = send_with_retries(upstream, url, |url| {
+        let mut copy_req = clients
+            .request_from(upstream, url, req.head())
+            .insert_header(("x-amz-copy-source", copy_source.clone()))
+            .insert_header(("x-amz-metadata-directive", "REPLACE"))
+            .insert_header((metadata.original_length, object.size.to_string()))
+            .insert_header((metadata.original_etag, object.etag.clone()));
+
+        for header in FORWARD_REQUEST_HEADERS_TO_REMOVE
+            .iter()
+            .chain(&[header::TRANSFER_ENCODING])
+            .chain(&CONDITIONAL_REQUEST_HEADERS)
+        {
+            copy_req.headers_mut().remove(header);
+        }
+        remove_checksums(&mut copy_req);
+        remove_aws_chunked(&mut copy_req);
+
+        sign_if_needed(copy_req, upstream).send()
+    })
+    .await;
+
+    match res {
+        Ok(res) if res.status().is_success() => (),
+        Ok(res) => warn!("unable to record the etag of {}: {}", url, res.status()),
+        Err(e) => warn!("unable to record the etag of {}: {}", url, e),
+    }
+}
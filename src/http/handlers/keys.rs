@@ -0,0 +1,21 @@
+use super::*;
+
+// exposes the key fingerprints so that the keyrings of
+// several environments can be compared without revealing them
+pub async fn keys(config: web::Data<HttpConfig>) -> HttpResponse {
+    let tenants: serde_json::Map<String, serde_json::Value> = config
+        .tenants
+        .iter()
+        .map(|tenant| {
+            (
+                tenant.prefix.clone(),
+                serde_json::json!(tenant.keyring.describe_keys()),
+            )
+        })
+        .collect();
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "default": config.keyring.describe_keys(),
+        "tenants": tenants,
+    }))
+}
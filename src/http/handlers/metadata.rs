@@ -0,0 +1,75 @@
+use super::*;
+use crate::http::utils::known_objects::{KnownObject, KnownObjects};
+use crate::http::utils::xml::unquote;
+use actix_web::http::header::{EntityTag, HeaderMap};
+
+// The clear length and etag are kept with the object as metadata by `forward`:
+// the upstream only knows those of the encrypted content.
+pub struct MetadataHeaders {
+    pub original_length: &'static str,
+    pub original_etag: &'static str,
+}
+
+static AWS_METADATA: MetadataHeaders = MetadataHeaders {
+    original_length: "x-amz-meta-original-content-length",
+    original_etag: "x-amz-meta-original-etag",
+};
+
+static SWIFT_METADATA: MetadataHeaders = MetadataHeaders {
+    original_length: "x-object-meta-original-content-length",
+    original_etag: "x-object-meta-original-etag",
+};
+
+// none for a url presigned by s3, which only accepts the signed headers
+pub fn metadata_headers(
+    upstream: &Upstream,
+    req: &HttpRequest,
+) -> Option<&'static MetadataHeaders> {
+    if upstream.aws_config.is_some() {
+        Some(&AWS_METADATA)
+    } else if req
+        .query_string()
+        .to_lowercase()
+        .contains("x-amz-signature")
+    {
+        None
+    } else {
+        Some(&SWIFT_METADATA)
+    }
+}
+
+fn metadata(headers: &HeaderMap, name: impl Fn(&MetadataHeaders) -> &'static str) -> Option<&str> {
+    [&AWS_METADATA, &SWIFT_METADATA]
+        .iter()
+        .find_map(|metadata| headers.get(name(metadata)))
+        .and_then(|value| value.to_str().ok())
+}
+
+fn original_length(headers: &HeaderMap) -> Option<usize> {
+    metadata(headers, |metadata| metadata.original_length).and_then(|length| length.parse().ok())
+}
+
+// an object without metadata, such as a multipart one,
+// may be known by the proxy from its upstream etag
+fn known_object(headers: &HeaderMap, known_objects: &KnownObjects) -> Option<KnownObject> {
+    headers
+        .get(header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .and_then(|upstream_etag| known_objects.get(unquote(upstream_etag)))
+}
+
+// the clear length, from the metadata or from the objects known by the proxy
+pub fn clear_length(headers: &HeaderMap, known_objects: &KnownObjects) -> Option<usize> {
+    original_length(headers)
+        .or_else(|| known_object(headers, known_objects).map(|known| known.size))
+}
+
+// the md5 of the clear content, from the metadata or from the objects known by the proxy
+pub fn clear_etag(headers: &HeaderMap, known_objects: &KnownObjects) -> Option<EntityTag> {
+    let etag = match metadata(headers, |metadata| metadata.original_etag) {
+        Some(etag) => Some(etag.to_string()),
+        None => known_object(headers, known_objects).map(|known| known.etag),
+    };
+
+    etag.map(EntityTag::new_strong)
+}
@@ -1,27 +1,48 @@
+mod clear_length;
 mod encrypt_to_file;
 mod fetch;
 mod fetch_file;
 mod forward;
+mod keys;
+mod metadata;
+mod multipart;
 mod ping;
 mod simple_proxy;
+mod upstreams;
 
 pub use encrypt_to_file::encrypt_to_file;
 pub use fetch::fetch;
 pub use fetch_file::fetch_file;
 pub use forward::forward;
+pub use keys::keys;
+pub use multipart::{abort_multipart_upload, complete_multipart_upload, list_parts, upload_part};
 pub use ping::ping;
 pub use simple_proxy::simple_proxy;
+pub use upstreams::upstreams;
 
 // shared import between handlers
-use super::super::config::HttpConfig;
+use super::super::circuit_breaker::CircuitState;
+use super::super::config::{HttpConfig, Upstream};
 use super::super::crypto::*;
+use super::super::keyring::Keyring;
+use super::super::retry::{is_retryable_error, is_retryable_status};
+use super::utils::aws_chunked::{is_aws_chunked, AwsChunked};
+use super::utils::aws_helper::sign_request;
+use super::utils::checksum::{Rejection, Verification, VerifiedPayload};
+use super::utils::conditional::{self, Precondition};
+use super::utils::upstream_clients::UpstreamClients;
 use super::utils::*;
-use actix_web::http::header;
+use actix_web::error::PayloadError;
+use actix_web::http::header::{self, EntityTag, HeaderMap, HttpDate};
+use actix_web::http::StatusCode;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
-use awc::Client;
+use awc::error::SendRequestError;
+use awc::ClientRequest;
+use awc::SendClientRequest;
 use futures::TryStreamExt;
 use futures_core::stream::Stream;
-use log::{error, trace};
+use log::{error, info, trace, warn};
+use std::future::Future;
 
 pub static FETCH_RESPONSE_HEADERS_TO_REMOVE: [header::HeaderName; 3] = [
     // Connection settings (keepalived) must not be resend
@@ -34,6 +55,15 @@ pub static FETCH_RESPONSE_HEADERS_TO_REMOVE: [header::HeaderName; 3] = [
     header::ETAG,
 ];
 
+// a request made by the proxy for itself is not conditional
+pub static CONDITIONAL_REQUEST_HEADERS: [header::HeaderName; 5] = [
+    header::IF_MATCH,
+    header::IF_NONE_MATCH,
+    header::IF_MODIFIED_SINCE,
+    header::IF_UNMODIFIED_SINCE,
+    header::IF_RANGE,
+];
+
 pub static FETCH_REQUEST_HEADERS_TO_REMOVE: [header::HeaderName; 2] = [
     // Connection settings (keepalived) must not be resend
     header::CONNECTION,
@@ -47,3 +77,326 @@ pub fn not_found() -> Result<HttpResponse, Error> {
 
     Ok(response)
 }
+
+type UpstreamResponse = <SendClientRequest as Future>::Output;
+
+// a failing upstream is not waited for while its circuit is open
+fn circuit_open(upstream: &Upstream) -> Option<HttpResponse> {
+    upstream.circuit_breaker.allow().err().map(|retry_after| {
+        HttpResponse::ServiceUnavailable()
+            .insert_header((
+                header::RETRY_AFTER,
+                (retry_after.as_secs_f64().ceil() as u64).to_string(),
+            ))
+            .finish()
+    })
+}
+
+// the preconditions of a GET or a HEAD are evaluated on the clear object
+fn unmet_precondition(
+    req: &HttpRequest,
+    headers: &HeaderMap,
+    etag: Option<&EntityTag>,
+) -> Option<HttpResponse> {
+    match conditional::evaluate(req, etag, last_modified(headers)) {
+        Precondition::Passed => None,
+        Precondition::Failed => Some(HttpResponse::PreconditionFailed().finish()),
+        Precondition::NotModified => {
+            let mut response = HttpResponse::NotModified();
+
+            for name in [
+                header::CACHE_CONTROL,
+                header::EXPIRES,
+                header::LAST_MODIFIED,
+                header::VARY,
+            ] {
+                if let Some(value) = headers.get(&name) {
+                    response.insert_header((name, value.clone()));
+                }
+            }
+
+            if let Some(etag) = etag {
+                response.insert_header(header::ETag(etag.clone()));
+            }
+
+            Some(response.finish())
+        }
+    }
+}
+
+fn last_modified(headers: &HeaderMap) -> Option<HttpDate> {
+    headers
+        .get(header::LAST_MODIFIED)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| date.parse().ok())
+}
+
+// the name of an upstream in the json outputs
+fn upstream_name(upstream: &Upstream) -> String {
+    if upstream.prefix.is_empty() {
+        "default".to_string()
+    } else {
+        upstream.prefix.clone()
+    }
+}
+
+// the failures counted by the circuit breaker
+fn is_upstream_failure(result: &UpstreamResponse) -> bool {
+    match result {
+        Err(SendRequestError::Timeout) => true,
+        Err(e) => is_retryable_error(e),
+        Ok(res) => is_retryable_status(res.status()),
+    }
+}
+
+// A request that can be replayed is sent to another endpoint of the upstream
+// when the connection to the first one fails, and retried after a backoff
+// when the upstream is unavailable. Nothing has reached the client yet.
+async fn send_with_retries<F>(
+    upstream: &Upstream,
+    url: &str,
+    send: impl Fn(String) -> F,
+) -> UpstreamResponse
+where
+    F: Future<Output = UpstreamResponse>,
+{
+    let policy = upstream.retry;
+    let mut tried = vec![];
+    let mut attempt = 0;
+    let mut retry = 0;
+
+    loop {
+        let endpoint = upstream
+            .pool
+            .select(&tried)
+            .expect("an upstream has at least one endpoint");
+        let endpoint_url = upstream.endpoint_url(url, endpoint.index);
+
+        attempt += 1;
+        upstream.pool.record_attempt(endpoint.index);
+        info!("attempt {} to {}", attempt, endpoint_url);
+
+        let result = send(endpoint_url.clone()).await;
+        let failed = is_upstream_failure(&result);
+
+        upstream.circuit_breaker.record(!failed);
+        if failed {
+            upstream.pool.record_failure(endpoint.index);
+        }
+
+        let failure = match &result {
+            Err(e) if is_retryable_error(e) => e.to_string(),
+            Ok(res) if is_retryable_status(res.status()) => res.status().to_string(),
+            _ => return result,
+        };
+
+        tried.push(endpoint.index);
+
+        // another endpoint is tried straight away
+        if let Err(SendRequestError::Connect(_)) = result {
+            upstream.pool.set_healthy(endpoint.index, false);
+
+            if tried.len() < upstream.pool.len() {
+                warn!(
+                    "attempt {} to {} failed: {}",
+                    attempt, endpoint_url, failure
+                );
+                continue;
+            }
+        }
+
+        if retry >= policy.max_retries || upstream.circuit_breaker.state() == CircuitState::Open {
+            error!(
+                "attempt {} to {} failed: {}, giving up",
+                attempt, endpoint_url, failure
+            );
+            return result;
+        }
+
+        retry += 1;
+        upstream.pool.record_retry();
+        let delay = policy.delay(retry);
+
+        warn!(
+            "attempt {} to {} failed: {}, retry {}/{} in {:?}",
+            attempt, endpoint_url, failure, retry, policy.max_retries, delay
+        );
+
+        drop(endpoint);
+        actix_web::rt::time::sleep(delay).await;
+        tried.clear();
+    }
+}
+
+// a request that cannot be replayed is sent once
+async fn send_once(
+    upstream: &Upstream,
+    url: &str,
+    send: impl FnOnce(String) -> SendClientRequest,
+) -> UpstreamResponse {
+    let endpoint = upstream.pool.select(&[]).unwrap();
+    let endpoint_url = upstream.endpoint_url(url, endpoint.index);
+
+    upstream.pool.record_attempt(endpoint.index);
+    info!("single attempt to {}", endpoint_url);
+
+    let result = send(endpoint_url).await;
+    let failed = is_upstream_failure(&result);
+
+    upstream.circuit_breaker.record(!failed);
+    if failed {
+        upstream.pool.record_failure(endpoint.index);
+    }
+
+    if let Err(SendRequestError::Connect(_)) = result {
+        upstream.pool.set_healthy(endpoint.index, false);
+    }
+
+    result
+}
+
+fn sign_if_needed(req: ClientRequest, upstream: &Upstream) -> ClientRequest {
+    match upstream.aws_config.clone() {
+        Some(aws_config) => sign_request(req, aws_config),
+        None => req,
+    }
+}
+
+// the checksums sent by the client are those of the clear content,
+// verified by the proxy: the upstream would compare them to what it stores
+fn remove_checksums(req: &mut ClientRequest) {
+    let checksums: Vec<_> = req
+        .headers()
+        .keys()
+        .filter(|name| name.as_str().starts_with("x-amz-checksum-"))
+        .cloned()
+        .collect();
+
+    for name in checksums {
+        req.headers_mut().remove(name);
+    }
+
+    req.headers_mut().remove("content-md5");
+    req.headers_mut().remove("x-amz-sdk-checksum-algorithm");
+}
+
+fn s3_error(status: StatusCode, code: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header((header::CONTENT_TYPE, "application/xml"))
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code><Message>{}</Message></Error>",
+            code, message
+        ))
+}
+
+fn rejected(rejection: Rejection) -> HttpResponse {
+    match rejection {
+        Rejection::BadDigest(algorithm) => s3_error(
+            StatusCode::BAD_REQUEST,
+            "BadDigest",
+            &format!(
+                "The {} you specified did not match the calculated checksum.",
+                algorithm.header_name()
+            ),
+        ),
+        Rejection::SignatureDoesNotMatch => s3_error(
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch",
+            "The request signature we calculated does not match the signature you provided.",
+        ),
+        Rejection::IncompleteBody => s3_error(
+            StatusCode::BAD_REQUEST,
+            "IncompleteBody",
+            "You did not provide the number of bytes specified by the Content-Length HTTP header.",
+        ),
+        Rejection::MalformedChunk => s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidRequest",
+            "The aws-chunked body of the request is malformed.",
+        ),
+    }
+}
+
+type ClearPayload = Box<dyn Stream<Item = Result<web::Bytes, PayloadError>> + Unpin>;
+
+// the clear content of an upload, without its aws-chunked frames,
+// verified against the checksums of the client while it is read
+fn clear_payload(
+    req: &HttpRequest,
+    payload: web::Payload,
+    upstream: &Upstream,
+) -> (ClearPayload, Verification) {
+    let verification = Verification::default();
+
+    let payload: ClearPayload = if is_aws_chunked(req.headers()) {
+        Box::new(AwsChunked::new(
+            payload,
+            req,
+            upstream.aws_config.as_ref(),
+            verification.clone(),
+        ))
+    } else {
+        Box::new(payload)
+    };
+
+    let payload = VerifiedPayload::new(payload, req.headers(), verification.clone());
+
+    (Box::new(payload), verification)
+}
+
+// the proxy sends the decoded content upstream
+fn remove_aws_chunked(req: &mut ClientRequest) {
+    let encodings: Vec<String> = req
+        .headers()
+        .get_all(header::CONTENT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty() && *encoding != "aws-chunked")
+        .map(str::to_string)
+        .collect();
+
+    req.headers_mut().remove(header::CONTENT_ENCODING);
+    if !encodings.is_empty() {
+        req.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            encodings.join(", ").parse().unwrap(),
+        );
+    }
+
+    if req
+        .headers()
+        .get("x-amz-content-sha256")
+        .is_some_and(|value| value.as_bytes().starts_with(b"STREAMING-"))
+    {
+        req.headers_mut().remove("x-amz-content-sha256");
+    }
+
+    req.headers_mut().remove("x-amz-decoded-content-length");
+    req.headers_mut().remove("x-amz-trailer");
+}
+
+// the routes a proxy does not hold the keys for
+pub async fn not_allowed() -> HttpResponse {
+    HttpResponse::MethodNotAllowed().finish()
+}
+
+// uploads are sealed to the encryption public key when there is one,
+// encrypted with the active key of the keyring otherwise
+fn encoder_for<E>(
+    config: &HttpConfig,
+    keyring: &Keyring,
+    s: Box<dyn Stream<Item = Result<web::Bytes, E>> + Unpin>,
+) -> Encoder<E> {
+    match config.encryption_public_key {
+        Some(public_key) => Encoder::new_sealed(public_key, config.chunk_size, s),
+        None => {
+            let (key_id, key) = keyring
+                .get_last_key()
+                .expect("no key avalaible for encryption");
+
+            Encoder::new(key, key_id, config.chunk_size, s)
+                .with_recovery_public_key(config.recovery_public_key)
+        }
+    }
+}
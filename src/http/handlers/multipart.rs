@@ -0,0 +1,378 @@
+use super::forward::FORWARD_REQUEST_HEADERS_TO_REMOVE;
+use super::*;
+use crate::crypto::header::{segment_header, SEGMENT_HEADER_SIZE};
+use crate::http::utils::known_objects::{KnownObject, KnownObjects};
+use crate::http::utils::memory_or_file_buffer::MemoryOrFileBuffer;
+use crate::http::utils::multipart::*;
+use crate::http::utils::xml::*;
+use crate::multipart_store::{MultipartStore, PartRecord, STALE_UPLOAD_AGE};
+use actix_web::body::SizedStream;
+use actix_web::error::PayloadError;
+use futures::{stream, StreamExt};
+
+// a CompleteMultipartUpload lists up to 10 000 parts
+const MAX_COMPLETE_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+fn upload_id(req: &HttpRequest) -> String {
+    query_param(req.query_string(), "uploadId").expect("guarded by is_multipart_upload")
+}
+
+fn bad_gateway(e: SendRequestError, req: &HttpRequest) -> Error {
+    error!("multipart fwk error {:?}, {:?}", e, req);
+    actix_web::error::ErrorBadGateway(e)
+}
+
+fn store_error(e: String) -> Error {
+    error!("multipart store error {:?}", e);
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+fn spool_error(e: std::io::Error) -> Error {
+    error!("multipart spool error {:?}", e);
+    actix_web::error::ErrorInternalServerError(e)
+}
+
+fn response_builder(res: &awc::ClientResponse<impl Stream>) -> actix_web::HttpResponseBuilder {
+    let mut client_resp = HttpResponse::build(res.status());
+
+    for header in res
+        .headers()
+        .iter()
+        .filter(|(h, _)| !FETCH_RESPONSE_HEADERS_TO_REMOVE.contains(h))
+    {
+        client_resp.append_header(header);
+    }
+
+    client_resp
+}
+
+// Each part is encrypted as a whole file, behind a segment header giving its
+// length: the object assembled by the upstream is a sequence of segments,
+// decrypted one after the other.
+pub async fn upload_part(
+    req: HttpRequest,
+    payload: web::Payload,
+    clients: web::Data<UpstreamClients>,
+    config: web::Data<HttpConfig>,
+    store: web::Data<MultipartStore>,
+) -> Result<HttpResponse, Error> {
+    let Some(put_url) = config.create_upstream_url(&req) else {
+        return not_found();
+    };
+
+    let upstream = config.upstream_for(&req);
+
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
+    }
+
+    // the copied part would be stored as it is, not as a segment
+    if req.headers().contains_key("x-amz-copy-source") {
+        return Ok(s3_error(
+            StatusCode::NOT_IMPLEMENTED,
+            "NotImplemented",
+            "UploadPartCopy is not supported by the encrypting proxy",
+        ));
+    }
+
+    let upload_id = upload_id(&req);
+    let Some(part_number) =
+        query_param(req.query_string(), "partNumber").and_then(|n| n.parse::<u32>().ok())
+    else {
+        return Ok(s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidArgument",
+            "partNumber must be an integer",
+        ));
+    };
+
+    let spool = MultipartSpool::new(&config.local_encryption_directory);
+    spool.create_directory().await.map_err(spool_error)?;
+
+    if let Err(e) = spool.remove_stale_parts(STALE_UPLOAD_AGE).await {
+        warn!("unable to remove the stale multipart parts: {}", e);
+    }
+
+    let (payload, verification) = clear_payload(&req, payload, upstream);
+    let mut encrypted_stream =
+        encoder_for(&config, config.keyring_for(upstream, &put_url), payload);
+
+    let mut buffer = MemoryOrFileBuffer::new(
+        spool.spool_path(&upload_id, part_number),
+        config.max_in_memory_file_size,
+    );
+
+    let read = async {
+        while let Some(bytes) = encrypted_stream.try_next().await? {
+            buffer.append(bytes).await;
+        }
+        Ok::<_, PayloadError>(())
+    }
+    .await;
+
+    if let Some(rejection) = verification.rejection() {
+        return Ok(rejected(rejection));
+    }
+    read?;
+
+    let (_output_sha256, length) = buffer.sha256_and_len();
+    let size = encrypted_stream.input_length();
+    let md5 = encrypted_stream.input_md5();
+    let segment_header = web::Bytes::from(segment_header(length as usize));
+
+    // the spooled part is replayed on each attempt
+    let buffer = &buffer;
+    let mut res = send_with_retries(upstream, &put_url, |url| {
+        let mut part_req = clients
+            .request_from(upstream, url, req.head())
+            .timeout(upstream.upload_timeout);
+
+        for header in &FORWARD_REQUEST_HEADERS_TO_REMOVE {
+            part_req.headers_mut().remove(header);
+        }
+        remove_checksums(&mut part_req);
+        remove_aws_chunked(&mut part_req);
+
+        let part_req = sign_if_needed(part_req, upstream);
+        let segment_header = segment_header.clone();
+
+        async move {
+            let body = stream::once(async { Ok(segment_header) }).chain(buffer.as_stream().await);
+
+            part_req
+                .send_body(SizedStream::new(
+                    SEGMENT_HEADER_SIZE as u64 + length,
+                    body.boxed_local(),
+                ))
+                .await
+        }
+    })
+    .await
+    .map_err(|e| bad_gateway(e, &req))?;
+
+    trace!("backend response for UploadPart {:?} : {:?}", put_url, res);
+
+    if !res.status().is_success() {
+        error!("upload part status error {:?} {:?}", req, res);
+    } else if let Some(upstream_etag) = res.headers().get(header::ETAG) {
+        let record = PartRecord {
+            md5: md5.clone(),
+            upstream_etag: upstream_etag.to_str().unwrap_or_default().to_string(),
+            size,
+        };
+
+        store
+            .save_part(&upload_id, part_number, &record)
+            .await
+            .map_err(store_error)?;
+    }
+
+    let mut client_resp = response_builder(&res);
+
+    if res.status().is_success() {
+        client_resp.insert_header((header::ETAG, format!("\"{}\"", md5)));
+
+        for checksum in verification.response_headers() {
+            client_resp.insert_header(checksum);
+        }
+    }
+
+    Ok(client_resp.body(res.body().await?))
+}
+
+// the client completes the upload with the etags of its clear parts,
+// the upstream expects the etags of the encrypted ones
+pub async fn complete_multipart_upload(
+    req: HttpRequest,
+    payload: web::Payload,
+    clients: web::Data<UpstreamClients>,
+    config: web::Data<HttpConfig>,
+    known_objects: web::Data<KnownObjects>,
+    store: web::Data<MultipartStore>,
+) -> Result<HttpResponse, Error> {
+    let Some(url) = config.create_upstream_url(&req) else {
+        return not_found();
+    };
+
+    let upstream = config.upstream_for(&req);
+
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
+    }
+
+    let upload_id = upload_id(&req);
+    let parts = store.load_parts(&upload_id).await.map_err(store_error)?;
+
+    let body = payload
+        .to_bytes_limited(MAX_COMPLETE_BODY_SIZE)
+        .await
+        .map_err(actix_web::error::ErrorPayloadTooLarge)??;
+    let body = String::from_utf8_lossy(&body);
+
+    let mut md5s = vec![];
+    let mut size = 0;
+    let rewritten = map_elements(&body, "Part", |part| {
+        let part_number = element_text(part, "PartNumber")?
+            .trim()
+            .parse::<u32>()
+            .ok()?;
+        let record = parts.get(&part_number)?;
+
+        if unquote(element_text(part, "ETag")?) != record.md5 {
+            return None;
+        }
+
+        md5s.push(record.md5.clone());
+        size += record.size;
+
+        let mut part = replace_element(part, "ETag", &record.upstream_etag);
+        for checksum in [
+            "ChecksumCRC32",
+            "ChecksumCRC32C",
+            "ChecksumCRC64NVME",
+            "ChecksumSHA1",
+            "ChecksumSHA256",
+        ] {
+            part = remove_element(&part, checksum);
+        }
+
+        Some(part)
+    });
+
+    let Some(rewritten) = rewritten else {
+        return Ok(s3_error(
+            StatusCode::BAD_REQUEST,
+            "InvalidPart",
+            "One or more of the specified parts could not be found or its etag does not match",
+        ));
+    };
+
+    let res = send_once(upstream, &url, |url| {
+        let mut complete_req = clients.request_from(upstream, url, req.head());
+
+        for header in &FORWARD_REQUEST_HEADERS_TO_REMOVE {
+            complete_req.headers_mut().remove(header);
+        }
+        remove_checksums(&mut complete_req);
+
+        sign_if_needed(complete_req, upstream).send_body(rewritten)
+    })
+    .await;
+
+    let mut res = res.map_err(|e| bad_gateway(e, &req))?;
+
+    trace!(
+        "backend response for CompleteMultipartUpload {:?} : {:?}",
+        url,
+        res
+    );
+
+    let response_body = res.body().await?;
+    let response_body = String::from_utf8_lossy(&response_body);
+
+    // s3 can answer 200 and report the failure in the body
+    let completed = res.status().is_success() && !response_body.contains("<Error>");
+
+    if !completed {
+        error!("complete multipart status error {:?} {:?}", req, res);
+        return Ok(response_builder(&res).body(response_body.into_owned()));
+    }
+
+    store.remove_upload(&upload_id).await.map_err(store_error)?;
+
+    let etag = multipart_etag(&md5s);
+
+    if let Some(upstream_etag) = element_text(&response_body, "ETag") {
+        known_objects.insert(
+            unquote(upstream_etag),
+            KnownObject {
+                etag: etag.clone(),
+                size,
+            },
+        );
+    }
+
+    let etag = format!("&quot;{}&quot;", etag);
+
+    Ok(response_builder(&res).body(replace_element(&response_body, "ETag", &etag)))
+}
+
+// the parts are listed with their clear size and etag
+pub async fn list_parts(
+    req: HttpRequest,
+    clients: web::Data<UpstreamClients>,
+    config: web::Data<HttpConfig>,
+    store: web::Data<MultipartStore>,
+) -> Result<HttpResponse, Error> {
+    let Some(url) = config.create_upstream_url(&req) else {
+        return not_found();
+    };
+
+    let upstream = config.upstream_for(&req);
+
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
+    }
+
+    let res = send_with_retries(upstream, &url, |url| {
+        let mut list_req = clients.request_from(upstream, url, req.head());
+
+        for header in &FETCH_REQUEST_HEADERS_TO_REMOVE {
+            list_req.headers_mut().remove(header);
+        }
+
+        sign_if_needed(list_req, upstream).send()
+    })
+    .await;
+
+    let mut res = res.map_err(|e| bad_gateway(e, &req))?;
+    let response_body = res.body().await?;
+
+    if !res.status().is_success() {
+        error!("list parts status error {:?} {:?}", req, res);
+        return Ok(response_builder(&res).body(response_body));
+    }
+
+    let parts = store
+        .load_parts(&upload_id(&req))
+        .await
+        .map_err(store_error)?;
+
+    let response_body = String::from_utf8_lossy(&response_body);
+    let rewritten = map_elements(&response_body, "Part", |part| {
+        let record = element_text(part, "PartNumber")
+            .and_then(|number| number.trim().parse::<u32>().ok())
+            .and_then(|number| parts.get(&number));
+
+        // a part uploaded around the proxy is left as it is
+        Some(match record {
+            Some(record) => {
+                let part = replace_element(part, "ETag", &format!("&quot;{}&quot;", record.md5));
+                replace_element(&part, "Size", &record.size.to_string())
+            }
+            None => part.to_string(),
+        })
+    })
+    .unwrap_or_else(|| response_body.into_owned());
+
+    Ok(response_builder(&res).body(rewritten))
+}
+
+pub async fn abort_multipart_upload(
+    req: HttpRequest,
+    payload: web::Payload,
+    clients: web::Data<UpstreamClients>,
+    config: web::Data<HttpConfig>,
+    known_objects: web::Data<KnownObjects>,
+    store: web::Data<MultipartStore>,
+) -> Result<HttpResponse, Error> {
+    let upload_id = upload_id(&req);
+
+    let response = simple_proxy(req, payload, clients, config, known_objects).await?;
+
+    if response.status().is_success() {
+        store.remove_upload(&upload_id).await.map_err(store_error)?;
+    }
+
+    Ok(response)
+}
@@ -1,20 +1,17 @@
 use super::*;
 
+// a bare status: the state of the upstreams is only given on the unix socket
 pub async fn ping() -> HttpResponse {
-    let mut response = match std::env::current_dir() {
+    match std::env::current_dir() {
         Ok(path_buff) => {
             if path_buff.join("maintenance").exists() {
-                HttpResponse::NotFound()
+                HttpResponse::NotFound().finish()
             } else {
-                HttpResponse::Ok()
+                HttpResponse::Ok().finish()
             }
         }
 
         // the server cannot even read a directory
-        Err(_) => HttpResponse::InternalServerError(),
-    };
-
-    response
-        .insert_header((header::CONTENT_TYPE, "application/json"))
-        .body("{}")
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
 }
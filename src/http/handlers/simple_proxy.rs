@@ -2,13 +2,18 @@ use actix_http::Method;
 
 use crate::http::utils::aws_helper::sign_request;
 
+use super::clear_length::probe_clear_length;
+use super::metadata::{clear_etag, clear_length};
 use super::*;
+use crate::http::utils::known_objects::KnownObjects;
+use crate::http::utils::listing::Listing;
 
 pub async fn simple_proxy(
     req: HttpRequest,
     payload: web::Payload,
-    client: web::Data<Client>,
+    clients: web::Data<UpstreamClients>,
     config: web::Data<HttpConfig>,
+    known_objects: web::Data<KnownObjects>,
 ) -> Result<HttpResponse, Error> {
     let url = config.create_upstream_url(&req);
 
@@ -16,48 +21,90 @@ pub async fn simple_proxy(
         return not_found();
     }
 
-    let mut proxied_req = client.request_from(url.unwrap(), req.head()).force_close();
+    let url = url.unwrap();
+    let upstream = config.upstream_for(&req);
 
-    for header in &FETCH_REQUEST_HEADERS_TO_REMOVE {
-        proxied_req.headers_mut().remove(header);
+    if let Some(response) = circuit_open(upstream) {
+        return Ok(response);
     }
 
-    let req_to_send = if let Some(aws_config) = config.aws_config.clone() {
-        sign_request(proxied_req, aws_config)
+    // the HEAD of an object is answered as the GET would be
+    let listing = Listing::requested(&req);
+    let object_head = req.method() == Method::HEAD && listing.is_none();
+
+    let build = |url: String| {
+        let mut proxied_req = clients.request_from(upstream, url, req.head());
+
+        for header in &FETCH_REQUEST_HEADERS_TO_REMOVE {
+            proxied_req.headers_mut().remove(header);
+        }
+
+        if object_head {
+            for header in &CONDITIONAL_REQUEST_HEADERS {
+                proxied_req.headers_mut().remove(header);
+            }
+        }
+
+        if let Some(aws_config) = upstream.aws_config.clone() {
+            sign_request(proxied_req, aws_config)
+        } else {
+            proxied_req
+        }
+    };
+
+    // only the body of an idempotent request is kept to be replayed
+    let res = if req.method().is_idempotent() {
+        let body = payload.to_bytes().await?;
+        send_with_retries(upstream, &url, |url| build(url).send_body(body.clone())).await
     } else {
-        proxied_req
+        send_once(upstream, &url, |url| build(url).send_stream(payload)).await
     };
 
-    req_to_send
-        .send_stream(payload)
-        .await
-        .map_err(|e| {
-            error!("simple proxy fwk error {:?}, {:?}", e, req);
-            actix_web::error::ErrorBadGateway(e)
-        })
-        .map(|res| {
-            if res.status().is_client_error() || res.status().is_server_error() {
-                error!("simple proxy status error {:?} {:?}", req, res);
-            }
+    let res = res.map_err(|e| {
+        error!("simple proxy fwk error {:?}, {:?}", e, req);
+        actix_web::error::ErrorBadGateway(e)
+    })?;
 
-            let mut client_resp = HttpResponse::build(res.status());
+    if res.status().is_client_error() || res.status().is_server_error() {
+        error!("simple proxy status error {:?} {:?}", req, res);
+    }
 
-            for header in res
-                .headers()
-                .iter()
-                .filter(|(h, _)| !FETCH_RESPONSE_HEADERS_TO_REMOVE.contains(h))
-            {
-                client_resp.append_header(header);
-            }
+    let mut client_resp = HttpResponse::build(res.status());
 
-            if req.method() == Method::HEAD {
-                if let Some(content_length) =
-                    res.headers().get("x-amz-meta-original-content-length")
-                {
-                    client_resp.insert_header(("content-length", content_length.clone()));
-                }
-            }
+    for header in res
+        .headers()
+        .iter()
+        .filter(|(h, _)| !FETCH_RESPONSE_HEADERS_TO_REMOVE.contains(h))
+    {
+        client_resp.append_header(header);
+    }
+
+    // an object can be given the query of a listing
+    let object_head = object_head
+        || (req.method() == Method::HEAD
+            && listing.is_some_and(|listing| !listing.answered_by(res.headers())));
+
+    if object_head && res.status().is_success() {
+        let etag = clear_etag(res.headers(), &known_objects);
+
+        if let Some(response) = unmet_precondition(&req, res.headers(), etag.as_ref()) {
+            return Ok(response);
+        }
+
+        if let Some(etag) = etag {
+            client_resp.insert_header(header::ETag(etag));
+        }
+
+        // the segments are probed only as a last resort
+        let clear_length = match clear_length(res.headers(), &known_objects) {
+            Some(length) => Some(length),
+            None => probe_clear_length(&clients, upstream, &url, &req).await?,
+        };
+
+        if let Some(clear_length) = clear_length {
+            client_resp.insert_header((header::CONTENT_LENGTH, clear_length.to_string()));
+        }
+    }
 
-            client_resp.streaming(res)
-        })
+    Ok(client_resp.streaming(res))
 }
@@ -0,0 +1,20 @@
+use super::*;
+
+// the state of the endpoints of each upstream and the attempts made on them
+pub async fn upstreams(config: web::Data<HttpConfig>) -> HttpResponse {
+    let upstreams: serde_json::Map<String, serde_json::Value> = config
+        .upstreams()
+        .map(|upstream| {
+            (
+                upstream_name(upstream),
+                serde_json::json!({
+                    "circuit": upstream.circuit_breaker.state(),
+                    "retries": upstream.pool.retries(),
+                    "endpoints": upstream.pool.describe_endpoints(),
+                }),
+            )
+        })
+        .collect();
+
+    HttpResponse::Ok().json(upstreams)
+}
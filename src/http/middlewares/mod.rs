@@ -69,7 +69,11 @@ pub async fn verify_aws_signature(
 
     let config = service_request.app_data::<web::Data<HttpConfig>>().unwrap();
 
-    if let Some(config) = config.aws_config.clone() {
+    if let Some(config) = config
+        .upstream_for(service_request.request())
+        .aws_config
+        .clone()
+    {
         if !config.bypass_signature_check && !is_signature_valid(service_request.request(), config)
         {
             log::warn!(
@@ -1,55 +1,133 @@
-use super::super::config::HttpConfig;
+use super::super::config::{HttpConfig, KeyringReloadConfig, Upstream};
+use super::super::keyring::Keyring;
 use super::handlers::*;
 use super::middlewares::*;
-use crate::redis_utils::configure_redis_pool;
+use super::utils::known_objects::KnownObjects;
+use super::utils::multipart::is_multipart_upload;
+use super::utils::upstream_clients::{create_client, UpstreamClients};
+use crate::multipart_store::MultipartStore;
+use crate::redis_utils::{configure_redis_pool, create_redis_pool};
+use crate::tls::{reloadable_acceptor, TlsConfig};
 use crate::write_once_service::WriteOnceService;
 use actix_web::dev::Service;
-use actix_web::guard::{Get, Put};
+use actix_web::guard::{self, fn_guard, Get, Put};
+use actix_web::http::Method;
 use actix_web::{
     middleware,
     middleware::from_fn,
     web::{resource, scope, Data},
-    App, HttpServer,
+    App, HttpServer, Resource,
 };
 use futures::FutureExt;
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
-use std::time::Duration;
-
-const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+use openssl::ssl::SslContext;
+use std::sync::{Arc, RwLock};
+use tokio::signal::unix::{signal, SignalKind};
 
 #[actix_web::main]
 pub async fn main(config: HttpConfig) -> std::io::Result<()> {
     let address = config.address;
+    let keep_alive = config.keep_alive;
+
+    log::info!(
+        "can encrypt: {}, can decrypt: {}",
+        config.can_encrypt(),
+        config.can_decrypt()
+    );
+
+    log_fingerprints("default", &config.keyring);
+    for tenant in &config.tenants {
+        log_fingerprints(&tenant.prefix, &tenant.keyring);
+    }
+
+    // also holds the parts of the multipart uploads, redis is only
+    // required at startup by write once
     let redis_pool = if config.write_once {
-        Some(configure_redis_pool(config.redis_config.clone()).await)
+        configure_redis_pool(config.redis_config.clone()).await
     } else {
-        None
+        create_redis_pool(config.redis_config.clone())
+    };
+
+    if let Some(keyring_reload) = config.keyring_reload.clone() {
+        for tenant in &config.tenants {
+            actix_web::rt::spawn(reload_keyring_periodically(
+                KeyringReloadConfig {
+                    keyring_file: tenant.keyring_file.clone(),
+                    ..keyring_reload.clone()
+                },
+                tenant.keyring.clone(),
+            ));
+        }
+
+        actix_web::rt::spawn(reload_keyring_periodically(
+            keyring_reload,
+            config.keyring.clone(),
+        ));
+    }
+
+    let tls_acceptor = match &config.tls {
+        Some(tls) => {
+            let current = Arc::new(RwLock::new(
+                tls.ssl_context().map_err(std::io::Error::other)?,
+            ));
+            actix_web::rt::spawn(reload_tls_on_sighup(tls.clone(), current.clone()));
+
+            Some(reloadable_acceptor(tls, current).map_err(std::io::Error::other)?)
+        }
+        None => None,
     };
 
-    HttpServer::new(move || {
-        let mut awc_connector = awc::Connector::new().timeout(config.backend_connection_timeout); // max time to connect to remote host including dns name resolution
-        if !config.verify_ssl_certificate {
-            let mut ssl_builder = SslConnector::builder(SslMethod::tls()).unwrap();
-            ssl_builder.set_verify(SslVerifyMode::NONE);
-            let ssl_connector = ssl_builder.build();
-            awc_connector = awc_connector.openssl(ssl_connector);
+    for upstream in config.upstreams() {
+        if upstream.pool.len() > 1 {
+            actix_web::rt::spawn(check_endpoints_periodically(upstream.clone()));
         }
+    }
+
+    // shared by the workers
+    let known_objects = Data::new(KnownObjects::new());
+
+    let server = HttpServer::new(move || {
         let mut app = App::new()
-            .app_data(Data::new(
-                awc::Client::builder()
-                    .connector(awc_connector)
-                    .timeout(RESPONSE_TIMEOUT) // the total time before a response must be received
-                    .finish(),
-            ))
+            .app_data(Data::new(UpstreamClients::new(&config)))
             .app_data(Data::new(config.clone()))
+            .app_data(known_objects.clone())
+            .app_data(Data::new(MultipartStore::new(redis_pool.clone())))
             .wrap(middleware::Logger::default())
             .service(resource("/ping").guard(Get()).to(ping))
+            .service(
+                // only served on the unix socket, not on the public listener
+                scope("/admin")
+                    .guard(fn_guard(|ctx| ctx.head().peer_addr.is_none()))
+                    .service(resource("/keys").guard(Get()).to(keys))
+                    .service(resource("/upstreams").guard(Get()).to(upstreams)),
+            )
             .service({
+                // the calls on a multipart upload in progress, before the ones on objects
                 let scope = scope("/upstream")
-                    .service(resource("").guard(Get()).to(fetch)) // for ex: used for listing bucket  (?list-type=2&encoding-type=url)
-                    .service(resource("{name}*").guard(Get()).to(fetch));
+                    .service(if config.can_encrypt() {
+                        multipart_upload(Method::PUT).to(upload_part)
+                    } else {
+                        multipart_upload(Method::PUT).to(not_allowed)
+                    })
+                    .service(multipart_upload(Method::POST).to(complete_multipart_upload))
+                    .service(multipart_upload(Method::GET).to(list_parts))
+                    .service(multipart_upload(Method::DELETE).to(abort_multipart_upload));
+
+                let scope = if config.can_decrypt() {
+                    scope
+                        .service(resource("").guard(Get()).to(fetch)) // for ex: used for listing bucket  (?list-type=2&encoding-type=url)
+                        .service(resource("{name}*").guard(Get()).to(fetch))
+                } else {
+                    scope
+                        .service(resource("").guard(Get()).to(not_allowed))
+                        .service(resource("{name}*").guard(Get()).to(not_allowed))
+                };
 
-                let upstream_put = resource("{name}*").guard(Put()).to(forward);
+                // without a guarded resource, a put would reach the upstream unencrypted
+                let upstream_put = if config.can_encrypt() {
+                    resource("{name}*").guard(Put()).to(forward)
+                } else {
+                    resource("{name}*").guard(Put()).to(not_allowed)
+                };
 
                 let scope = if config.write_once {
                     scope.service(upstream_put.wrap(from_fn(ensure_write_once)))
@@ -62,7 +140,11 @@ pub async fn main(config: HttpConfig) -> std::io::Result<()> {
             })
             .service(
                 scope("/local")
-                    .service(resource("encrypt/{name}").guard(Put()).to(encrypt_to_file))
+                    .service(if config.can_encrypt() {
+                        resource("encrypt/{name}").guard(Put()).to(encrypt_to_file)
+                    } else {
+                        resource("encrypt/{name}").guard(Put()).to(not_allowed)
+                    })
                     .service(
                         resource("encrypt/{name}")
                             .guard(Get())
@@ -72,16 +154,131 @@ pub async fn main(config: HttpConfig) -> std::io::Result<()> {
             );
 
         if config.write_once {
-            app = app.app_data(Data::new(WriteOnceService::new(
-                redis_pool.clone().unwrap(),
-            )));
+            app = app.app_data(Data::new(
+                WriteOnceService::new(redis_pool.clone())
+                    .with_lock_duration(config.write_once_lock_duration),
+            ));
         }
 
         app
     })
-    .keep_alive(actix_http::KeepAlive::Disabled)
-    .bind_uds("/tmp/actix-uds.socket")?
-    .bind(address)?
+    .keep_alive(match keep_alive {
+        Some(duration) => actix_http::KeepAlive::Timeout(duration),
+        None => actix_http::KeepAlive::Disabled,
+    })
+    .bind_uds("/tmp/actix-uds.socket")?;
+
+    match tls_acceptor {
+        Some(acceptor) => server.bind_openssl(address, acceptor)?,
+        None => server.bind(address)?,
+    }
     .run()
     .await
 }
+
+fn multipart_upload(method: Method) -> Resource {
+    resource("{name}*")
+        .guard(guard::Method(method))
+        .guard(fn_guard(is_multipart_upload))
+}
+
+fn log_fingerprints(tenant: &str, keyring: &Keyring) {
+    for description in keyring.describe_keys() {
+        log::info!(
+            "{} key {} ({}) fingerprint: {}",
+            tenant,
+            description.id,
+            description.state,
+            description.fingerprint
+        );
+    }
+}
+
+// an endpoint is ejected when it cannot be reached or answers with a server error,
+// and comes back once it answers again
+async fn check_endpoints_periodically(upstream: Upstream) {
+    let client = create_client(&upstream);
+    let mut interval = actix_web::rt::time::interval(upstream.health_check_interval);
+
+    loop {
+        interval.tick().await;
+
+        for (index, base_url) in upstream.pool.base_urls().enumerate() {
+            let probe_url = match &upstream.health_check_path {
+                Some(path) => base_url.join(path).unwrap_or_else(|_| base_url.clone()),
+                None => base_url.clone(),
+            };
+
+            let healthy = match client.get(probe_url.as_str()).send().await {
+                Ok(res) => !res.status().is_server_error(),
+                Err(e) => {
+                    log::debug!("health check of {} failed: {}", probe_url, e);
+                    false
+                }
+            };
+
+            upstream.pool.set_healthy(index, healthy);
+        }
+    }
+}
+
+// a renewed certificate is used by the next connections,
+// the proxy keeps the previous one if the files are invalid
+async fn reload_tls_on_sighup(tls: TlsConfig, current: Arc<RwLock<SslContext>>) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::error!(
+                "unable to listen to SIGHUP, the tls files won't be reloaded: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match tls.ssl_context() {
+            Ok(context) => {
+                *current.write().unwrap() = context;
+                log::info!("the tls files have been reloaded");
+            }
+            Err(why) => log::error!("unable to reload the tls files: {}", why),
+        }
+    }
+}
+
+async fn reload_keyring_periodically(keyring_reload: KeyringReloadConfig, keyring: Keyring) {
+    let mut interval = actix_web::rt::time::interval(keyring_reload.interval);
+
+    loop {
+        interval.tick().await;
+
+        let keyring_file = keyring_reload.keyring_file.clone();
+        let max_key_age = keyring_reload.max_key_age;
+
+        // the keyring file is locked during a rotation, which can block
+        let reloaded = actix_web::rt::task::spawn_blocking(move || {
+            if let Some(max_key_age) = max_key_age {
+                match keyring_file.rotate_if_older_than(max_key_age) {
+                    Ok(true) => log::info!(
+                        "the active key was older than {:?}, a new key has been added",
+                        max_key_age
+                    ),
+                    Ok(false) => (),
+                    // the keyring is left as it is, and still reloaded
+                    Err(why) => log::error!("unable to rotate the keyring: {}", why),
+                }
+            }
+
+            keyring_file.try_load()
+        })
+        .await;
+
+        match reloaded {
+            Ok(Ok(reloaded)) => keyring.replace_with(reloaded),
+            // the proxy keeps running with the keys it already has
+            Ok(Err(why)) => log::error!("unable to reload the keyring: {}", why),
+            Err(e) => log::error!("unable to reload the keyring: {:?}", e),
+        }
+    }
+}
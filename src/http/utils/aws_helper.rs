@@ -46,6 +46,7 @@ fn sign_request_with_time(
     let mut host = uri.host().unwrap_or_default().to_string();
     let port = uri.port();
 
+    #[allow(clippy::unnecessary_unwrap)]
     if port.is_some() {
         host = format!("{}:{}", host, port.unwrap().as_str());
     }
@@ -93,6 +94,21 @@ pub fn remove_aws_signature_params(url: Url) -> String {
     cleaned_url.to_string()
 }
 
+// the x-amz-copy-source of an object: its path holds the bucket in path
+// style, while the bucket is the first label of the host in virtual-hosted style
+pub fn copy_source(url: &Url) -> String {
+    match url.host_str() {
+        Some(host) if host.contains(".s3.") || host.contains(".s3-") => {
+            format!(
+                "/{}{}",
+                host.split('.').next().unwrap_or_default(),
+                url.path()
+            )
+        }
+        _ => url.path().to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -189,4 +205,14 @@ mod tests {
 
         assert_eq!(signed.headers().get("authorization").unwrap(), "AWS4-HMAC-SHA256 Credential=an_access_key/20251201/eu-west-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature=9d0c8b45db94e946687e2ff747c9e352e1468140a5bd6de9ee772f94317c7ec2");
     }
+
+    #[test]
+    fn the_copy_source_holds_the_bucket() {
+        let path_style = Url::parse("https://s3.eu-west-1.amazonaws.com/bucket/dir/key").unwrap();
+        assert_eq!("/bucket/dir/key", copy_source(&path_style));
+
+        let virtual_hosted =
+            Url::parse("https://bucket.s3.eu-west-1.amazonaws.com/dir/key").unwrap();
+        assert_eq!("/bucket/dir/key", copy_source(&virtual_hosted));
+    }
 }
@@ -0,0 +1,422 @@
+use actix_web::error::PayloadError;
+use actix_web::http::header::HeaderMap;
+use actix_web::web::Bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crc::{Algorithm, Crc, CRC_32_ISCSI, CRC_32_ISO_HDLC};
+use futures::Stream;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::io;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+// not in the crc catalog yet
+const CRC_64_NVME: Algorithm<u64> = Algorithm {
+    width: 64,
+    poly: 0xad93d23594c93659,
+    init: 0xffffffffffffffff,
+    refin: true,
+    refout: true,
+    xorout: 0xffffffffffffffff,
+    check: 0xae8b14860a799888,
+    residue: 0xf310303b2b6f6e42,
+};
+
+static CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+static CRC32C: Crc<u32> = Crc::<u32>::new(&CRC_32_ISCSI);
+static CRC64NVME: Crc<u64> = Crc::<u64>::new(&CRC_64_NVME);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Crc32,
+    Crc32c,
+    Crc64Nvme,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    const ALL: [ChecksumAlgorithm; 6] = [
+        ChecksumAlgorithm::Md5,
+        ChecksumAlgorithm::Crc32,
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Crc64Nvme,
+        ChecksumAlgorithm::Sha1,
+        ChecksumAlgorithm::Sha256,
+    ];
+
+    // the header holding the base64 checksum
+    pub fn header_name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "content-md5",
+            ChecksumAlgorithm::Crc32 => "x-amz-checksum-crc32",
+            ChecksumAlgorithm::Crc32c => "x-amz-checksum-crc32c",
+            ChecksumAlgorithm::Crc64Nvme => "x-amz-checksum-crc64nvme",
+            ChecksumAlgorithm::Sha1 => "x-amz-checksum-sha1",
+            ChecksumAlgorithm::Sha256 => "x-amz-checksum-sha256",
+        }
+    }
+
+    pub fn from_header_name(name: &str) -> Option<ChecksumAlgorithm> {
+        ChecksumAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.header_name() == name.to_lowercase())
+    }
+
+    // as named by x-amz-sdk-checksum-algorithm
+    pub fn from_name(name: &str) -> Option<ChecksumAlgorithm> {
+        match name.to_uppercase().as_str() {
+            "CRC32" => Some(ChecksumAlgorithm::Crc32),
+            "CRC32C" => Some(ChecksumAlgorithm::Crc32c),
+            "CRC64NVME" => Some(ChecksumAlgorithm::Crc64Nvme),
+            "SHA1" => Some(ChecksumAlgorithm::Sha1),
+            "SHA256" => Some(ChecksumAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Crc32(crc::Digest<'static, u32>),
+    Crc64(crc::Digest<'static, u64>),
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Hasher {
+        match algorithm {
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(CRC32.digest()),
+            ChecksumAlgorithm::Crc32c => Hasher::Crc32(CRC32C.digest()),
+            ChecksumAlgorithm::Crc64Nvme => Hasher::Crc64(CRC64NVME.digest()),
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Hasher::Md5(hasher) => hasher.update(bytes),
+            Hasher::Crc32(digest) => digest.update(bytes),
+            Hasher::Crc64(digest) => digest.update(bytes),
+            Hasher::Sha1(hasher) => hasher.update(bytes),
+            Hasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    // the crcs are given big endian
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Md5(hasher) => STANDARD.encode(hasher.finalize()),
+            Hasher::Crc32(digest) => STANDARD.encode(digest.finalize().to_be_bytes()),
+            Hasher::Crc64(digest) => STANDARD.encode(digest.finalize().to_be_bytes()),
+            Hasher::Sha1(hasher) => STANDARD.encode(hasher.finalize()),
+            Hasher::Sha256(hasher) => STANDARD.encode(hasher.finalize()),
+        }
+    }
+}
+
+struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    expected: Option<String>,
+    hasher: Hasher,
+}
+
+// Why an upload is refused, once its payload is read: its checksums or
+// the framing of an aws-chunked body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    BadDigest(ChecksumAlgorithm),
+    SignatureDoesNotMatch,
+    IncompleteBody,
+    MalformedChunk,
+}
+
+impl Rejection {
+    pub fn to_error(self) -> PayloadError {
+        PayloadError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the upload is rejected: {:?}", self),
+        ))
+    }
+}
+
+// what is known once the whole payload is read
+#[derive(Default)]
+struct Outcome {
+    computed: Vec<(ChecksumAlgorithm, String)>,
+    trailers: Vec<(String, String)>,
+    rejection: Option<Rejection>,
+}
+
+#[derive(Clone, Default)]
+pub struct Verification {
+    outcome: Rc<RefCell<Outcome>>,
+}
+
+impl Verification {
+    pub fn rejection(&self) -> Option<Rejection> {
+        self.outcome.borrow().rejection
+    }
+
+    pub fn reject(&self, rejection: Rejection) -> PayloadError {
+        self.outcome.borrow_mut().rejection = Some(rejection);
+        rejection.to_error()
+    }
+
+    // the trailing headers of an aws-chunked body
+    pub fn set_trailers(&self, trailers: Vec<(String, String)>) {
+        self.outcome.borrow_mut().trailers = trailers;
+    }
+
+    fn trailer(&self, name: &str) -> Option<String> {
+        self.outcome
+            .borrow()
+            .trailers
+            .iter()
+            .find(|(trailer, _)| trailer == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    // the checksums to return to the client, s3 does not return the md5
+    pub fn response_headers(&self) -> Vec<(&'static str, String)> {
+        self.outcome
+            .borrow()
+            .computed
+            .iter()
+            .filter(|(algorithm, _)| *algorithm != ChecksumAlgorithm::Md5)
+            .map(|(algorithm, checksum)| (algorithm.header_name(), checksum.clone()))
+            .collect()
+    }
+}
+
+// The checksums sent by the client are those of the clear content: they are
+// verified while it is read, before it is encrypted. On a mismatch, the payload
+// ends with an error instead, so the encrypted upload is never completed.
+pub struct VerifiedPayload<S> {
+    inner: S,
+    checksums: Vec<Checksum>,
+    verification: Verification,
+    ended: bool,
+}
+
+impl<S> VerifiedPayload<S> {
+    pub fn new(inner: S, headers: &HeaderMap, verification: Verification) -> VerifiedPayload<S> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim().to_string())
+        };
+
+        let mut checksums: Vec<Checksum> = ChecksumAlgorithm::ALL
+            .iter()
+            .filter_map(|algorithm| {
+                header(algorithm.header_name()).map(|expected| Checksum {
+                    algorithm: *algorithm,
+                    expected: Some(expected),
+                    hasher: Hasher::new(*algorithm),
+                })
+            })
+            .collect();
+
+        // the sdk can ask for a checksum it does not give, or give it
+        // after the content, as a trailer of an aws-chunked body
+        let requested = [
+            header("x-amz-sdk-checksum-algorithm")
+                .and_then(|name| ChecksumAlgorithm::from_name(&name)),
+            header("x-amz-trailer").and_then(|name| ChecksumAlgorithm::from_header_name(&name)),
+        ];
+
+        for algorithm in requested.iter().flatten().copied() {
+            if !checksums.iter().any(|c| c.algorithm == algorithm) {
+                checksums.push(Checksum {
+                    algorithm,
+                    expected: None,
+                    hasher: Hasher::new(algorithm),
+                });
+            }
+        }
+
+        VerifiedPayload {
+            inner,
+            checksums,
+            verification,
+            ended: false,
+        }
+    }
+
+    fn verify(&mut self) -> Result<(), PayloadError> {
+        let mut bad_digest = None;
+        let mut computed_checksums = vec![];
+
+        for checksum in std::mem::take(&mut self.checksums) {
+            let algorithm = checksum.algorithm;
+            let computed = checksum.hasher.finalize();
+            let expected = checksum
+                .expected
+                .or_else(|| self.verification.trailer(algorithm.header_name()));
+
+            if expected.is_some_and(|expected| expected != computed) {
+                bad_digest = Some(algorithm);
+            }
+
+            computed_checksums.push((algorithm, computed));
+        }
+
+        self.verification.outcome.borrow_mut().computed = computed_checksums;
+
+        match bad_digest {
+            Some(algorithm) => Err(self.verification.reject(Rejection::BadDigest(algorithm))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S> Stream for VerifiedPayload<S>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let payload = self.get_mut();
+
+        if payload.ended {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut payload.inner).poll_next(cx) {
+            Poll::Ready(Some(Ok(bytes))) => {
+                for checksum in payload.checksums.iter_mut() {
+                    checksum.hasher.update(&bytes);
+                }
+                Poll::Ready(Some(Ok(bytes)))
+            }
+            Poll::Ready(None) => {
+                payload.ended = true;
+                match payload.verify() {
+                    Ok(()) => Poll::Ready(None),
+                    Err(e) => Poll::Ready(Some(Err(e))),
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+    use futures::{stream, StreamExt};
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                HeaderName::from_static(name),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    async fn read(headers: &HeaderMap) -> (Vec<Result<Bytes, PayloadError>>, Verification) {
+        let content = stream::iter(vec![
+            Ok(Bytes::from_static(b"hello ")),
+            Ok(Bytes::from_static(b"world")),
+        ]);
+        let verification = Verification::default();
+        let payload = VerifiedPayload::new(content, headers, verification.clone());
+
+        (payload.collect().await, verification)
+    }
+
+    #[test]
+    fn the_crc64nvme_check() {
+        assert_eq!(CRC_64_NVME.check, CRC64NVME.checksum(b"123456789"));
+    }
+
+    #[actix_rt::test]
+    async fn verify_the_checksums() {
+        let (items, verification) = read(&headers(&[
+            ("content-md5", "XrY7u+Ae7tCTyyK7j1rNww=="),
+            ("x-amz-checksum-crc32", "DUoRhQ=="),
+            (
+                "x-amz-checksum-sha256",
+                "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=",
+            ),
+        ]))
+        .await;
+
+        assert_eq!(2, items.len());
+        assert!(items.iter().all(|item| item.is_ok()));
+        assert_eq!(None, verification.rejection());
+        assert_eq!(
+            vec![
+                ("x-amz-checksum-crc32", "DUoRhQ==".to_string()),
+                (
+                    "x-amz-checksum-sha256",
+                    "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=".to_string()
+                ),
+            ],
+            verification.response_headers()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn end_with_an_error_on_a_mismatch() {
+        let (items, verification) = read(&headers(&[("x-amz-checksum-crc32", "AAAAAA==")])).await;
+
+        assert_eq!(3, items.len());
+        assert!(items[2].is_err());
+        assert_eq!(
+            Some(Rejection::BadDigest(ChecksumAlgorithm::Crc32)),
+            verification.rejection()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn compute_the_checksum_asked_by_the_sdk() {
+        let (items, verification) =
+            read(&headers(&[("x-amz-sdk-checksum-algorithm", "CRC32")])).await;
+
+        assert!(items.iter().all(|item| item.is_ok()));
+        assert_eq!(
+            vec![("x-amz-checksum-crc32", "DUoRhQ==".to_string())],
+            verification.response_headers()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn verify_the_checksum_given_as_a_trailer() {
+        let verification = Verification::default();
+        let content = stream::iter(vec![Ok(Bytes::from_static(b"hello world"))]);
+        let headers = headers(&[("x-amz-trailer", "x-amz-checksum-crc32")]);
+
+        // set by the aws-chunked decoder, before the end of the content
+        verification.set_trailers(vec![(
+            "x-amz-checksum-crc32".to_string(),
+            "AAAAAA==".to_string(),
+        )]);
+
+        let items: Vec<_> = VerifiedPayload::new(content, &headers, verification.clone())
+            .collect()
+            .await;
+
+        assert!(items[1].is_err());
+        assert_eq!(
+            Some(Rejection::BadDigest(ChecksumAlgorithm::Crc32)),
+            verification.rejection()
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use actix_web::http::header::{
+    self, EntityTag, Header, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch, IfRange,
+    IfUnmodifiedSince,
+};
+use actix_web::HttpRequest;
+
+// The preconditions of a GET or a HEAD are evaluated by the proxy against the
+// clear etag: the upstream only knows the etag of the encrypted content.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    Passed,
+    NotModified,
+    Failed,
+}
+
+// in the order of rfc 9110, section 13.2.2
+pub fn evaluate(
+    req: &HttpRequest,
+    etag: Option<&EntityTag>,
+    last_modified: Option<HttpDate>,
+) -> Precondition {
+    if req.headers().contains_key(header::IF_MATCH) {
+        let matches = match IfMatch::parse(req) {
+            Ok(IfMatch::Any) => true,
+            Ok(IfMatch::Items(tags)) => {
+                etag.is_some_and(|etag| tags.iter().any(|tag| tag.strong_eq(etag)))
+            }
+            Err(_) => false,
+        };
+
+        if !matches {
+            return Precondition::Failed;
+        }
+    } else if let (Ok(IfUnmodifiedSince(since)), Some(last_modified)) =
+        (IfUnmodifiedSince::parse(req), last_modified)
+    {
+        if last_modified > since {
+            return Precondition::Failed;
+        }
+    }
+
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        let matches = match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => {
+                etag.is_some_and(|etag| tags.iter().any(|tag| tag.weak_eq(etag)))
+            }
+            Err(_) => false,
+        };
+
+        if matches {
+            return Precondition::NotModified;
+        }
+    } else if let (Ok(IfModifiedSince(since)), Some(last_modified)) =
+        (IfModifiedSince::parse(req), last_modified)
+    {
+        if last_modified <= since {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Passed
+}
+
+// a range is served only if the object did not change since If-Range
+pub fn range_applies(
+    req: &HttpRequest,
+    etag: Option<&EntityTag>,
+    last_modified: Option<HttpDate>,
+) -> bool {
+    if !req.headers().contains_key(header::IF_RANGE) {
+        return true;
+    }
+
+    match IfRange::parse(req) {
+        Ok(IfRange::EntityTag(tag)) => etag.is_some_and(|etag| tag.strong_eq(etag)),
+        Ok(IfRange::Date(date)) => last_modified == Some(date),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use std::time::{Duration, SystemTime};
+
+    fn etag() -> EntityTag {
+        EntityTag::new_strong("abc".to_string())
+    }
+
+    fn date(secs: u64) -> HttpDate {
+        HttpDate::from(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn evaluate_with(headers: &[(header::HeaderName, String)]) -> Precondition {
+        let mut req = TestRequest::get();
+        for header in headers {
+            req = req.insert_header(header.clone());
+        }
+
+        evaluate(&req.to_http_request(), Some(&etag()), Some(date(1000)))
+    }
+
+    #[test]
+    fn evaluate_the_preconditions() {
+        use Precondition::*;
+
+        assert_eq!(Passed, evaluate_with(&[]));
+
+        assert_eq!(
+            Passed,
+            evaluate_with(&[(header::IF_MATCH, "\"abc\"".to_string())])
+        );
+        assert_eq!(
+            Failed,
+            evaluate_with(&[(header::IF_MATCH, "\"other\"".to_string())])
+        );
+        assert_eq!(
+            Passed,
+            evaluate_with(&[(header::IF_MATCH, "*".to_string())])
+        );
+
+        assert_eq!(
+            NotModified,
+            evaluate_with(&[(header::IF_NONE_MATCH, "\"other\", W/\"abc\"".to_string())])
+        );
+        assert_eq!(
+            Passed,
+            evaluate_with(&[(header::IF_NONE_MATCH, "\"other\"".to_string())])
+        );
+
+        assert_eq!(
+            NotModified,
+            evaluate_with(&[(header::IF_MODIFIED_SINCE, date(1000).to_string())])
+        );
+        assert_eq!(
+            Passed,
+            evaluate_with(&[(header::IF_MODIFIED_SINCE, date(999).to_string())])
+        );
+        assert_eq!(
+            Failed,
+            evaluate_with(&[(header::IF_UNMODIFIED_SINCE, date(999).to_string())])
+        );
+
+        // the etags take precedence over the dates
+        assert_eq!(
+            Passed,
+            evaluate_with(&[
+                (header::IF_MATCH, "\"abc\"".to_string()),
+                (header::IF_UNMODIFIED_SINCE, date(999).to_string())
+            ])
+        );
+        assert_eq!(
+            Passed,
+            evaluate_with(&[
+                (header::IF_NONE_MATCH, "\"other\"".to_string()),
+                (header::IF_MODIFIED_SINCE, date(1000).to_string())
+            ])
+        );
+
+        // an unknown etag matches nothing
+        let req = TestRequest::get()
+            .insert_header((header::IF_MATCH, "\"abc\""))
+            .to_http_request();
+        assert_eq!(Failed, evaluate(&req, None, None));
+    }
+
+    #[test]
+    fn apply_the_range_if_unchanged() {
+        let range_applies_with = |if_range: Option<String>| {
+            let mut req = TestRequest::get();
+            if let Some(if_range) = if_range {
+                req = req.insert_header((header::IF_RANGE, if_range));
+            }
+
+            range_applies(&req.to_http_request(), Some(&etag()), Some(date(1000)))
+        };
+
+        assert!(range_applies_with(None));
+        assert!(range_applies_with(Some("\"abc\"".to_string())));
+        assert!(!range_applies_with(Some("\"other\"".to_string())));
+        assert!(!range_applies_with(Some("W/\"abc\"".to_string())));
+        assert!(range_applies_with(Some(date(1000).to_string())));
+        assert!(!range_applies_with(Some(date(999).to_string())));
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// beyond, the oldest objects are forgotten
+const MAX_KNOWN_OBJECTS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownObject {
+    pub etag: String,
+    pub size: usize,
+}
+
+// The clear etag and size of the objects written through the proxy, by the etag
+// the upstream gave to their encrypted content: a listing only holds the latter.
+// They are kept in memory, an object written by another instance or before
+// a restart is not known.
+pub struct KnownObjects {
+    objects: Mutex<Objects>,
+}
+
+#[derive(Default)]
+struct Objects {
+    by_upstream_etag: HashMap<String, KnownObject>,
+    insertion_order: VecDeque<String>,
+}
+
+impl KnownObjects {
+    pub fn new() -> KnownObjects {
+        KnownObjects {
+            objects: Mutex::new(Objects::default()),
+        }
+    }
+
+    pub fn insert(&self, upstream_etag: &str, object: KnownObject) {
+        let mut objects = self.objects.lock().unwrap();
+
+        if objects
+            .by_upstream_etag
+            .insert(upstream_etag.to_string(), object)
+            .is_none()
+        {
+            objects.insertion_order.push_back(upstream_etag.to_string());
+        }
+
+        if objects.insertion_order.len() > MAX_KNOWN_OBJECTS {
+            if let Some(oldest) = objects.insertion_order.pop_front() {
+                objects.by_upstream_etag.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, upstream_etag: &str) -> Option<KnownObject> {
+        self.objects
+            .lock()
+            .unwrap()
+            .by_upstream_etag
+            .get(upstream_etag)
+            .cloned()
+    }
+}
+
+impl Default for KnownObjects {
+    fn default() -> Self {
+        KnownObjects::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_objects_are_forgotten() {
+        let known_objects = KnownObjects::new();
+        let object = |i: usize| KnownObject {
+            etag: format!("clear-{}", i),
+            size: i,
+        };
+
+        for i in 0..=MAX_KNOWN_OBJECTS {
+            known_objects.insert(&i.to_string(), object(i));
+        }
+
+        assert_eq!(None, known_objects.get("0"));
+        assert_eq!(Some(object(1)), known_objects.get("1"));
+        assert_eq!(
+            Some(object(MAX_KNOWN_OBJECTS)),
+            known_objects.get(&MAX_KNOWN_OBJECTS.to_string())
+        );
+    }
+}
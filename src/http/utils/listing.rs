@@ -0,0 +1,208 @@
+use super::query_param;
+use super::xml::*;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::HttpRequest;
+use serde_json::Value;
+
+// The listings of a bucket or a container give the size and the etag
+// of the encrypted objects, they are rewritten with the clear ones.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Listing {
+    // ListObjects and ListObjectsV2, in xml
+    S3,
+    // a container listed with ?format=json
+    Swift,
+}
+
+// ListObjects (V1) has no list-type, a path-style bucket is named like an object
+static LIST_OBJECTS_PARAMS: [&str; 5] =
+    ["prefix", "delimiter", "marker", "max-keys", "encoding-type"];
+
+impl Listing {
+    // only the responses to a listing are read whole, not the objects
+    pub fn requested(req: &HttpRequest) -> Option<Listing> {
+        let query = req.query_string();
+
+        if query_param(query, "format").as_deref() == Some("json") {
+            Some(Listing::Swift)
+        } else if query_param(query, "list-type").is_some()
+            || LIST_OBJECTS_PARAMS
+                .iter()
+                .any(|param| query_param(query, param).is_some())
+            || req.match_info().get("name").unwrap_or_default().is_empty()
+        {
+            Some(Listing::S3)
+        } else {
+            None
+        }
+    }
+
+    // The query can be given to an object as well, Swift ignores `format` for
+    // them: the response tells. An object has an etag, a container its count.
+    pub fn answered_by(&self, headers: &HeaderMap) -> bool {
+        match self {
+            Listing::S3 => !headers.contains_key(header::ETAG),
+            Listing::Swift => headers.contains_key("x-container-object-count"),
+        }
+    }
+
+    // `clear` gives the size and etag of an object from its encrypted ones;
+    // a body which is not a listing is left as it is
+    pub fn rewrite(
+        &self,
+        body: &[u8],
+        clear: impl Fn(usize, &str) -> (usize, String),
+    ) -> Option<Vec<u8>> {
+        match self {
+            Listing::S3 => {
+                rewrite_s3(&String::from_utf8_lossy(body), clear).map(String::into_bytes)
+            }
+            Listing::Swift => rewrite_swift(body, clear),
+        }
+    }
+}
+
+fn rewrite_s3(xml: &str, clear: impl Fn(usize, &str) -> (usize, String)) -> Option<String> {
+    if !xml.contains("<ListBucketResult") {
+        return None;
+    }
+
+    map_elements(xml, "Contents", |object| {
+        let (Some(size), Some(etag)) = (
+            element_text(object, "Size").and_then(|size| size.trim().parse::<usize>().ok()),
+            element_text(object, "ETag"),
+        ) else {
+            return Some(object.to_string());
+        };
+
+        let (size, etag) = clear(size, unquote(etag));
+
+        let object = replace_element(object, "Size", &size.to_string());
+        Some(replace_element(
+            &object,
+            "ETag",
+            &format!("&quot;{}&quot;", etag),
+        ))
+    })
+}
+
+fn rewrite_swift(json: &[u8], clear: impl Fn(usize, &str) -> (usize, String)) -> Option<Vec<u8>> {
+    let mut objects: Vec<Value> = serde_json::from_slice(json).ok()?;
+
+    // the pseudo directories only have a subdir
+    for object in objects.iter_mut() {
+        let (Some(size), Some(etag)) = (
+            object.get("bytes").and_then(Value::as_u64),
+            object.get("hash").and_then(Value::as_str),
+        ) else {
+            continue;
+        };
+
+        let (size, etag) = clear(size as usize, etag);
+
+        object["bytes"] = Value::from(size);
+        object["hash"] = Value::from(etag);
+    }
+
+    serde_json::to_vec(&objects).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn clear(size: usize, etag: &str) -> (usize, String) {
+        (size - 100, format!("clear-{}", etag))
+    }
+
+    #[test]
+    fn recognize_the_listings() {
+        let listing = |uri: &str, name: &str| {
+            let req = TestRequest::get()
+                .uri(uri)
+                .param("name", name.to_string())
+                .to_http_request();
+            Listing::requested(&req)
+        };
+
+        assert_eq!(Some(Listing::S3), listing("/upstream?list-type=2", ""));
+        assert_eq!(
+            Some(Listing::S3),
+            listing("/upstream/bucket?list-type=2", "bucket")
+        );
+        assert_eq!(
+            Some(Listing::Swift),
+            listing("/upstream/container?format=json", "container")
+        );
+        assert_eq!(
+            Some(Listing::S3),
+            listing("/upstream/bucket?prefix=", "bucket")
+        );
+        assert_eq!(
+            Some(Listing::S3),
+            listing("/upstream/bucket?delimiter=%2F&marker=a", "bucket")
+        );
+        assert_eq!(None, listing("/upstream/file.pdf", "file.pdf"));
+        assert_eq!(None, listing("/upstream/file.pdf?versionId=1", "file.pdf"));
+    }
+
+    #[test]
+    fn an_object_is_not_a_listing() {
+        let headers = |name: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::HeaderName::from_static(name),
+                header::HeaderValue::from_static("1"),
+            );
+            headers
+        };
+
+        assert!(Listing::S3.answered_by(&headers("x-amz-request-id")));
+        assert!(!Listing::S3.answered_by(&headers("etag")));
+        assert!(Listing::Swift.answered_by(&headers("x-container-object-count")));
+        assert!(!Listing::Swift.answered_by(&headers("etag")));
+    }
+
+    #[test]
+    fn rewrite_an_s3_listing() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <ListBucketResult><Name>bucket</Name><KeyCount>2</KeyCount>\
+            <Contents><Key>a</Key><ETag>&quot;abc&quot;</ETag><Size>1100</Size></Contents>\
+            <Contents><Key>b</Key><ETag>\"def\"</ETag><Size>200</Size></Contents>\
+            <CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes>\
+            </ListBucketResult>";
+
+        let rewritten = Listing::S3.rewrite(xml.as_bytes(), clear).unwrap();
+
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <ListBucketResult><Name>bucket</Name><KeyCount>2</KeyCount>\
+            <Contents><Key>a</Key><ETag>&quot;clear-abc&quot;</ETag><Size>1000</Size></Contents>\
+            <Contents><Key>b</Key><ETag>&quot;clear-def&quot;</ETag><Size>100</Size></Contents>\
+            <CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes>\
+            </ListBucketResult>",
+            String::from_utf8(rewritten).unwrap()
+        );
+
+        assert_eq!(None, Listing::S3.rewrite(b"<Error></Error>", clear));
+    }
+
+    #[test]
+    fn rewrite_a_swift_listing() {
+        let json = r#"[
+            {"name": "a", "bytes": 1100, "hash": "abc", "content_type": "text/plain"},
+            {"subdir": "dir/"}
+        ]"#;
+
+        let rewritten = Listing::Swift.rewrite(json.as_bytes(), clear).unwrap();
+        let objects: Vec<Value> = serde_json::from_slice(&rewritten).unwrap();
+
+        assert_eq!(1000, objects[0]["bytes"]);
+        assert_eq!("clear-abc", objects[0]["hash"]);
+        assert_eq!("text/plain", objects[0]["content_type"]);
+        assert_eq!("dir/", objects[1]["subdir"]);
+
+        assert_eq!(None, Listing::Swift.rewrite(b"a\nb\n", clear));
+    }
+}
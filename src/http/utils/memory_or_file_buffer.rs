@@ -10,24 +10,24 @@ use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 
-const MAX_IN_MEMORY_FILE_SIZE: usize = 10 * 1024 * 1024;
-
 pub struct MemoryOrFileBuffer {
     file: Option<File>,
     buf: BytesMut,
     filepath: PathBuf,
     sha256_hasher: Box<dyn DynDigest>,
     output_len: u64,
+    max_in_memory_size: usize,
 }
 
 impl MemoryOrFileBuffer {
-    pub fn new(filepath: PathBuf) -> MemoryOrFileBuffer {
+    pub fn new(filepath: PathBuf, max_in_memory_size: usize) -> MemoryOrFileBuffer {
         MemoryOrFileBuffer {
             file: None,
             buf: BytesMut::new(),
             filepath,
             sha256_hasher: Box::new(Sha256::new()),
             output_len: 0,
+            max_in_memory_size,
         }
     }
 
@@ -37,7 +37,7 @@ impl MemoryOrFileBuffer {
 
         match &mut self.file {
             None => {
-                if self.buf.len() < MAX_IN_MEMORY_FILE_SIZE {
+                if self.buf.len() < self.max_in_memory_size {
                     log::info!("going memory");
                     self.buf.extend_from_slice(&bytes);
                 } else {
@@ -62,10 +62,9 @@ impl MemoryOrFileBuffer {
         }
     }
 
-    pub async fn as_stream(
-        &mut self,
-    ) -> Box<dyn Stream<Item = Result<bytes::Bytes, Error>> + Unpin> {
-        match &mut self.file {
+    // each stream reads the buffer from the start, one at a time, to replay it
+    pub async fn as_stream(&self) -> Box<dyn Stream<Item = Result<bytes::Bytes, Error>> + Unpin> {
+        match &self.file {
             Some(f) => {
                 let mut f2 = f.try_clone().await.unwrap();
                 f2.rewind().await.unwrap();
@@ -1,9 +1,17 @@
 use actix_web::http::{header, header::HeaderMap};
 
+pub mod aws_chunked;
 pub mod aws_helper;
+pub mod checksum;
+pub mod conditional;
+pub mod known_objects;
+pub mod listing;
 pub mod memory_or_file_buffer;
+pub mod multipart;
 pub mod partial_extractor;
+pub mod upstream_clients;
 pub mod verify_signature;
+pub mod xml;
 
 pub fn content_length(headers: &HeaderMap) -> Option<usize> {
     headers
@@ -11,3 +19,9 @@ pub fn content_length(headers: &HeaderMap) -> Option<usize> {
         .and_then(|l| l.to_str().ok())
         .and_then(|s| s.parse::<usize>().ok())
 }
+
+pub fn query_param(query: &str, name: &str) -> Option<String> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
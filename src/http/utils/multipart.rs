@@ -0,0 +1,133 @@
+use super::query_param;
+use actix_web::guard::GuardContext;
+use data_encoding::HEXLOWER;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// The encrypted parts are spooled on disk while they are sent, so that they can
+// be sent again: one file per part, removed once sent or left by a crash.
+pub struct MultipartSpool {
+    directory: PathBuf,
+}
+
+impl MultipartSpool {
+    pub fn new(local_encryption_directory: &Path) -> MultipartSpool {
+        MultipartSpool {
+            directory: local_encryption_directory.join("multipart"),
+        }
+    }
+
+    // the upload id is chosen by the upstream, it is not used as a path
+    pub fn spool_path(&self, upload_id: &str, part_number: u32) -> PathBuf {
+        let upload_hash = HEXLOWER.encode(&Sha256::digest(upload_id.as_bytes()));
+
+        self.directory
+            .join(format!("{}-{}.part", upload_hash, part_number))
+    }
+
+    pub async fn create_directory(&self) -> io::Result<()> {
+        tokio::fs::create_dir_all(&self.directory).await
+    }
+
+    // the parts left by a crash, and the directories of the uploads
+    // which were kept on disk by the previous versions
+    pub async fn remove_stale_parts(&self, max_age: Duration) -> io::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let stale = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age > max_age);
+
+            if !stale {
+                continue;
+            }
+
+            let removed = if metadata.is_dir() {
+                tokio::fs::remove_dir_all(entry.path()).await
+            } else {
+                tokio::fs::remove_file(entry.path()).await
+            };
+
+            match removed {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => log::info!("removed the stale multipart part {:?}", entry.path()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// UploadPart, CompleteMultipartUpload, ListParts and AbortMultipartUpload
+// target an upload in progress
+pub fn is_multipart_upload(ctx: &GuardContext) -> bool {
+    query_param(ctx.head().uri.query().unwrap_or_default(), "uploadId").is_some()
+}
+
+// the etag given by s3 to a multipart object: the md5 of the md5 of its parts
+pub fn multipart_etag(md5s: &[String]) -> String {
+    let mut hasher = Md5::new();
+
+    for md5 in md5s {
+        hasher.update(HEXLOWER.decode(md5.as_bytes()).unwrap_or_default());
+    }
+
+    format!("{}-{}", HEXLOWER.encode(&hasher.finalize()), md5s.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multipart_store::STALE_UPLOAD_AGE;
+
+    #[test]
+    fn the_etag_of_a_multipart_object() {
+        let md5s = vec![
+            HEXLOWER.encode(&Md5::digest(b"first part")),
+            HEXLOWER.encode(&Md5::digest(b"second part")),
+        ];
+
+        let mut concatenated = Md5::digest(b"first part").to_vec();
+        concatenated.extend(Md5::digest(b"second part"));
+
+        assert_eq!(
+            format!("{}-2", HEXLOWER.encode(&Md5::digest(&concatenated))),
+            multipart_etag(&md5s)
+        );
+    }
+
+    #[test]
+    fn read_the_query() {
+        let query = "partNumber=3&uploadId=a%2Bb%3D";
+
+        assert_eq!(Some("a+b=".to_string()), query_param(query, "uploadId"));
+        assert_eq!(Some("3".to_string()), query_param(query, "partNumber"));
+        assert_eq!(None, query_param(query, "uploads"));
+    }
+
+    #[actix_rt::test]
+    async fn remove_the_stale_parts() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let spool = MultipartSpool::new(temp.path());
+        spool.create_directory().await.unwrap();
+
+        let path = spool.spool_path("upload/id", 1);
+        assert_ne!(path, spool.spool_path("upload/id", 2));
+        assert_ne!(path, spool.spool_path("another", 1));
+        tokio::fs::write(&path, b"part").await.unwrap();
+
+        spool.remove_stale_parts(STALE_UPLOAD_AGE).await.unwrap();
+        assert!(path.exists());
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        spool.remove_stale_parts(Duration::ZERO).await.unwrap();
+        assert!(!path.exists());
+    }
+}
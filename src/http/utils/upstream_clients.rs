@@ -0,0 +1,60 @@
+use crate::config::{HttpConfig, Upstream};
+use crate::egress_proxy::EgressConnector;
+use actix_http::RequestHead;
+use awc::{Client, ClientRequest};
+use std::collections::HashMap;
+
+// each upstream has its own tls settings and timeouts, thus its own client
+pub struct UpstreamClients {
+    clients: HashMap<String, Client>,
+}
+
+impl UpstreamClients {
+    pub fn new(config: &HttpConfig) -> UpstreamClients {
+        let clients = config
+            .upstreams()
+            .map(|upstream| (upstream.prefix.clone(), create_client(upstream)))
+            .collect();
+
+        UpstreamClients { clients }
+    }
+
+    pub fn for_upstream(&self, upstream: &Upstream) -> &Client {
+        &self.clients[&upstream.prefix]
+    }
+
+    // the connection goes back to the pool of the upstream, unless forced closed
+    pub fn request_from(
+        &self,
+        upstream: &Upstream,
+        url: String,
+        head: &RequestHead,
+    ) -> ClientRequest {
+        let request = self.for_upstream(upstream).request_from(url, head);
+
+        if upstream.force_close {
+            request.force_close()
+        } else {
+            request
+        }
+    }
+}
+
+pub fn create_client(upstream: &Upstream) -> Client {
+    let mut awc_connector = awc::Connector::new()
+        .connector(EgressConnector::new(upstream.egress_proxy.clone()))
+        .timeout(upstream.backend_connection_timeout) // max time to connect to remote host including dns name resolution, and tunneling through the egress proxy
+        .limit(upstream.max_connections)
+        .conn_keep_alive(upstream.idle_timeout);
+    // checked when the configuration is created
+    let ssl_connector = upstream
+        .tls
+        .ssl_connector(upstream.verify_ssl_certificate)
+        .expect("the upstream tls settings are valid");
+    awc_connector = awc_connector.openssl(ssl_connector);
+
+    awc::Client::builder()
+        .connector(awc_connector)
+        .timeout(upstream.response_timeout) // the total time before a response must be received
+        .finish()
+}
@@ -0,0 +1,114 @@
+// The xml documents of the s3 api rewritten by the proxy are flat,
+// the few elements it rewrites are found by their tags.
+
+// the etags are quoted, escaped or not in the xml documents
+pub fn unquote(etag: &str) -> &str {
+    let etag = etag.trim();
+    let etag = etag
+        .strip_prefix("&quot;")
+        .and_then(|e| e.strip_suffix("&quot;"))
+        .unwrap_or(etag);
+
+    etag.trim_matches('"')
+}
+
+pub fn element_text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+
+    Some(&xml[start..end])
+}
+
+pub fn replace_element(xml: &str, name: &str, text: &str) -> String {
+    let open = format!("<{}>", name);
+
+    match element_text(xml, name) {
+        Some(current) => {
+            let start = xml.find(&open).unwrap() + open.len();
+            format!("{}{}{}", &xml[..start], text, &xml[start + current.len()..])
+        }
+        None => xml.to_string(),
+    }
+}
+
+pub fn remove_element(xml: &str, name: &str) -> String {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+
+    match (xml.find(&open), element_text(xml, name)) {
+        (Some(start), Some(text)) => {
+            let end = start + open.len() + text.len() + close.len();
+            format!("{}{}", &xml[..start], &xml[end..])
+        }
+        _ => xml.to_string(),
+    }
+}
+
+// rewrites the content of each element, or fails when one cannot be
+pub fn map_elements(
+    xml: &str,
+    name: &str,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+
+    let mut rewritten = String::with_capacity(xml.len());
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        let content_start = start + open.len();
+        let content_end = content_start + rest[content_start..].find(&close)?;
+
+        rewritten.push_str(&rest[..content_start]);
+        rewritten.push_str(&rewrite(&rest[content_start..content_end])?);
+        rewritten.push_str(&close);
+
+        rest = &rest[content_end + close.len()..];
+    }
+
+    rewritten.push_str(rest);
+    Some(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquote_the_etags() {
+        assert_eq!("abc", unquote("\"abc\""));
+        assert_eq!("abc", unquote("&quot;abc&quot;"));
+        assert_eq!("abc", unquote(" abc "));
+    }
+
+    #[test]
+    fn rewrite_the_parts() {
+        let xml = "<ListPartsResult><PartNumberMarker>0</PartNumberMarker>\
+            <Part><PartNumber>1</PartNumber><ETag>&quot;a&quot;</ETag><Size>10</Size></Part>\
+            <Part><PartNumber>2</PartNumber><ETag>&quot;b&quot;</ETag><Size>20</Size></Part>\
+            </ListPartsResult>";
+
+        let rewritten = map_elements(xml, "Part", |part| {
+            let number = element_text(part, "PartNumber")?;
+            let part = replace_element(part, "Size", number);
+            Some(remove_element(&part, "ETag"))
+        });
+
+        assert_eq!(
+            Some(
+                "<ListPartsResult><PartNumberMarker>0</PartNumberMarker>\
+                <Part><PartNumber>1</PartNumber><Size>1</Size></Part>\
+                <Part><PartNumber>2</PartNumber><Size>2</Size></Part>\
+                </ListPartsResult>"
+                    .to_string()
+            ),
+            rewritten
+        );
+
+        assert_eq!(None, map_elements(xml, "Part", |_| None));
+    }
+}
@@ -1,27 +1,211 @@
+use super::secure_memory::{zeroize, SecureBuffer};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
 use log::trace;
-use sodiumoxide::crypto::secretstream::xchacha20poly1305::Key;
+use serde::Serialize;
+use sodiumoxide::crypto::generichash;
+use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Key, KEYBYTES};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
 
+const FINGERPRINT_CONTEXT: &[u8] = b"ds_proxy key fingerprint";
+const FINGERPRINT_SIZE: usize = 16;
+
+// the content is shared between the clones, so that
+// a reloaded keyring is seen by every worker at once
 #[derive(Debug, Clone)]
 pub struct Keyring {
-    keys: HashMap<u64, Key>,
+    content: Arc<RwLock<KeyringContent>>,
+}
+
+// the keys are stored one after the other in a single locked allocation,
+// and copied out only for the time they are used
+#[derive(Debug)]
+struct KeyringContent {
+    positions: HashMap<u64, usize>,
+    material: Arc<SecureBuffer>,
+    created_at: HashMap<u64, DateTime<Utc>>,
+}
+
+impl KeyringContent {
+    fn key(&self, id: &u64) -> Option<Key> {
+        self.positions.get(id).map(|position| {
+            let start = position * KEYBYTES;
+            Key::from_slice(&self.material.as_slice()[start..start + KEYBYTES]).unwrap()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyState {
+    // used to encrypt new files
+    Active,
+    // only used to decrypt files encrypted before a newer key was added
+    DecryptOnly,
+}
+
+impl fmt::Display for KeyState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyState::Active => write!(f, "active"),
+            KeyState::DecryptOnly => write!(f, "decrypt-only"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyDescription {
+    pub id: u64,
+    pub state: KeyState,
+    pub created_at: Option<DateTime<Utc>>,
+    pub fingerprint: String,
 }
 
 impl Keyring {
+    // the given keys are zeroed when dropped
     pub fn new(keys: HashMap<u64, Key>) -> Keyring {
-        Keyring { keys }
+        let mut positions = HashMap::new();
+        let mut plain_material = Vec::with_capacity(keys.len() * KEYBYTES);
+
+        for (position, (id, key)) in keys.iter().enumerate() {
+            positions.insert(*id, position);
+            plain_material.extend_from_slice(&key.0);
+        }
+
+        let material = Arc::new(SecureBuffer::new(&plain_material));
+        zeroize(&mut plain_material);
+
+        Keyring {
+            content: Arc::new(RwLock::new(KeyringContent {
+                positions,
+                material,
+                created_at: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn with_creation_dates(self, created_at: HashMap<u64, DateTime<Utc>>) -> Keyring {
+        self.content.write().unwrap().created_at = created_at;
+        self
+    }
+
+    // replaces the keys of this keyring, and of all its clones,
+    // by the ones of another keyring
+    pub fn replace_with(&self, other: Keyring) {
+        let other_content = other.content.read().unwrap();
+        let mut content = self.content.write().unwrap();
+
+        content.positions = other_content.positions.clone();
+        content.material = other_content.material.clone();
+        content.created_at = other_content.created_at.clone();
     }
 
     pub fn get_last_key(&self) -> Option<(u64, Key)> {
-        if let Some(id) = self.keys.keys().max() {
+        let content = self.content.read().unwrap();
+
+        if let Some(id) = content.positions.keys().max() {
             trace!("returning key_id {} as last_key", id);
-            self.get_key_by_id(id).map(|k| (*id, k))
+            content.key(id).map(|k| (*id, k))
         } else {
             None
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.content.read().unwrap().positions.is_empty()
+    }
+
     pub fn get_key_by_id(&self, id: &u64) -> Option<Key> {
-        self.keys.get(id).map(|k| k.to_owned())
+        self.content.read().unwrap().key(id)
+    }
+
+    // describes the keys, sorted by id, without revealing any key material
+    pub fn describe_keys(&self) -> Vec<KeyDescription> {
+        let content = self.content.read().unwrap();
+        let last_id = content.positions.keys().max();
+
+        let mut descriptions: Vec<KeyDescription> = content
+            .positions
+            .keys()
+            .map(|id| (id, content.key(id).unwrap()))
+            .map(|(id, key)| KeyDescription {
+                id: *id,
+                state: if Some(id) == last_id {
+                    KeyState::Active
+                } else {
+                    KeyState::DecryptOnly
+                },
+                created_at: content.created_at.get(id).copied(),
+                fingerprint: fingerprint(&key),
+            })
+            .collect();
+
+        descriptions.sort_by_key(|description| description.id);
+        descriptions
+    }
+}
+
+// a hash keyed by the key itself: identical keys give identical fingerprints
+// in every environment, and it cannot be computed without the key
+pub fn fingerprint(key: &Key) -> String {
+    let mut state = generichash::State::new(Some(FINGERPRINT_SIZE), Some(&key.0)).unwrap();
+    state.update(FINGERPRINT_CONTEXT).unwrap();
+    HEXLOWER.encode(state.finalize().unwrap().as_ref())
+}
+
+impl fmt::Display for KeyDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let created_at = self
+            .created_at
+            .map(|date| date.to_rfc3339())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{}",
+            self.id, self.state, created_at, self.fingerprint
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_keys() {
+        let keys = HashMap::from([(0, Key([1; 32])), (2, Key([2; 32])), (1, Key([1; 32]))]);
+        let date = Utc::now();
+        let keyring = Keyring::new(keys).with_creation_dates(HashMap::from([(2, date)]));
+
+        let descriptions = keyring.describe_keys();
+
+        assert_eq!(
+            vec![0, 1, 2],
+            descriptions.iter().map(|d| d.id).collect::<Vec<u64>>()
+        );
+        assert_eq!(KeyState::DecryptOnly, descriptions[0].state);
+        assert_eq!(KeyState::Active, descriptions[2].state);
+        assert_eq!(None, descriptions[0].created_at);
+        assert_eq!(Some(date), descriptions[2].created_at);
+
+        assert_eq!(descriptions[0].fingerprint, descriptions[1].fingerprint);
+        assert_ne!(descriptions[0].fingerprint, descriptions[2].fingerprint);
+        assert_eq!(2 * FINGERPRINT_SIZE, descriptions[0].fingerprint.len());
+    }
+
+    #[test]
+    fn replace_with_updates_every_clone() {
+        let keyring = Keyring::new(HashMap::from([(0, Key([0; 32]))]));
+        let clone = keyring.clone();
+
+        keyring.replace_with(Keyring::new(HashMap::from([
+            (0, Key([0; 32])),
+            (1, Key([1; 32])),
+        ])));
+
+        assert_eq!(Some(1), clone.get_last_key().map(|(id, _)| id));
     }
 }
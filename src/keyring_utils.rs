@@ -1,43 +1,322 @@
 use super::keyring::Keyring;
+use super::secure_memory::{zeroize, SecureBuffer};
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::auth::hmacsha256;
+use sodiumoxide::crypto::generichash;
 use sodiumoxide::crypto::pwhash;
 use sodiumoxide::crypto::pwhash::scryptsalsa208sha256::Salt;
 use sodiumoxide::crypto::secretbox;
 use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Key, KEYBYTES};
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::sync::Arc;
+use std::time::Duration;
 
-pub fn load_keyring(keyring_file: &str, master_password: String, salt: String) -> Keyring {
-    let master_key = build_master_key(master_password, salt);
+// domain separation between the key used to cipher the keyring entries
+// and the one used to authenticate the whole file
+const MAC_KEY_CONTEXT: &[u8] = b"ds_proxy keyring mac";
 
-    let hash_map = load_secrets(keyring_file)
+pub fn load_keyring(
+    keyring_file: &str,
+    master_password: String,
+    salt: String,
+    keyring_state_file: Option<&str>,
+) -> Keyring {
+    KeyringFile::open(keyring_file, master_password, salt, keyring_state_file).load()
+}
+
+pub fn add_random_key_to_keyring(keyring_file: &str, master_password: String, salt: String) {
+    KeyringFile::open(keyring_file, master_password, salt, None)
+        .add_random_key()
+        .unwrap_or_else(|why| panic!("{}", why));
+}
+
+// a keyring file along with its master key, so that it can be
+// reloaded or updated without deriving the master key again
+#[derive(Debug, Clone)]
+pub struct KeyringFile {
+    path: String,
+    // kept in locked memory for the whole life of the process
+    master_key: Arc<SecureBuffer>,
+    state_file: Option<String>,
+}
+
+impl KeyringFile {
+    pub fn open(
+        keyring_file: &str,
+        master_password: String,
+        salt: String,
+        keyring_state_file: Option<&str>,
+    ) -> KeyringFile {
+        KeyringFile {
+            path: keyring_file.to_string(),
+            master_key: Arc::new(SecureBuffer::new(
+                &build_master_key(master_password, salt)[..],
+            )),
+            state_file: keyring_state_file.map(|f| f.to_string()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // a copy zeroed when dropped
+    fn master_key(&self) -> secretbox::Key {
+        secretbox::Key::from_slice(self.master_key.as_slice()).unwrap()
+    }
+
+    pub fn load(&self) -> Keyring {
+        self.try_load().unwrap_or_else(|why| panic!("{}", why))
+    }
+
+    pub fn try_load(&self) -> Result<Keyring, String> {
+        let secrets = load_secrets(&self.path)?;
+        let master_key = self.master_key();
+
+        self.verify_signature(&master_key, &secrets)?;
+        self.ensure_no_rollback(secrets.version)?;
+
+        let hash_map = secrets
+            .cipher_keyring
+            .iter()
+            .map(|(id, base64_cipher)| {
+                let byte_key = decode64(base64_cipher).and_then(|c| decrypt(&master_key, c))?;
+                Ok((to_u64(id), Key(byte_key)))
+            })
+            .collect::<Result<_, String>>()?;
+
+        let created_at = secrets
+            .created_at
+            .iter()
+            .map(|(id, date)| (to_u64(id), parse_date(date)))
+            .collect();
+
+        Ok(Keyring::new(hash_map).with_creation_dates(created_at))
+    }
+
+    pub fn add_random_key(&self) -> Result<(), String> {
+        let _lock = self.lock()?;
+        self.add_key(random_key())
+    }
+
+    // adds a new key if the active one is older than max_key_age,
+    // or if its creation date is unknown.
+    // The check is done under the lock so that, when several proxies
+    // share the keyring, only one of them rotates.
+    pub fn rotate_if_older_than(&self, max_key_age: Duration) -> Result<bool, String> {
+        let _lock = self.lock()?;
+
+        let secrets = load_secrets(&self.path)?;
+
+        let active_key_created_at = last_id(&secrets)
+            .and_then(|id| secrets.created_at.get(&id.to_string()))
+            .map(|date| parse_date(date));
+
+        let needs_rotation = match active_key_created_at {
+            Some(created_at) => {
+                let age = Utc::now().signed_duration_since(created_at);
+                age.to_std().unwrap_or_default() >= max_key_age
+            }
+            None => true,
+        };
+
+        if needs_rotation {
+            self.add_key(random_key())?;
+        }
+
+        Ok(needs_rotation)
+    }
+
+    // signs a keyring written before the mac was introduced, without
+    // adding a key. Every key must decipher with the master key.
+    pub fn sign(&self) -> Result<bool, String> {
+        let _lock = self.lock()?;
+
+        let mut secrets = load_secrets(&self.path)?;
+        let master_key = self.master_key();
+
+        if secrets.mac.is_some() {
+            self.verify_signature(&master_key, &secrets)?;
+            return Ok(false);
+        }
+
+        self.ensure_no_rollback(secrets.version)?;
+
+        for (id, base64_cipher) in &secrets.cipher_keyring {
+            let mut key = decode64(base64_cipher)
+                .and_then(|c| decrypt(&master_key, c))
+                .map_err(|why| format!("couldn't decipher the key {}: {}", id, why))?;
+            zeroize(&mut key);
+        }
+
+        secrets.version += 1;
+        secrets.mac = Some(compute_mac(&master_key, &secrets));
+
+        save_secrets(&self.path, &secrets)?;
+        self.ensure_no_rollback(secrets.version)?;
+
+        Ok(true)
+    }
+
+    // a new keyring is signed when its first key is added, an existing one
+    // must be signed, and must not be older than the last one seen
+    fn add_key(&self, key: [u8; 32]) -> Result<(), String> {
+        let master_key = self.master_key();
+        let new_base64_cipher = base64_cipher(&master_key, key);
+
+        let mut secrets = load_secrets(&self.path)?;
+
+        if !secrets.cipher_keyring.is_empty() || secrets.mac.is_some() {
+            self.verify_signature(&master_key, &secrets)?;
+        }
+
+        self.ensure_no_rollback(secrets.version)?;
+
+        let id = next_id(&secrets);
+        secrets.cipher_keyring.insert(id.clone(), new_base64_cipher);
+        secrets.created_at.insert(id, Utc::now().to_rfc3339());
+
+        secrets.version += 1;
+        secrets.mac = Some(compute_mac(&master_key, &secrets));
+
+        save_secrets(&self.path, &secrets)?;
+        self.ensure_no_rollback(secrets.version)
+    }
+
+    fn verify_signature(
+        &self,
+        master_key: &secretbox::Key,
+        secrets: &Secrets,
+    ) -> Result<(), String> {
+        if secrets.mac.is_none() {
+            return Err(format!(
+                "the keyring {} is not signed, sign it with `ds_proxy sign-keyring`",
+                self.path
+            ));
+        }
+
+        if !verify_mac(master_key, secrets) {
+            return Err(format!(
+                "the keyring {} has been tampered with or was not written with this password",
+                self.path
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn ensure_no_rollback(&self, version: u64) -> Result<(), String> {
+        match &self.state_file {
+            Some(state_file) => ensure_no_rollback(state_file, version),
+            None => Ok(()),
+        }
+    }
+
+    // exclusive lock on a sidecar file: the keyring itself
+    // is replaced on each write
+    fn lock(&self) -> Result<File, String> {
+        let lock_path = format!("{}.lock", self.path);
+
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|why| format!("couldn't open lock file {}: {}", lock_path, why))?;
+
+        lock_file
+            .lock()
+            .map_err(|why| format!("couldn't lock {}: {}", lock_path, why))?;
+
+        Ok(lock_file)
+    }
+}
+
+fn compute_mac(master_key: &secretbox::Key, secrets: &Secrets) -> String {
+    let tag = hmacsha256::authenticate(&mac_message(secrets), &mac_key(master_key));
+    STANDARD.encode(tag)
+}
+
+fn verify_mac(master_key: &secretbox::Key, secrets: &Secrets) -> bool {
+    let Some(tag) = secrets
+        .mac
+        .as_ref()
+        .and_then(|mac| STANDARD.decode(mac).ok())
+        .and_then(|mac| hmacsha256::Tag::from_slice(&mac))
+    else {
+        return false;
+    };
+
+    hmacsha256::verify(&tag, &mac_message(secrets), &mac_key(master_key))
+}
+
+fn mac_key(master_key: &secretbox::Key) -> hmacsha256::Key {
+    let mut state =
+        generichash::State::new(Some(hmacsha256::KEYBYTES), Some(&master_key[..])).unwrap();
+    state.update(MAC_KEY_CONTEXT).unwrap();
+    let digest = state.finalize().unwrap();
+
+    hmacsha256::Key::from_slice(digest.as_ref()).unwrap()
+}
+
+// canonical representation of the keyring:
+// the version followed by the entries sorted by id
+fn mac_message(secrets: &Secrets) -> Vec<u8> {
+    let mut entries: Vec<(u64, &String)> = secrets
         .cipher_keyring
         .iter()
-        .map(|(id, base64_cipher)| (to_u64(id), decode64(base64_cipher)))
-        .map(|(id, cipher)| (id, decrypt(&master_key, cipher)))
-        .map(|(id, byte_key)| (id, Key(byte_key)))
+        .map(|(id, cipher)| (to_u64(id), cipher))
         .collect();
+    entries.sort();
 
-    Keyring::new(hash_map)
-}
+    let mut message = format!("version:{}\n", secrets.version);
+    for (id, cipher) in entries {
+        message.push_str(&format!("{}:{}\n", id, cipher));
+    }
 
-pub fn add_random_key_to_keyring(keyring_file: &str, master_password: String, salt: String) {
-    let new_key = random_key();
-    let master_key = build_master_key(master_password, salt);
-    add_key(keyring_file, &master_key, new_key);
+    let mut dates: Vec<(u64, &String)> = secrets
+        .created_at
+        .iter()
+        .map(|(id, date)| (to_u64(id), date))
+        .collect();
+    dates.sort();
+
+    for (id, date) in dates {
+        message.push_str(&format!("created_at:{}:{}\n", id, date));
+    }
+
+    message.into_bytes()
 }
 
-fn add_key(keyring_file: &str, master_key: &secretbox::Key, key: [u8; 32]) {
-    let new_base64_cipher = base64_cipher(master_key, key);
+// the state file records the highest keyring version seen on this host
+// so that an older copy of the keyring cannot be put back in place
+fn ensure_no_rollback(state_file: &str, version: u64) -> Result<(), String> {
+    let last_seen_version = match std::fs::read_to_string(state_file) {
+        Ok(content) => content
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("the keyring state file {} is corrupted", state_file))?,
+        Err(_) => 0,
+    };
 
-    let mut secrets = load_secrets(keyring_file);
-    secrets
-        .cipher_keyring
-        .insert(next_id(&secrets), new_base64_cipher);
+    if version < last_seen_version {
+        return Err(format!(
+            "the keyring version {} is older than the last one seen ({}), refusing a rollback",
+            version, last_seen_version
+        ));
+    }
+
+    if last_seen_version < version {
+        write_atomically(state_file, &version.to_string())
+            .map_err(|why| format!("couldn't write keyring state file {}: {}", state_file, why))?;
+    }
 
-    save_secrets(keyring_file, &secrets)
+    Ok(())
 }
 
 fn random_key() -> [u8; 32] {
@@ -50,45 +329,96 @@ fn to_u64(id: &str) -> u64 {
     id.parse::<u64>().unwrap()
 }
 
-fn decode64(text: &str) -> Vec<u8> {
-    STANDARD.decode(text).unwrap()
+fn parse_date(date: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(date)
+        .unwrap_or_else(|_| panic!("invalid key creation date {}", date))
+        .with_timezone(&Utc)
 }
 
-fn load_secrets(keyring_file: &str) -> Secrets {
-    if let Ok(text_secrets) = std::fs::read_to_string(keyring_file) {
-        toml::from_str(&text_secrets).unwrap()
-    } else {
-        Secrets {
+fn decode64(text: &str) -> Result<Vec<u8>, String> {
+    STANDARD
+        .decode(text)
+        .map_err(|why| format!("a key is not encoded in base64: {}", why))
+}
+
+// the ids and the dates are checked here so that they can be used afterwards
+fn load_secrets(keyring_file: &str) -> Result<Secrets, String> {
+    let Ok(text_secrets) = std::fs::read_to_string(keyring_file) else {
+        return Ok(Secrets {
+            version: 0,
+            mac: None,
             cipher_keyring: HashMap::new(),
-        }
+            created_at: HashMap::new(),
+        });
+    };
+
+    let secrets: Secrets = toml::from_str(&text_secrets)
+        .map_err(|why| format!("invalid keyring {}: {}", keyring_file, why))?;
+
+    let ids = secrets
+        .cipher_keyring
+        .keys()
+        .chain(secrets.created_at.keys());
+    if let Some(id) = ids.into_iter().find(|id| id.parse::<u64>().is_err()) {
+        return Err(format!("invalid key id {} in {}", id, keyring_file));
     }
+
+    if let Some(date) = secrets
+        .created_at
+        .values()
+        .find(|date| DateTime::parse_from_rfc3339(date).is_err())
+    {
+        return Err(format!(
+            "invalid key creation date {} in {}",
+            date, keyring_file
+        ));
+    }
+
+    Ok(secrets)
 }
 
-fn decrypt(master_key: &secretbox::Key, nonce_cipher: Vec<u8>) -> [u8; KEYBYTES] {
+fn decrypt(master_key: &secretbox::Key, nonce_cipher: Vec<u8>) -> Result<[u8; KEYBYTES], String> {
+    if nonce_cipher.len() < secretbox::NONCEBYTES {
+        return Err("a key is truncated".to_string());
+    }
+
     let nonce = secretbox::Nonce::from_slice(&nonce_cipher[0..24]).unwrap();
     let cipher = &nonce_cipher[24..];
 
-    secretbox::open(cipher, &nonce, master_key)
-        .expect("could not decipher a key")
+    let mut key = secretbox::open(cipher, &nonce, master_key)
+        .map_err(|_| "could not decipher a key".to_string())?;
+
+    // the deciphered key is copied, then zeroed
+    let opened = key
+        .as_slice()
         .try_into()
-        .unwrap()
+        .map_err(|_| "a deciphered key has not the expected size".to_string());
+    zeroize(&mut key);
+
+    opened
 }
 
+// the password is zeroed once the master key is derived
 fn build_master_key(master_password: String, salt: String) -> secretbox::Key {
     let mut key = [0u8; KEYBYTES];
+    let mut master_password = master_password.into_bytes();
 
     let typed_salt = Salt::from_slice(salt.as_bytes()).unwrap();
 
     pwhash::derive_key(
         &mut key,
-        master_password.as_bytes(),
+        &master_password,
         &typed_salt,
         pwhash::OPSLIMIT_INTERACTIVE,
         pwhash::MEMLIMIT_INTERACTIVE,
     )
     .unwrap();
 
-    secretbox::Key::from_slice(&key).unwrap()
+    zeroize(&mut master_password);
+    let master_key = secretbox::Key::from_slice(&key).unwrap();
+    zeroize(&mut key);
+
+    master_key
 }
 
 fn next_id(secrets: &Secrets) -> String {
@@ -100,11 +430,8 @@ fn next_id(secrets: &Secrets) -> String {
 }
 
 fn last_id(secrets: &Secrets) -> Option<u64> {
-    secrets
-        .cipher_keyring
-        .keys()
-        .max()
-        .map(|x| x.parse::<u64>().unwrap())
+    // ids must be compared as numbers: "9" > "10"
+    secrets.cipher_keyring.keys().map(|x| to_u64(x)).max()
 }
 
 fn base64_cipher(master_key: &secretbox::Key, key: [u8; 32]) -> String {
@@ -129,13 +456,287 @@ fn concat(nonce: [u8; 24], mut cipher: Vec<u8>) -> Vec<u8> {
     serialized
 }
 
-fn save_secrets(keyring_file: &str, secrets: &Secrets) {
+fn save_secrets(keyring_file: &str, secrets: &Secrets) -> Result<(), String> {
     let text_secrets = toml::to_string(secrets).unwrap();
-    std::fs::write(keyring_file, text_secrets).unwrap()
+
+    write_atomically(keyring_file, &text_secrets)
+        .map_err(|why| format!("couldn't write keyring {}: {}", keyring_file, why))
+}
+
+// written to a temporary file then renamed, so that a proxy
+// reloading the keyring never reads a partially written file.
+// Only the owner can read it, as the file it replaces.
+fn write_atomically(file: &str, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let tmp_file = format!("{}.tmp", file);
+
+    // the mode is only given to a new file
+    match std::fs::remove_file(&tmp_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => (),
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp_file)?
+        .write_all(content.as_bytes())?;
+
+    std::fs::rename(&tmp_file, file)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct Secrets {
+    #[serde(default)]
+    version: u64,
+    mac: Option<String>,
     #[serde(rename = "keys")]
     cipher_keyring: HashMap<String, String>,
+    // keys added before the creation date was recorded have no entry
+    #[serde(default)]
+    created_at: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+
+    const PASSWORD: &str = "plop";
+    const SALT: &str = "12345678901234567890123456789012";
+
+    fn keyring_path(temp: &assert_fs::TempDir) -> String {
+        temp.child("keyring.toml")
+            .path()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn add_key_signs_and_versions_the_keyring() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_file = keyring_path(&temp);
+
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+
+        let secrets = load_secrets(&keyring_file).unwrap();
+        assert_eq!(2, secrets.version);
+
+        let master_key = build_master_key(PASSWORD.to_string(), SALT.to_string());
+        assert!(verify_mac(&master_key, &secrets));
+
+        let keyring = load_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string(), None);
+        assert_eq!(Some(1), keyring.get_last_key().map(|(id, _)| id));
+    }
+
+    #[test]
+    fn removing_a_key_breaks_the_mac() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_file = keyring_path(&temp);
+
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+
+        let mut secrets = load_secrets(&keyring_file).unwrap();
+        secrets.cipher_keyring.remove("1");
+
+        let master_key = build_master_key(PASSWORD.to_string(), SALT.to_string());
+        assert!(!verify_mac(&master_key, &secrets));
+
+        secrets.version = 12;
+        secrets.cipher_keyring.clear();
+        assert!(!verify_mac(&master_key, &secrets));
+    }
+
+    #[test]
+    fn a_wrong_password_breaks_the_mac() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_file = keyring_path(&temp);
+
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+
+        let secrets = load_secrets(&keyring_file).unwrap();
+        let other_key = build_master_key("another".to_string(), SALT.to_string());
+        assert!(!verify_mac(&other_key, &secrets));
+    }
+
+    #[test]
+    #[should_panic(expected = "refusing a rollback")]
+    fn an_older_keyring_is_refused() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_file = keyring_path(&temp);
+        let state_file = temp.child("keyring.state");
+        let state_path = state_file.path().to_str().unwrap();
+
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+        let old_keyring = std::fs::read_to_string(&keyring_file).unwrap();
+
+        add_random_key_to_keyring(&keyring_file, PASSWORD.to_string(), SALT.to_string());
+        load_keyring(
+            &keyring_file,
+            PASSWORD.to_string(),
+            SALT.to_string(),
+            Some(state_path),
+        );
+        assert_eq!("2", std::fs::read_to_string(state_path).unwrap());
+
+        std::fs::write(&keyring_file, old_keyring).unwrap();
+        load_keyring(
+            &keyring_file,
+            PASSWORD.to_string(),
+            SALT.to_string(),
+            Some(state_path),
+        );
+    }
+
+    #[test]
+    fn rotate_if_older_than() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+        let keyring_file =
+            KeyringFile::open(&keyring_path, PASSWORD.to_string(), SALT.to_string(), None);
+
+        // no active key yet
+        assert_eq!(
+            Ok(true),
+            keyring_file.rotate_if_older_than(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            Ok(false),
+            keyring_file.rotate_if_older_than(Duration::from_secs(3600))
+        );
+        assert_eq!(Ok(true), keyring_file.rotate_if_older_than(Duration::ZERO));
+
+        let keyring = keyring_file.load();
+        assert_eq!(Some(1), keyring.get_last_key().map(|(id, _)| id));
+    }
+
+    #[test]
+    fn concurrent_rotations_add_a_single_key() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+        let keyring_file =
+            KeyringFile::open(&keyring_path, PASSWORD.to_string(), SALT.to_string(), None);
+
+        keyring_file.add_random_key().unwrap();
+        let mut secrets = load_secrets(&keyring_path).unwrap();
+        secrets.created_at.clear();
+        secrets.mac = Some(compute_mac(&keyring_file.master_key(), &secrets));
+        save_secrets(&keyring_path, &secrets).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let keyring_file = keyring_file.clone();
+                std::thread::spawn(move || {
+                    keyring_file
+                        .rotate_if_older_than(Duration::from_secs(3600))
+                        .unwrap()
+                })
+            })
+            .collect();
+
+        let rotations = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|rotated| *rotated)
+            .count();
+
+        assert_eq!(1, rotations);
+        assert_eq!(2, load_secrets(&keyring_path).unwrap().cipher_keyring.len());
+    }
+
+    #[test]
+    fn an_unsigned_keyring_can_be_signed() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+        let keyring_file =
+            KeyringFile::open(&keyring_path, PASSWORD.to_string(), SALT.to_string(), None);
+
+        keyring_file.add_random_key().unwrap();
+        let mut secrets = load_secrets(&keyring_path).unwrap();
+        secrets.mac = None;
+        save_secrets(&keyring_path, &secrets).unwrap();
+        assert!(keyring_file.try_load().is_err());
+
+        assert_eq!(Ok(true), keyring_file.sign());
+        assert_eq!(Ok(false), keyring_file.sign());
+
+        let keyring = keyring_file.load();
+        assert_eq!(1, load_secrets(&keyring_path).unwrap().cipher_keyring.len());
+        assert_eq!(Some(0), keyring.get_last_key().map(|(id, _)| id));
+    }
+
+    #[test]
+    fn adding_a_key_to_an_unsigned_keyring_is_refused() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+        let keyring_file =
+            KeyringFile::open(&keyring_path, PASSWORD.to_string(), SALT.to_string(), None);
+
+        keyring_file.add_random_key().unwrap();
+        let mut secrets = load_secrets(&keyring_path).unwrap();
+        secrets.mac = None;
+        save_secrets(&keyring_path, &secrets).unwrap();
+
+        let refused = keyring_file.add_random_key().unwrap_err();
+        assert!(refused.contains("is not signed"));
+    }
+
+    #[test]
+    fn adding_a_key_to_an_older_keyring_is_refused() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+        let state_file = temp.child("keyring.state");
+        let keyring_file = KeyringFile::open(
+            &keyring_path,
+            PASSWORD.to_string(),
+            SALT.to_string(),
+            state_file.path().to_str(),
+        );
+
+        keyring_file.add_random_key().unwrap();
+        let old_keyring = std::fs::read_to_string(&keyring_path).unwrap();
+        keyring_file.add_random_key().unwrap();
+        assert_eq!("2", std::fs::read_to_string(state_file.path()).unwrap());
+
+        std::fs::write(&keyring_path, old_keyring).unwrap();
+        let refused = keyring_file.add_random_key().unwrap_err();
+        assert!(refused.contains("refusing a rollback"));
+    }
+
+    #[test]
+    fn only_the_owner_can_read_the_keyring() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+
+        add_random_key_to_keyring(&keyring_path, PASSWORD.to_string(), SALT.to_string());
+        add_random_key_to_keyring(&keyring_path, PASSWORD.to_string(), SALT.to_string());
+
+        let mode = std::fs::metadata(&keyring_path)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn ids_are_compared_as_numbers() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let keyring_path = keyring_path(&temp);
+
+        for _ in 0..11 {
+            add_random_key_to_keyring(&keyring_path, PASSWORD.to_string(), SALT.to_string());
+        }
+
+        let secrets = load_secrets(&keyring_path).unwrap();
+        assert_eq!(11, secrets.cipher_keyring.len());
+        assert_eq!(Some(10), last_id(&secrets));
+    }
 }
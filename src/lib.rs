@@ -1,11 +1,22 @@
 pub mod args;
 pub mod aws_config;
+pub mod check_config;
+pub mod circuit_breaker;
 pub mod config;
+pub mod config_error;
 pub mod crypto;
+pub mod egress_proxy;
 pub mod file;
 pub mod http;
 pub mod keyring;
 pub mod keyring_utils;
+pub mod multipart_store;
 pub mod redis_config;
 pub mod redis_utils;
+pub mod retry;
+pub mod secure_memory;
+pub mod settings;
+pub mod shamir;
+pub mod tls;
+pub mod upstream_pool;
 pub mod write_once_service;
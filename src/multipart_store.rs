@@ -0,0 +1,92 @@
+use deadpool_redis::{redis::pipe, redis::AsyncCommands, Pool};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// What the proxy knows of an uploaded part, to present it as the client sent it:
+// the md5 of the clear part is its etag for the client, the etag of the
+// encrypted part is the one the upstream expects to complete the upload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartRecord {
+    pub md5: String,
+    pub upstream_etag: String,
+    pub size: usize,
+}
+
+// the uploads neither completed nor aborted are forgotten after a week without a part
+pub const STALE_UPLOAD_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// The parts of the multipart uploads in progress, kept in Redis between the
+// requests, so that every instance of the proxy can complete an upload:
+// one hash per upload, one field per part.
+#[derive(Clone)]
+pub struct MultipartStore {
+    pool: Pool,
+}
+
+impl MultipartStore {
+    pub fn new(pool: Pool) -> Self {
+        MultipartStore { pool }
+    }
+
+    // the upload id is chosen by the upstream, it is not used as a key
+    pub fn hash_key(upload_id: &str) -> String {
+        format!("multipart:{:x}", Sha256::digest(upload_id.as_bytes()))
+    }
+
+    // the expiration is pushed back by each part
+    pub async fn save_part(
+        &self,
+        upload_id: &str,
+        part_number: u32,
+        record: &PartRecord,
+    ) -> Result<(), String> {
+        let key = Self::hash_key(upload_id);
+        let record = serde_json::to_string(record).map_err(|e| e.to_string())?;
+        let mut conn = self.get_redis_connection().await?;
+
+        pipe()
+            .atomic()
+            .hset(&key, part_number, record)
+            .ignore()
+            .expire(&key, STALE_UPLOAD_AGE.as_secs() as i64)
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn load_parts(&self, upload_id: &str) -> Result<BTreeMap<u32, PartRecord>, String> {
+        let records: BTreeMap<u32, String> = self
+            .get_redis_connection()
+            .await?
+            .hgetall(Self::hash_key(upload_id))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        records
+            .into_iter()
+            .map(|(part_number, record)| {
+                serde_json::from_str(&record)
+                    .map(|record| (part_number, record))
+                    .map_err(|e| e.to_string())
+            })
+            .collect()
+    }
+
+    pub async fn remove_upload(&self, upload_id: &str) -> Result<(), String> {
+        self.get_redis_connection()
+            .await?
+            .del(Self::hash_key(upload_id))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_redis_connection(&self) -> Result<deadpool_redis::Connection, String> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| format!("Failed to get Redis connection: {}", e))
+    }
+}
@@ -0,0 +1,77 @@
+use actix_web::http::StatusCode;
+use awc::error::SendRequestError;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // exponential backoff with full jitter: a random delay up to
+    // base_delay * 2^(retry - 1), capped at max_delay
+    pub fn delay(&self, retry: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        let ceiling_ms = ceiling.as_millis().min(u32::MAX as u128) as u32;
+
+        Duration::from_millis(sodiumoxide::randombytes::randombytes_uniform(ceiling_ms + 1) as u64)
+    }
+}
+
+// the upstream may answer on a later attempt
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// the connection failed or was reset before a response
+pub fn is_retryable_error(error: &SendRequestError) -> bool {
+    matches!(
+        error,
+        SendRequestError::Connect(_) | SendRequestError::Send(_) | SendRequestError::Response(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_delay_grows_up_to_the_max_delay() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+
+        for _ in 0..100 {
+            assert!(policy.delay(1) <= Duration::from_millis(100));
+            assert!(policy.delay(3) <= Duration::from_millis(400));
+            assert!(policy.delay(10) <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn only_the_unavailability_statuses_are_retried() {
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn a_timeout_is_not_retried() {
+        let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+
+        assert!(is_retryable_error(&SendRequestError::Send(reset)));
+        assert!(!is_retryable_error(&SendRequestError::Timeout));
+    }
+}
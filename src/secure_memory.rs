@@ -0,0 +1,109 @@
+use libsodium_sys::{sodium_free, sodium_malloc, sodium_mprotect_readonly};
+use std::fmt;
+
+// A buffer allocated by libsodium: it is surrounded by guard pages,
+// locked in memory so that it is never swapped, and zeroed when freed.
+// Once filled, it is made read only.
+pub struct SecureBuffer {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// the buffer is read only once built
+unsafe impl Send for SecureBuffer {}
+unsafe impl Sync for SecureBuffer {}
+
+impl SecureBuffer {
+    pub fn new(content: &[u8]) -> SecureBuffer {
+        // sodium_malloc needs libsodium to be initialized,
+        // which is idempotent and thread safe
+        sodiumoxide::init().expect("unable to initialize libsodium");
+
+        // sodium_malloc does not accept a 0 size on every platform
+        let ptr = unsafe { sodium_malloc(content.len().max(1)) } as *mut u8;
+
+        if ptr.is_null() {
+            panic!("unable to allocate secure memory");
+        }
+
+        let protected = unsafe {
+            std::ptr::copy_nonoverlapping(content.as_ptr(), ptr, content.len());
+            sodium_mprotect_readonly(ptr as *mut _)
+        };
+
+        // a writable buffer would not be what it claims to be
+        if protected != 0 {
+            let error = std::io::Error::last_os_error();
+            unsafe { sodium_free(ptr as *mut _) };
+            panic!("unable to make the secure memory read only: {}", error);
+        }
+
+        SecureBuffer {
+            ptr,
+            len: content.len(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        // sodium_free zeroes the memory, even when read only
+        unsafe { sodium_free(self.ptr as *mut _) }
+    }
+}
+
+impl fmt::Debug for SecureBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecureBuffer(****)")
+    }
+}
+
+pub fn zeroize(bytes: &mut [u8]) {
+    sodiumoxide::utils::memzero(bytes);
+}
+
+// a core dump would contain the keys
+#[cfg(target_os = "linux")]
+pub fn disable_core_dumps() {
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        panic!(
+            "unable to disable core dumps: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+// without prctl, the size of the core dumps is limited to nothing
+#[cfg(not(target_os = "linux"))]
+pub fn disable_core_dumps() {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        panic!(
+            "unable to disable core dumps: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secure_buffer() {
+        let buffer = SecureBuffer::new(b"secret");
+        assert_eq!(b"secret", buffer.as_slice());
+        assert_eq!("SecureBuffer(****)", format!("{:?}", buffer));
+
+        let empty = SecureBuffer::new(b"");
+        assert!(empty.as_slice().is_empty());
+    }
+}
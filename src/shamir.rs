@@ -0,0 +1,268 @@
+use super::config::{CombineSecretConfig, SplitSecretConfig};
+use super::secure_memory::zeroize;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::str::FromStr;
+
+// Shamir secret sharing over GF(256), byte by byte:
+// each byte of the secret is the constant term of a random polynomial
+// of degree threshold - 1, and a share is the evaluation of
+// all those polynomials at the share index.
+
+const SHARE_PREFIX: &str = "ds_proxy-share-v1";
+const RANDOM_SECRET_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub index: u8,
+    pub threshold: u8,
+    pub value: Vec<u8>,
+}
+
+pub fn split(secret: &[u8], threshold: u8, nb_shares: u8) -> Vec<Share> {
+    assert!(
+        0 < threshold && threshold <= nb_shares,
+        "the threshold must be between 1 and the number of shares"
+    );
+
+    let mut shares: Vec<Share> = (1..=nb_shares)
+        .map(|index| Share {
+            index,
+            threshold,
+            value: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+
+    for byte in secret {
+        let mut coefficients = vec![*byte];
+        coefficients.extend(sodiumoxide::randombytes::randombytes(
+            threshold as usize - 1,
+        ));
+
+        for share in shares.iter_mut() {
+            share.value.push(evaluate(&coefficients, share.index));
+        }
+    }
+
+    shares
+}
+
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, String> {
+    let first = shares.first().ok_or("no share given")?;
+
+    if shares.len() < first.threshold as usize {
+        return Err(format!(
+            "{} shares are needed, only {} given",
+            first.threshold,
+            shares.len()
+        ));
+    }
+
+    if shares
+        .iter()
+        .any(|s| s.threshold != first.threshold || s.value.len() != first.value.len())
+    {
+        return Err("the shares do not come from the same split".to_string());
+    }
+
+    let indexes: HashSet<u8> = shares.iter().map(|s| s.index).collect();
+    if indexes.len() != shares.len() {
+        return Err("the same share is given twice".to_string());
+    }
+
+    let shares = &shares[..first.threshold as usize];
+
+    let secret = (0..first.value.len())
+        .map(|position| interpolate_at_zero(shares, position))
+        .collect();
+
+    Ok(secret)
+}
+
+pub fn split_secret(config: SplitSecretConfig) {
+    let mut secret = match config.secret {
+        Some(secret) => secret,
+        None => STANDARD
+            .encode(sodiumoxide::randombytes::randombytes(RANDOM_SECRET_SIZE))
+            .into_bytes(),
+    };
+
+    for share in split(&secret, config.threshold, config.nb_shares) {
+        let path = format!("{}.{}", config.output_prefix, share.index);
+
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut f| f.write_all(share.to_string().as_bytes()))
+            .unwrap_or_else(|why| panic!("couldn't write share {}: {}", path, why));
+
+        println!("{}", path);
+    }
+
+    zeroize(&mut secret);
+}
+
+pub fn combine_secret(mut config: CombineSecretConfig) {
+    // readable by its owner only, as the shares
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&config.output_file)
+        .and_then(|mut f| f.write_all(&config.secret))
+        .unwrap_or_else(|why| panic!("couldn't write {}: {}", config.output_file, why));
+
+    zeroize(&mut config.secret);
+}
+
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    // horner's method
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |acc, coefficient| gf_mul(acc, x) ^ coefficient)
+}
+
+fn interpolate_at_zero(shares: &[Share], position: usize) -> u8 {
+    shares.iter().fold(0, |acc, share| {
+        // lagrange basis polynomial evaluated at 0:
+        // product of x_j / (x_j - x_i), with - being ^ in GF(256)
+        let basis = shares
+            .iter()
+            .filter(|other| other.index != share.index)
+            .fold(1, |basis, other| {
+                gf_mul(basis, gf_div(other.index, other.index ^ share.index))
+            });
+
+        acc ^ gf_mul(share.value[position], basis)
+    })
+}
+
+// multiplication in GF(256) modulo x^8 + x^4 + x^3 + x + 1
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = a >> 7;
+        a <<= 1;
+        a ^= 0x1b & 0u8.wrapping_sub(carry);
+        b >>= 1;
+    }
+
+    product
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = gf_mul(inverse, b);
+    }
+
+    gf_mul(a, inverse)
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            SHARE_PREFIX,
+            self.index,
+            self.threshold,
+            STANDARD.encode(&self.value)
+        )
+    }
+}
+
+impl FromStr for Share {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.trim().split(':').collect();
+
+        match parts[..] {
+            [SHARE_PREFIX, index, threshold, value] => {
+                let index = index.parse::<u8>().map_err(|e| e.to_string())?;
+                if index == 0 {
+                    return Err("a share index cannot be 0".to_string());
+                }
+
+                Ok(Share {
+                    index,
+                    threshold: threshold.parse::<u8>().map_err(|e| e.to_string())?,
+                    value: STANDARD.decode(value).map_err(|e| e.to_string())?,
+                })
+            }
+            _ => Err("not a ds_proxy share".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn gf_arithmetic() {
+        // example from FIPS-197
+        assert_eq!(0xc1, gf_mul(0x57, 0x83));
+
+        for a in 1..=255u8 {
+            assert_eq!(1, gf_mul(a, gf_div(1, a)));
+        }
+    }
+
+    #[test]
+    fn any_threshold_shares_recover_the_secret() {
+        let secret = b"a master password\n";
+        let shares = split(secret, 3, 5);
+
+        assert_eq!(secret.to_vec(), combine(&shares).unwrap());
+        assert_eq!(
+            secret.to_vec(),
+            combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap()
+        );
+        assert_eq!(
+            secret.to_vec(),
+            combine(&[shares[1].clone(), shares[3].clone(), shares[4].clone()]).unwrap()
+        );
+    }
+
+    #[test]
+    fn not_enough_shares() {
+        let shares = split(b"secret", 3, 5);
+
+        assert!(combine(&shares[..2]).is_err());
+        assert!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn share_serialization() {
+        let share = split(b"secret", 2, 2).remove(1);
+        let serialized = share.to_string();
+
+        assert!(serialized.starts_with("ds_proxy-share-v1:2:2:"));
+        assert_eq!(Ok(share), format!("{}\n", serialized).parse::<Share>());
+        assert!("ds_proxy-share-v1:0:2:AAAA".parse::<Share>().is_err());
+        assert!("plop".parse::<Share>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn split_and_combine(secret: Vec<u8>, threshold in 1..6u8, extra in 0..4u8) {
+            let shares = split(&secret, threshold, threshold + extra);
+            let kept = &shares[extra as usize..];
+
+            prop_assert_eq!(secret, combine(kept).unwrap());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openssl::error::ErrorStack;
+use openssl::sha::sha256;
+use openssl::ssl::{
+    SniError, SslAcceptor, SslAcceptorBuilder, SslConnector, SslContext, SslFiletype, SslMethod,
+    SslVerifyMode, SslVersion,
+};
+use openssl::x509::{X509Name, X509Ref};
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+// the certificate of the listener, and the ca of the clients when they must present one
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    pub client_ca_file: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    // read again from the files on each reload
+    pub fn ssl_context(&self) -> Result<SslContext, String> {
+        self.acceptor_builder()
+            .map(|builder| builder.build().into_context())
+            .map_err(|why| format!("cannot load the tls files: {}", why))
+    }
+
+    fn acceptor_builder(&self) -> Result<SslAcceptorBuilder, ErrorStack> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+
+        builder.set_certificate_chain_file(&self.cert_file)?;
+        builder.set_private_key_file(&self.key_file, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+
+        if let Some(client_ca_file) = &self.client_ca_file {
+            builder.set_ca_file(client_ca_file)?;
+            builder.set_client_ca_list(X509Name::load_client_ca_file(client_ca_file)?);
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(builder)
+    }
+}
+
+// The listener is bound once, with this acceptor. Each handshake switches
+// to the current context, replaced on reload, so that the new certificate
+// is used without restarting the proxy.
+pub fn reloadable_acceptor(
+    tls: &TlsConfig,
+    current: Arc<RwLock<SslContext>>,
+) -> Result<SslAcceptorBuilder, String> {
+    let mut builder = tls
+        .acceptor_builder()
+        .map_err(|why| format!("cannot load the tls files: {}", why))?;
+
+    // called on each client hello, with or without a server name
+    builder.set_servername_callback(move |ssl, _alert| {
+        let context = current.read().unwrap();
+
+        ssl.set_ssl_context(&context)
+            .map_err(|_| SniError::ALERT_FATAL)
+    });
+
+    Ok(builder)
+}
+
+// how the proxy authenticates an upstream, and itself to it
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamTls {
+    pub ca_file: Option<PathBuf>,
+    pub client_cert_file: Option<PathBuf>,
+    pub client_key_file: Option<PathBuf>,
+    pub min_version: Option<String>,
+    // sha256 of the public key (spki) of a certificate of the chain
+    pub pinned_keys: Vec<[u8; 32]>,
+}
+
+impl UpstreamTls {
+    pub fn ssl_connector(&self, verify_certificate: bool) -> Result<SslConnector, String> {
+        self.connector(verify_certificate)
+            .map_err(|why| format!("cannot load the tls files: {}", why))
+    }
+
+    fn connector(&self, verify_certificate: bool) -> Result<SslConnector, ErrorStack> {
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        // as the default connector of awc
+        builder.set_alpn_protos(b"\x02h2\x08http/1.1")?;
+
+        if let Some(ca_file) = &self.ca_file {
+            builder.set_ca_file(ca_file)?;
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (&self.client_cert_file, &self.client_key_file) {
+            builder.set_certificate_chain_file(cert_file)?;
+            builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+            builder.check_private_key()?;
+        }
+
+        if let Some(min_version) = &self.min_version {
+            // checked by parse_tls_version
+            builder.set_min_proto_version(parse_tls_version(min_version).ok())?;
+        }
+
+        // the certificate is always checked: without a verification of the
+        // chain, refused by the configuration unless keys are pinned
+        if self.pinned_keys.is_empty() {
+            builder.set_verify(SslVerifyMode::PEER);
+        } else {
+            let pinned_keys = self.pinned_keys.clone();
+
+            // the chain is known when the leaf, at depth 0, is verified last
+            builder.set_verify_callback(SslVerifyMode::PEER, move |preverified, context| {
+                if context.error_depth() > 0 {
+                    return preverified || !verify_certificate;
+                }
+
+                let pinned = match context.chain() {
+                    Some(chain) if !chain.is_empty() => {
+                        chain.iter().any(|cert| is_pinned(cert, &pinned_keys))
+                    }
+                    _ => context
+                        .current_cert()
+                        .is_some_and(|cert| is_pinned(cert, &pinned_keys)),
+                };
+
+                if !pinned {
+                    log::error!("no certificate of the upstream matches a pinned key");
+                }
+
+                (preverified || !verify_certificate) && pinned
+            });
+        }
+
+        Ok(builder.build())
+    }
+}
+
+fn is_pinned(cert: &X509Ref, pinned_keys: &[[u8; 32]]) -> bool {
+    cert.public_key()
+        .and_then(|key| key.public_key_to_der())
+        .map(|spki| pinned_keys.contains(&sha256(&spki)))
+        .unwrap_or(false)
+}
+
+pub fn parse_tls_version(version: &str) -> Result<SslVersion, String> {
+    match version {
+        "1.2" => Ok(SslVersion::TLS1_2),
+        "1.3" => Ok(SslVersion::TLS1_3),
+        _ => Err(format!("{} is neither 1.2 nor 1.3", version)),
+    }
+}
+
+// the format of curl --pinnedpubkey: sha256//<base64 of the hash>
+pub fn parse_pinned_key(pin: &str) -> Result<[u8; 32], String> {
+    let encoded = pin
+        .strip_prefix("sha256//")
+        .ok_or_else(|| format!("{} does not start with sha256//", pin))?;
+
+    STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|hash| hash.try_into().ok())
+        .ok_or_else(|| format!("{} is not the base64 of a sha256", pin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_the_pinned_keys() {
+        let hash = [7; 32];
+        let pin = format!("sha256//{}", STANDARD.encode(hash));
+
+        assert_eq!(Ok(hash), parse_pinned_key(&pin));
+        assert!(parse_pinned_key(&STANDARD.encode(hash)).is_err());
+        assert!(parse_pinned_key("sha256//c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn only_recent_tls_versions_are_accepted() {
+        assert_eq!(Ok(SslVersion::TLS1_3), parse_tls_version("1.3"));
+        assert!(parse_tls_version("1.0").is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use url::Url;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LoadBalancing {
+    #[default]
+    RoundRobin,
+    LeastConnections,
+}
+
+impl std::str::FromStr for LoadBalancing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(LoadBalancing::RoundRobin),
+            "least-connections" => Ok(LoadBalancing::LeastConnections),
+            _ => Err(format!(
+                "{} is neither round-robin nor least-connections",
+                s
+            )),
+        }
+    }
+}
+
+// the endpoints serving the same content, shared by all the workers
+#[derive(Debug)]
+pub struct EndpointPool {
+    endpoints: Vec<Endpoint>,
+    load_balancing: LoadBalancing,
+    next: AtomicUsize,
+    retries: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    base_url: Url,
+    healthy: AtomicBool,
+    // requests in flight
+    active: AtomicUsize,
+    attempts: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointDescription {
+    pub url: String,
+    pub healthy: bool,
+    pub in_flight: usize,
+    pub attempts: u64,
+    pub failures: u64,
+}
+
+// counts a request in flight on an endpoint until dropped
+pub struct EndpointGuard<'a> {
+    pool: &'a EndpointPool,
+    pub index: usize,
+}
+
+impl Drop for EndpointGuard<'_> {
+    fn drop(&mut self) {
+        self.pool.endpoints[self.index]
+            .active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl EndpointPool {
+    pub fn new(base_urls: Vec<Url>, load_balancing: LoadBalancing) -> EndpointPool {
+        EndpointPool {
+            endpoints: base_urls
+                .into_iter()
+                .map(|base_url| Endpoint {
+                    base_url,
+                    healthy: AtomicBool::new(true),
+                    active: AtomicUsize::new(0),
+                    attempts: AtomicU64::new(0),
+                    failures: AtomicU64::new(0),
+                })
+                .collect(),
+            load_balancing,
+            next: AtomicUsize::new(0),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    pub fn load_balancing(&self) -> LoadBalancing {
+        self.load_balancing
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+
+    pub fn base_urls(&self) -> impl Iterator<Item = &Url> {
+        self.endpoints.iter().map(|e| &e.base_url)
+    }
+
+    // a healthy endpoint not tried yet, or an ejected one if all of them are,
+    // rather than failing without trying
+    pub fn select(&self, tried: &[usize]) -> Option<EndpointGuard<'_>> {
+        let candidates: Vec<usize> = (0..self.endpoints.len())
+            .filter(|i| !tried.contains(i))
+            .collect();
+
+        let healthy: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&i| self.endpoints[i].healthy.load(Ordering::Relaxed))
+            .collect();
+
+        let candidates = if healthy.is_empty() {
+            candidates
+        } else {
+            healthy
+        };
+
+        let index = match self.load_balancing {
+            LoadBalancing::RoundRobin => {
+                if candidates.is_empty() {
+                    return None;
+                }
+                let next = self.next.fetch_add(1, Ordering::Relaxed);
+                candidates[next % candidates.len()]
+            }
+            LoadBalancing::LeastConnections => *candidates
+                .iter()
+                .min_by_key(|&&i| self.endpoints[i].active.load(Ordering::Relaxed))?,
+        };
+
+        self.endpoints[index].active.fetch_add(1, Ordering::Relaxed);
+
+        Some(EndpointGuard { pool: self, index })
+    }
+
+    pub fn set_healthy(&self, index: usize, healthy: bool) {
+        let was_healthy = self.endpoints[index]
+            .healthy
+            .swap(healthy, Ordering::Relaxed);
+
+        if was_healthy != healthy {
+            log::warn!(
+                "upstream endpoint {} is {}",
+                self.endpoints[index].base_url,
+                if healthy { "back" } else { "ejected" }
+            );
+        }
+    }
+
+    pub fn record_attempt(&self, index: usize) {
+        self.endpoints[index]
+            .attempts
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self, index: usize) {
+        self.endpoints[index]
+            .failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub fn describe_endpoints(&self) -> Vec<EndpointDescription> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointDescription {
+                url: endpoint.base_url.to_string(),
+                healthy: endpoint.healthy.load(Ordering::Relaxed),
+                in_flight: endpoint.active.load(Ordering::Relaxed),
+                attempts: endpoint.attempts.load(Ordering::Relaxed),
+                failures: endpoint.failures.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    // the url built on the main base url, sent to the given endpoint
+    pub fn endpoint_url(&self, base_url: &Url, url: &str, index: usize) -> String {
+        match url.strip_prefix(base_url.as_str()) {
+            Some(relative) => format!("{}{}", self.endpoints[index].base_url, relative),
+            None => url.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(load_balancing: LoadBalancing) -> EndpointPool {
+        EndpointPool::new(
+            vec![
+                Url::parse("http://a/v1/").unwrap(),
+                Url::parse("http://b/v1/").unwrap(),
+                Url::parse("http://c/v1/").unwrap(),
+            ],
+            load_balancing,
+        )
+    }
+
+    #[test]
+    fn round_robin_skips_the_ejected_endpoints() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let select = |tried: &[usize]| pool.select(tried).map(|guard| guard.index);
+
+        assert_eq!(Some(0), select(&[]));
+        assert_eq!(Some(1), select(&[]));
+        assert_eq!(Some(2), select(&[]));
+
+        pool.set_healthy(1, false);
+        let selected: Vec<_> = (0..4).map(|_| select(&[]).unwrap()).collect();
+        assert!(!selected.contains(&1));
+
+        // the failover tries the other endpoints, ejected ones last
+        assert_eq!(Some(2), select(&[0]));
+        assert_eq!(Some(1), select(&[0, 2]));
+        assert_eq!(None, select(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn least_connections_picks_the_least_busy_endpoint() {
+        let pool = pool(LoadBalancing::LeastConnections);
+
+        let first = pool.select(&[]).unwrap();
+        let second = pool.select(&[]).unwrap();
+        assert_eq!((0, 1), (first.index, second.index));
+
+        drop(first);
+        assert_eq!(0, pool.select(&[]).unwrap().index);
+    }
+
+    #[test]
+    fn the_url_is_sent_to_the_endpoint() {
+        let pool = pool(LoadBalancing::RoundRobin);
+        let base_url = Url::parse("http://a/v1/").unwrap();
+
+        assert_eq!(
+            "http://b/v1/container/file?q=1",
+            pool.endpoint_url(&base_url, "http://a/v1/container/file?q=1", 1)
+        );
+    }
+}
@@ -1,7 +1,6 @@
+use crate::settings::DEFAULT_WRITE_ONCE_LOCK_DURATION;
 use deadpool_redis::{redis::cmd, redis::AsyncCommands, Pool};
 
-const LOCK_DURATION: u64 = 3600; // 1 hour
-
 use sha2::{Digest, Sha256};
 
 // Service that implements the "write once" functionality
@@ -12,11 +11,20 @@ use sha2::{Digest, Sha256};
 #[derive(Clone)]
 pub struct WriteOnceService {
     pool: Pool,
+    lock_duration: u64, // seconds
 }
 
 impl WriteOnceService {
     pub fn new(pool: Pool) -> Self {
-        WriteOnceService { pool }
+        WriteOnceService {
+            pool,
+            lock_duration: DEFAULT_WRITE_ONCE_LOCK_DURATION,
+        }
+    }
+
+    pub fn with_lock_duration(mut self, lock_duration: u64) -> Self {
+        self.lock_duration = lock_duration;
+        self
     }
 
     pub fn hash_key(uri: &str) -> String {
@@ -30,7 +38,7 @@ impl WriteOnceService {
             .arg(&key)
             .arg("true")
             .arg("EX")
-            .arg(LOCK_DURATION)
+            .arg(self.lock_duration)
             .arg("NX")
             .query_async(&mut conn)
             .await
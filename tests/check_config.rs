@@ -0,0 +1,102 @@
+use assert_cmd::cargo;
+use std::net::TcpListener;
+use std::process::Command;
+
+mod helpers;
+pub use helpers::*;
+
+fn check_config(password: &str, upstream_url: &str) -> std::process::Output {
+    check_config_with(password, upstream_url, &[])
+}
+
+fn check_config_with(password: &str, upstream_url: &str, args: &[&str]) -> std::process::Output {
+    let mut args = args.to_vec();
+    if !args.iter().any(|arg| arg.starts_with("--address=")) {
+        args.push("--address=127.0.0.1:0");
+    }
+
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("check-config")
+        .args(args)
+        .arg(format!("--upstream-url={}", upstream_url))
+        .arg(format!(
+            "--local-encryption-directory={}",
+            temp.path().display()
+        ))
+        .env("DS_KEYRING", DS_KEYRING)
+        .env("DS_PASSWORD", password)
+        .env("DS_SALT", SALT)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn a_valid_configuration_is_accepted() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream_url = format!("http://{}/", upstream.local_addr().unwrap());
+
+    let output = check_config(PASSWORD, &upstream_url);
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("the configuration is valid"));
+}
+
+#[test]
+fn every_problem_is_listed() {
+    // nothing listens on the port of a closed listener
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream_url = format!("http://{}/", upstream.local_addr().unwrap());
+    drop(upstream);
+
+    let output = check_config("wrong password", &upstream_url);
+    assert!(!output.status.success());
+
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("invalid keyring_file"));
+
+    let output = check_config(PASSWORD, &upstream_url);
+    assert!(!output.status.success());
+
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("invalid upstream_url: cannot connect"));
+
+    let output = check_config("wrong password", "not an url");
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("invalid keyring_file"));
+    assert!(errors.contains("invalid upstream_url"));
+}
+
+#[test]
+fn a_null_chunk_size_is_refused() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream_url = format!("http://{}/", upstream.local_addr().unwrap());
+
+    let output = check_config_with(PASSWORD, &upstream_url, &["--chunk-size=0"]);
+    assert!(!output.status.success());
+
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("invalid chunk_size"));
+}
+
+#[test]
+fn the_address_of_a_running_proxy_is_accepted() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream_url = format!("http://{}/", upstream.local_addr().unwrap());
+
+    // the port is held, as by the proxy being checked
+    let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("--address={}", proxy.local_addr().unwrap());
+
+    let output = check_config_with(PASSWORD, &upstream_url, &[&address]);
+    assert!(output.status.success());
+
+    let output = check_config_with(PASSWORD, &upstream_url, &["--address=192.0.2.1:8080"]);
+    assert!(!output.status.success());
+
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(errors.contains("invalid address"));
+}
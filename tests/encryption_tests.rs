@@ -2,7 +2,8 @@ extern crate ds_proxy;
 
 use ds_proxy::crypto::*;
 use ds_proxy::keyring::Keyring;
-use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Key, KEYBYTES};
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::secretstream::xchacha20poly1305::{Key, ABYTES, HEADERBYTES, KEYBYTES};
 use std::collections::HashMap;
 
 use actix_web::web::{BufMut, Bytes, BytesMut};
@@ -51,6 +52,145 @@ fn encoding_then_decoding_returns_source_data() {
     });
 }
 
+#[test]
+fn encoding_with_a_sealed_key_then_decoding_with_the_secret_key() {
+    let (public_key, secret_key) = box_::gen_keypair();
+    let empty_keyring = Keyring::new(HashMap::new());
+
+    proptest!(|(source_bytes: Vec<u8>, chunk_size in 1usize..10000)| {
+        let source : Result<Bytes, Error> = Ok(Bytes::from(source_bytes.clone()));
+        let source_stream  = futures::stream::once(Box::pin(async { source }));
+
+        let encoder = Encoder::new_sealed(public_key, chunk_size, Box::new(source_stream));
+
+        let mut boxy: Box<dyn futures::Stream<Item = Result<Bytes, _>> + Unpin> = Box::new(encoder);
+
+        let header_decoder = HeaderDecoder::new(&mut boxy);
+        let (cypher_type, buff) = block_on(header_decoder);
+
+        let decoder =
+        Decoder::new_from_cypher_and_buffer(empty_keyring.clone(), boxy, cypher_type, buff)
+            .with_secret_key(Some(secret_key.clone()));
+
+        let buf = block_on_stream(decoder)
+            .map(|r| r.unwrap())
+            .fold(BytesMut::with_capacity(64), |mut acc, x| { acc.put(x); acc });
+
+        assert_eq!(source_bytes, &buf[..]);
+    });
+}
+
+#[test]
+fn decoding_the_concatenated_segments_of_a_multipart_upload() {
+    let keyring: Keyring = build_keyring();
+
+    proptest!(|(parts: Vec<Vec<u8>>, chunk_size in 1usize..1000, piece_size in 1usize..100)| {
+        let (key_id, key) = keyring.get_last_key().unwrap();
+
+        // each part is encrypted on its own, behind a segment header
+        let mut uploaded = vec![];
+        for part in &parts {
+            let source : Result<Bytes, Error> = Ok(Bytes::from(part.clone()));
+            let source_stream  = futures::stream::once(Box::pin(async { source }));
+            let encoder = Encoder::new(key.clone(), key_id, chunk_size, Box::new(source_stream));
+
+            let encrypted: Vec<u8> = block_on_stream(encoder)
+                .flat_map(|r: Result<Bytes, Error>| r.unwrap())
+                .collect();
+
+            uploaded.extend(header::segment_header(encrypted.len()));
+            uploaded.extend(encrypted);
+        }
+
+        // the upstream sends the object in pieces unrelated to the segments
+        let pieces: Vec<Result<Bytes, Error>> = uploaded
+            .chunks(piece_size)
+            .map(|piece| Ok(Bytes::copy_from_slice(piece)))
+            .collect();
+
+        let mut boxy: Box<dyn futures::Stream<Item = Result<Bytes, _>> + Unpin> =
+            Box::new(futures::stream::iter(pieces));
+
+        let header_decoder = HeaderDecoder::new(&mut boxy);
+        let (cypher_type, buff) = block_on(header_decoder);
+
+        let decoder =
+        Decoder::new_from_cypher_and_buffer(keyring.clone(), boxy, cypher_type, buff);
+
+        let buf = block_on_stream(decoder)
+            .map(|r| r.unwrap())
+            .fold(BytesMut::with_capacity(64), |mut acc, x| { acc.put(x); acc });
+
+        assert_eq!(parts.concat(), &buf[..]);
+    });
+}
+
+#[test]
+fn an_invalid_segment_ends_the_stream_with_an_error() {
+    let keyring: Keyring = build_keyring();
+    let (key_id, key) = keyring.get_last_key().unwrap();
+
+    let source: Result<Bytes, Error> = Ok(Bytes::from_static(b"a part"));
+    let source_stream = futures::stream::once(Box::pin(async { source }));
+    let encoder = Encoder::new(key, key_id, 512, Box::new(source_stream));
+    let encrypted: Vec<u8> = block_on_stream(encoder)
+        .flat_map(|r: Result<Bytes, Error>| r.unwrap())
+        .collect();
+
+    let segment = [header::segment_header(encrypted.len()), encrypted].concat();
+
+    let nested = [
+        header::segment_header(segment.len() + header::SEGMENT_HEADER_SIZE),
+        header::segment_header(segment.len()),
+        segment.clone(),
+    ]
+    .concat();
+
+    for uploaded in [[segment.clone(), vec![0; 64]].concat(), nested] {
+        let source: Result<Bytes, Error> = Ok(Bytes::from(uploaded));
+        let mut boxy: Box<dyn futures::Stream<Item = Result<Bytes, _>> + Unpin> =
+            Box::new(futures::stream::once(Box::pin(async { source })));
+
+        let header_decoder = HeaderDecoder::new(&mut boxy);
+        let (cypher_type, buff) = block_on(header_decoder);
+
+        let decoder = Decoder::new_from_cypher_and_buffer(keyring.clone(), boxy, cypher_type, buff);
+        let items: Vec<Result<Bytes, Error>> = block_on_stream(decoder).collect();
+
+        assert!(items.last().unwrap().is_err());
+        assert_eq!(1, items.iter().filter(|item| item.is_err()).count());
+    }
+}
+
+#[test]
+fn the_last_chunk_is_held_until_the_content_ends() {
+    let keyring: Keyring = build_keyring();
+    let (key_id, key) = keyring.get_last_key().unwrap();
+    let chunk_size = 16;
+
+    // a content of two whole chunks, ending with an error
+    let source = futures::stream::iter(vec![
+        Ok(Bytes::from(vec![0; chunk_size])),
+        Ok(Bytes::from(vec![0; chunk_size])),
+        Err(actix_web::error::ErrorBadRequest("bad digest")),
+    ]);
+
+    let encoder = Encoder::new(key, key_id, chunk_size, Box::new(source));
+    let items: Vec<Result<Bytes, Error>> = block_on_stream(encoder).collect();
+
+    let sent: usize = items
+        .iter()
+        .take_while(|item| item.is_ok())
+        .map(|item| item.as_ref().unwrap().len())
+        .sum();
+
+    assert!(items.iter().any(|item| item.is_err()));
+    assert_eq!(
+        header::HEADER_V2_SIZE + HEADERBYTES + ABYTES + chunk_size,
+        sent
+    );
+}
+
 #[test]
 fn decrypting_plaintext_returns_plaintext() {
     let keyring: Keyring = build_keyring();
@@ -107,3 +107,74 @@ fn the_app_crashes_with_an_invalid_password() {
 
     decrypt_cmd.assert().failure();
 }
+
+#[test]
+fn recover_a_file_without_the_keyring() {
+    let temp = TempDir::new().unwrap();
+
+    let recovery_secret_key = temp.child("recovery.key");
+    let encrypted = temp.child("computer.svg.enc");
+    let decrypted = temp.child("computer.dec.svg");
+    let recovered = temp.child("computer.rec.svg");
+
+    let generate_output = Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("generate-recovery-key")
+        .arg(recovery_secret_key.path())
+        .output()
+        .unwrap();
+    assert!(generate_output.status.success());
+    let recovery_public_key = String::from_utf8(generate_output.stdout).unwrap();
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("encrypt")
+        .arg(COMPUTER_SVG_PATH)
+        .arg(encrypted.path())
+        .arg(format!(
+            "--recovery-public-key={}",
+            recovery_public_key.trim()
+        ))
+        .env("DS_KEYRING", DS_KEYRING)
+        .env("DS_PASSWORD", PASSWORD)
+        .env("DS_SALT", SALT)
+        .env("DS_CHUNK_SIZE", CHUNK_SIZE.to_string())
+        .assert()
+        .success();
+
+    // the keyring still decrypts the file
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("decrypt")
+        .arg(encrypted.path())
+        .arg(decrypted.path())
+        .env("DS_KEYRING", DS_KEYRING)
+        .env("DS_PASSWORD", PASSWORD)
+        .env("DS_SALT", SALT)
+        .assert()
+        .success();
+
+    assert_eq!(COMPUTER_SVG_BYTES, read(decrypted.path()).unwrap());
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("recover")
+        .arg(encrypted.path())
+        .arg(recovered.path())
+        .arg(format!(
+            "--recovery-secret-key-file={}",
+            recovery_secret_key.path().display()
+        ))
+        .assert()
+        .success();
+
+    assert_eq!(COMPUTER_SVG_BYTES, read(recovered.path()).unwrap());
+
+    // a file encrypted without recovery key cannot be recovered
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("recover")
+        .arg(ENCRYPTED_COMPUTER_SVG_PATH)
+        .arg(temp.child("witness.svg").path())
+        .arg(format!(
+            "--recovery-secret-key-file={}",
+            recovery_secret_key.path().display()
+        ))
+        .assert()
+        .failure();
+}
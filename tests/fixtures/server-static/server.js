@@ -18,17 +18,38 @@ app.put('*', function(req, res) {
   const filePath = path.join(__dirname, 'uploads', req.path);
   const fileDirectory = path.dirname(filePath);
 
+  // a copy of an object on itself only replaces its metadata
+  if (req.headers['x-amz-copy-source'] && req.headers['x-amz-metadata-directive'] === 'REPLACE') {
+    fs.writeFileSync(filePath + '.metadata', JSON.stringify(metadata, null, 2), 'utf8');
+    res.writeHead(200, {"content-type":"application/xml"});
+    res.end('<CopyObjectResult></CopyObjectResult>');
+    return;
+  }
+
   fs.mkdirSync(fileDirectory, { recursive: true })
 
-  writeStream = fs.createWriteStream(filePath);
+  // like s3, an upload interrupted before its end is not stored
+  const partPath = filePath + '.part';
+  const writeStream = fs.createWriteStream(partPath);
   req.pipe(writeStream);
 
-  if (Object.entries(metadata).length > 0) {
-    fs.writeFileSync(filePath + '.metadata', JSON.stringify(metadata, null, 2), 'utf8');
-  }
+  req.on('aborted', function () {
+    writeStream.destroy();
+    fs.rmSync(partPath, { force: true });
+  });
 
   // After all the data is saved, respond Ok
-  req.on('end', function () {
+  writeStream.on('finish', function () {
+    if (req.aborted) {
+      return;
+    }
+
+    fs.renameSync(partPath, filePath);
+
+    if (Object.entries(metadata).length > 0) {
+      fs.writeFileSync(filePath + '.metadata', JSON.stringify(metadata, null, 2), 'utf8');
+    }
+
     res.writeHead(200, {"content-type":"text/html"});
     res.end('Ok!');
   });
@@ -20,6 +20,24 @@ pub fn curl_get_status(url: &str) -> String {
     std::str::from_utf8(&stdout).unwrap().to_string()
 }
 
+pub fn curl_get_status_with_header(url: &str, header: &str) -> String {
+    let stdout = Command::new("curl")
+        .arg("-XGET")
+        .arg(url)
+        .arg("-H")
+        .arg(header)
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("-s")
+        .arg("-w")
+        .arg("%{http_code}")
+        .output()
+        .expect("failed to perform download")
+        .stdout;
+
+    std::str::from_utf8(&stdout).unwrap().to_string()
+}
+
 pub fn curl_put(file_path: &str, url: &str) -> Output {
     let cmd = Command::new("curl")
         .arg("-XPUT")
@@ -41,6 +59,29 @@ pub fn curl_put(file_path: &str, url: &str) -> Output {
     cmd
 }
 
+pub fn curl_put_status_with_header(file_path: &str, url: &str, header: &str) -> String {
+    let stdout = Command::new("curl")
+        .arg("-XPUT")
+        .arg(url)
+        .arg("-H")
+        .arg(header)
+        .arg("--data-binary")
+        .arg(format!("@{}", file_path))
+        .arg("-o")
+        .arg("/dev/null")
+        .arg("-s")
+        .arg("-w")
+        .arg("%{http_code}")
+        .output()
+        .expect("failed to perform upload")
+        .stdout;
+
+    // add sleep to let node remove the interrupted upload
+    thread::sleep(time::Duration::from_millis(100));
+
+    std::str::from_utf8(&stdout).unwrap().to_string()
+}
+
 pub fn curl_get_content_length_header(url: &str) -> usize {
     let response = curl_get_headers(url);
 
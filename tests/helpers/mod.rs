@@ -46,6 +46,15 @@ impl ProxyAndNode {
         ProxyAndNode::start_with_options(None, PrintServerLogs::No, Some(keyring_path), false)
     }
 
+    // the proxy is given other arguments
+    pub fn start_with_args(args: &[&str]) -> ProxyAndNode {
+        let proxy = launch_proxy_with_args(PrintServerLogs::No, None, false, args);
+        let node = launch_node_with_latency(None, PrintServerLogs::No);
+        let redis = launch_redis(PrintServerLogs::No);
+        thread::sleep(time::Duration::from_secs(4));
+        ProxyAndNode { proxy, node, redis }
+    }
+
     pub fn start_with_options(
         latency: Option<Duration>,
         log: PrintServerLogs,
@@ -82,6 +91,15 @@ pub fn launch_proxy(
     log: PrintServerLogs,
     keyring_path: Option<&str>,
     enable_aws_signature_check: bool,
+) -> ChildGuard {
+    launch_proxy_with_args(log, keyring_path, enable_aws_signature_check, &[])
+}
+
+pub fn launch_proxy_with_args(
+    log: PrintServerLogs,
+    keyring_path: Option<&str>,
+    enable_aws_signature_check: bool,
+    args: &[&str],
 ) -> ChildGuard {
     let keyring = if let Some(file) = keyring_path {
         file
@@ -100,7 +118,8 @@ pub fn launch_proxy(
         .env("DS_KEYRING", keyring)
         .env("DS_PASSWORD", PASSWORD)
         .env("DS_SALT", SALT)
-        .env("DS_CHUNK_SIZE", CHUNK_SIZE.to_string());
+        .env("DS_CHUNK_SIZE", CHUNK_SIZE.to_string())
+        .args(args);
 
     if !enable_aws_signature_check {
         command.arg("--bypass-aws-signature-check");
@@ -193,7 +212,7 @@ pub fn decrypt_bytes(input: Bytes) -> BytesMut {
     let header_decoder = HeaderDecoder::new(&mut boxy);
     let (cypher_type, buff) = block_on(header_decoder);
 
-    let keyring = load_keyring(DS_KEYRING, PASSWORD.to_string(), SALT.to_string());
+    let keyring = load_keyring(DS_KEYRING, PASSWORD.to_string(), SALT.to_string(), None);
 
     let decoder = Decoder::new_from_cypher_and_buffer(keyring, boxy, cypher_type, buff);
 
@@ -1,7 +1,11 @@
 use std::convert::TryInto;
 
+use assert_cmd::cargo;
 use assert_fs::prelude::*;
 use ds_proxy::crypto::header;
+use ds_proxy::keyring::fingerprint;
+use ds_proxy::keyring_utils::load_keyring;
+use std::process::Command;
 
 mod helpers;
 pub use helpers::*;
@@ -55,3 +59,38 @@ fn key_id(uploaded_path: &str) -> u64 {
             .unwrap(),
     )
 }
+
+#[test]
+fn list_keys() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let keyring_file = temp.child("keyring");
+    let keyring_path = keyring_file.path().to_str().unwrap();
+
+    add_a_key(keyring_path);
+    add_a_key(keyring_path);
+
+    let output = Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("list-keys")
+        .env("DS_KEYRING", keyring_path)
+        .env("DS_PASSWORD", PASSWORD)
+        .env("DS_SALT", SALT)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+
+    let keyring = load_keyring(keyring_path, PASSWORD.to_string(), SALT.to_string(), None);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<Vec<&str>> = stdout.lines().map(|l| l.split('\t').collect()).collect();
+
+    assert_eq!(2, lines.len());
+    assert_eq!(vec!["0", "decrypt-only"], lines[0][..2]);
+    assert_eq!(vec!["1", "active"], lines[1][..2]);
+    assert_eq!(
+        fingerprint(&keyring.get_key_by_id(&1).unwrap()),
+        lines[1][3]
+    );
+    assert!(!stdout.contains("unknown"));
+
+    temp.close().unwrap();
+}
@@ -0,0 +1,45 @@
+extern crate ds_proxy;
+
+use ds_proxy::multipart_store::{MultipartStore, PartRecord};
+use ds_proxy::redis_config::RedisConfig;
+use ds_proxy::redis_utils::configure_redis_pool;
+use std::thread;
+use url::Url;
+mod helpers;
+pub use helpers::*;
+
+#[actix_web::test]
+#[serial(servers)]
+async fn store_the_parts() {
+    let _redis_process = launch_redis(PrintServerLogs::No);
+    thread::sleep(std::time::Duration::from_secs(4));
+
+    let config = RedisConfig {
+        url: Url::parse("redis://127.0.0.1:5555").unwrap(),
+        ..RedisConfig::default()
+    };
+    let store = MultipartStore::new(configure_redis_pool(config).await);
+
+    store.remove_upload("upload/id").await.unwrap();
+    store.remove_upload("another").await.unwrap();
+
+    let record = PartRecord {
+        md5: "md5".to_string(),
+        upstream_etag: "\"etag\"".to_string(),
+        size: 12,
+    };
+
+    assert!(store.load_parts("upload/id").await.unwrap().is_empty());
+
+    store.save_part("upload/id", 2, &record).await.unwrap();
+    store.save_part("upload/id", 1, &record).await.unwrap();
+    store.save_part("another", 1, &record).await.unwrap();
+
+    let parts = store.load_parts("upload/id").await.unwrap();
+    assert_eq!(vec![1, 2], parts.keys().copied().collect::<Vec<_>>());
+    assert_eq!(record, parts[&2]);
+
+    store.remove_upload("upload/id").await.unwrap();
+    assert!(store.load_parts("upload/id").await.unwrap().is_empty());
+    assert_eq!(1, store.load_parts("another").await.unwrap().len());
+}
@@ -24,6 +24,13 @@ fn ping() {
 
     assert_eq!(curl_get_status("localhost:4444/ping"), "200");
 
+    // a bare status, the upstreams are only described on the unix socket
+    assert!(curl_get("localhost:4444/ping").stdout.is_empty());
+    let upstreams = curl_socket_get("localhost/admin/upstreams").stdout;
+    assert!(String::from_utf8(upstreams)
+        .unwrap()
+        .contains("\"circuit\":\"closed\""));
+
     File::create(maintenance_file_path)
         .unwrap_or_else(|_| panic!("Unable to create {} !", maintenance_file_path));
 
@@ -0,0 +1,61 @@
+use assert_cmd::cargo;
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use std::process::Command;
+
+#[test]
+fn print_config_merges_the_file_the_env_and_the_cli() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config_file = temp.child("proxy.toml");
+    config_file
+        .write_str(
+            r#"
+            upstream_url = "https://upstream.com"
+            chunk_size = 10
+            response_timeout = 10
+            upload_timeout = 10
+            salt = "a secret salt"
+            "#,
+        )
+        .unwrap();
+
+    let output = Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("print-config")
+        .arg(format!("--config={}", config_file.path().display()))
+        .arg("--upload-timeout=30")
+        .env("DS_CHUNK_SIZE", "20")
+        .env("DS_UPLOAD_TIMEOUT", "20")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let printed = String::from_utf8(output.stdout).unwrap();
+
+    // the cli overrides the env, which overrides the file
+    assert!(printed.contains("upstream_url = \"https://upstream.com\""));
+    assert!(printed.contains("response_timeout = 10"));
+    assert!(printed.contains("chunk_size = 20"));
+    assert!(printed.contains("upload_timeout = 30"));
+    // the defaults are printed
+    assert!(printed.contains("max_in_memory_file_size = 10485760"));
+    // the secrets are not
+    assert!(printed.contains("salt = \"****\""));
+    assert!(!printed.contains("a secret salt"));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn an_unknown_setting_is_refused() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config_file = temp.child("proxy.toml");
+    config_file.write_str("uplaod_timeout = 10").unwrap();
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("print-config")
+        .arg(format!("--config={}", config_file.path().display()))
+        .assert()
+        .failure();
+
+    temp.close().unwrap();
+}
@@ -0,0 +1,96 @@
+use assert_cmd::cargo;
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use std::process::Command;
+
+mod helpers;
+pub use helpers::*;
+
+#[test]
+fn split_and_combine_the_master_password() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let password_file = temp.child("password");
+    password_file.write_str(PASSWORD).unwrap();
+    let share_prefix = temp.child("share");
+    let share_prefix = share_prefix.path().to_str().unwrap();
+    let share = |i: u8| format!("{}.{}", share_prefix, i);
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("split-secret")
+        .arg(share_prefix)
+        .arg("--threshold=2")
+        .arg("--shares=3")
+        .arg(format!(
+            "--password-file={}",
+            password_file.path().display()
+        ))
+        .assert()
+        .success();
+
+    let combined = temp.child("combined");
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("combine-secret")
+        .arg(combined.path())
+        .arg(format!("--share-file={}", share(3)))
+        .arg(format!("--share-file={}", share(1)))
+        .assert()
+        .success();
+
+    combined.assert(PASSWORD);
+
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(combined.path())
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(0o600, mode & 0o777);
+
+    // the shares can replace the password file
+    let keyring_file = temp.child("keyring");
+    let keyring_path = keyring_file.path().to_str().unwrap();
+    add_a_key(keyring_path);
+
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("list-keys")
+        .arg(format!("--share-file={}", share(2)))
+        .arg(format!("--share-file={}", share(3)))
+        .env("DS_KEYRING", keyring_path)
+        .env("DS_SALT", SALT)
+        .assert()
+        .success();
+
+    // a single share is not enough
+    Command::new(cargo::cargo_bin!("ds_proxy"))
+        .arg("list-keys")
+        .arg(format!("--share-file={}", share(2)))
+        .env("DS_KEYRING", keyring_path)
+        .env("DS_SALT", SALT)
+        .assert()
+        .failure();
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn an_impossible_threshold_is_refused() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let share_prefix = temp.child("share");
+
+    for (threshold, shares) in [("0", "3"), ("4", "3")] {
+        let output = Command::new(cargo::cargo_bin!("ds_proxy"))
+            .arg("split-secret")
+            .arg(share_prefix.path())
+            .arg(format!("--threshold={}", threshold))
+            .arg(format!("--shares={}", shares))
+            .arg("--random-secret")
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success());
+        let errors = String::from_utf8(output.stderr).unwrap();
+        assert!(errors.contains("invalid threshold"), "{}", errors);
+    }
+
+    assert!(!temp.child("share.1").path().exists());
+}
@@ -0,0 +1,69 @@
+use assert_cmd::cargo;
+use assert_cmd::prelude::*;
+use assert_fs::prelude::*;
+use std::process::Command;
+
+mod helpers;
+pub use helpers::*;
+
+#[test]
+fn the_cli_uses_the_keyring_of_the_tenant() {
+    let temp = assert_fs::TempDir::new().unwrap();
+
+    let password_file = temp.child("tenant_password");
+    password_file.write_str("tenant password").unwrap();
+    let keyring_file = temp.child("tenant_keyring.toml");
+
+    let tenants_file = temp.child("tenants.toml");
+    tenants_file
+        .write_str(&format!(
+            "[[tenants]]\nprefix = \"bucket-a/\"\nkeyring_file = \"{}\"\npassword_file = \"{}\"\n",
+            keyring_file.path().display(),
+            password_file.path().display()
+        ))
+        .unwrap();
+
+    let ds_proxy = |command: &str| {
+        let mut cmd = Command::new(cargo::cargo_bin!("ds_proxy"));
+        cmd.arg(command)
+            .env("DS_KEYRING", DS_KEYRING)
+            .env("DS_PASSWORD", PASSWORD)
+            .env("DS_SALT", SALT)
+            .env("DS_TENANTS_FILE", tenants_file.path());
+        cmd
+    };
+
+    ds_proxy("add-key")
+        .arg("--upstream-path=bucket-a/")
+        .assert()
+        .success();
+
+    let encrypted = temp.child("computer.svg.enc");
+    let decrypted = temp.child("computer.svg");
+
+    ds_proxy("encrypt")
+        .arg(COMPUTER_SVG_PATH)
+        .arg(encrypted.path())
+        .arg("--upstream-path=bucket-a/computer.svg")
+        .assert()
+        .success();
+
+    ds_proxy("decrypt")
+        .arg(encrypted.path())
+        .arg(decrypted.path())
+        .arg("--upstream-path=bucket-a/computer.svg")
+        .assert()
+        .success();
+
+    decrypted.assert(&COMPUTER_SVG_BYTES[..]);
+
+    // the key 0 of the default keyring is not the key 0 of the tenant
+    ds_proxy("decrypt")
+        .arg(encrypted.path())
+        .arg(decrypted.path())
+        .arg("--upstream-path=bucket-b/computer.svg")
+        .assert()
+        .failure();
+
+    temp.close().unwrap();
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::to_string_in_format_args, clippy::bool_assert_comparison)]
+
 use assert_fs::prelude::*;
 use ds_proxy::crypto::header::*;
 
@@ -34,6 +36,10 @@ fn upload_and_download() {
         node_received_header("x-amz-meta-original-content-length"),
         Some(format!("\"{}\"", COMPUTER_SVG_BYTES.len().to_string()))
     );
+    assert_eq!(
+        node_received_header("x-amz-meta-original-etag"),
+        Some(COMPUTER_SVG_MD5_ETAG.to_string())
+    );
     assert!(node_received_header("x-amz-date").is_some());
     assert!(node_received_header("authorization").is_some());
 
@@ -60,9 +66,25 @@ fn upload_and_download() {
         format!("content-length: {}", COMPUTER_SVG_BYTES.len())
     );
 
+    let etag = String::from_utf8_lossy(&curl_head.stdout)
+        .lines()
+        .find(|line| line.starts_with("etag"))
+        .map(|line| line.to_string());
+    assert_eq!(etag, Some(format!("etag: {}", COMPUTER_SVG_MD5_ETAG)));
+
     let curl_download = curl_get("localhost:4444/upstream/victory");
     assert_eq!(curl_download.stdout, COMPUTER_SVG_BYTES);
 
+    let if_none_match = format!("If-None-Match: {}", COMPUTER_SVG_MD5_ETAG);
+    assert_eq!(
+        "304",
+        curl_get_status_with_header("localhost:4444/upstream/victory", &if_none_match)
+    );
+    assert_eq!(
+        "412",
+        curl_get_status_with_header("localhost:4444/upstream/victory", "If-Match: \"other\"")
+    );
+
     let curl_range_download = curl_range_get("localhost:4444/upstream/victory", 0, 10);
     assert_eq!(curl_range_download.stdout, &COMPUTER_SVG_BYTES[0..11]);
 
@@ -88,3 +110,34 @@ fn check_aws_signature() {
         "Invalid AWS signature".to_string()
     );
 }
+
+#[test]
+#[serial(servers)]
+fn a_bad_digest_is_not_stored() {
+    // the upload is spooled, then streamed
+    for args in [&[][..], &["--stream-uploads"][..]] {
+        let _proxy_node_and_redis = ProxyAndNode::start_with_args(args);
+        put_a_bad_digest();
+    }
+}
+
+fn put_a_bad_digest() {
+    let uploaded_path = "tests/fixtures/server-static/uploads/jail/cell/bad_digest";
+    ensure_is_absent(uploaded_path);
+
+    // a content of whole chunks: the last one is held until the digest is checked
+    let temp = assert_fs::TempDir::new().unwrap();
+    let content = temp.child("content");
+    content.write_binary(&[0; 2 * CHUNK_SIZE]).unwrap();
+
+    let status = curl_put_status_with_header(
+        content.path().to_str().unwrap(),
+        "localhost:4444/upstream/bad_digest",
+        "x-amz-checksum-crc32: AAAAAA==",
+    );
+
+    assert_eq!("400", status);
+    assert!(!std::path::Path::new(uploaded_path).exists());
+
+    temp.close().unwrap();
+}